@@ -0,0 +1,100 @@
+extern crate multiplayer;
+use multiplayer::comms::admin::{AdminClient, AdminCommand, AdminResponse};
+use std::env;
+use std::process;
+
+const USAGE: &str = "Usage: admin <command> [args]
+
+Commands:
+  clients              List connected clients
+  games                List games
+  kick <client>        Disconnect a client
+  ban <client>         Disconnect a client and refuse them until unbanned
+  ban-ip <ip>          Disconnect an address and refuse it until unbanned
+  unban <client>       Let a banned client connect again
+  unban-ip <ip>        Let a banned address connect again
+  pause <game>         Pause a game
+  resume <game>        Resume a paused game
+  broadcast <text...>  Send a system message to every client
+
+The server is reached at MULTIPLAYER_ADMIN_ADDR (default 127.0.0.1:7879), logging in with
+MULTIPLAYER_ADMIN_TOKEN.";
+
+/// Parses the command line into an AdminCommand.
+fn parse(args: &[String]) -> Result<AdminCommand, String> {
+    let arg = |i: usize| {
+        args.get(i)
+            .cloned()
+            .ok_or_else(|| format!("{} needs an argument", args[0]))
+    };
+    let command = match args.first().map(|s| s.as_str()) {
+        Some("clients") => AdminCommand::ListClients,
+        Some("games") => AdminCommand::ListGames,
+        Some("kick") => AdminCommand::Kick(arg(1)?),
+        Some("ban") => AdminCommand::Ban(arg(1)?),
+        Some("ban-ip") => AdminCommand::BanIp(arg(1)?.parse().map_err(|e| format!("{}", e))?),
+        Some("unban") => AdminCommand::Unban(arg(1)?),
+        Some("unban-ip") => AdminCommand::UnbanIp(arg(1)?.parse().map_err(|e| format!("{}", e))?),
+        Some("pause") => AdminCommand::Pause(arg(1)?.parse().map_err(|e| format!("{}", e))?),
+        Some("resume") => AdminCommand::Resume(arg(1)?.parse().map_err(|e| format!("{}", e))?),
+        Some("broadcast") if args.len() > 1 => AdminCommand::Broadcast(args[1..].join(" ")),
+        Some("broadcast") => return Err(String::from("broadcast needs some text")),
+        _ => return Err(String::from(USAGE)),
+    };
+    Ok(command)
+}
+
+fn print_response(response: AdminResponse) -> bool {
+    match response {
+        AdminResponse::Done(text) => println!("{}", text),
+        AdminResponse::Clients(clients) => {
+            println!("{} client(s):", clients.len());
+            for c in clients {
+                let game = c.game_id.map_or(String::from("-"), |id| id.to_string());
+                let addr = c.addr.unwrap_or_else(|| String::from("-"));
+                println!(
+                    "  {} [{:?}] game: {} addr: {}",
+                    c.client_id, c.state, game, addr
+                );
+            }
+        }
+        AdminResponse::Games(games) => {
+            println!("{} game(s):", games.len());
+            for g in games {
+                println!(
+                    "  Game {} [{:?}] players: {} spectators: {}",
+                    g.game_id, g.state, g.players, g.spectators
+                );
+            }
+        }
+        AdminResponse::Error(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    }
+    true
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = parse(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+
+    let addr =
+        env::var("MULTIPLAYER_ADMIN_ADDR").unwrap_or_else(|_| String::from("127.0.0.1:7879"));
+    let token = env::var("MULTIPLAYER_ADMIN_TOKEN").unwrap_or_default();
+    let result = AdminClient::connect(&addr, &token).and_then(|mut admin| admin.request(command));
+    match result {
+        Ok(response) => {
+            if !print_response(response) {
+                process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to reach {}: {}", addr, e);
+            process::exit(1);
+        }
+    }
+}
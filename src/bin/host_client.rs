@@ -1,7 +1,72 @@
 extern crate multiplayer;
-use multiplayer::host_side::host_server::HostServer;
+use clap::Parser;
+use multiplayer::host_side::repl::{Command, Repl};
+use multiplayer::host_side::tui;
+use multiplayer::logging;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process;
+
+/// Interactive client for the multiplayer server. Type /help once it starts for the commands.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Server to connect to on start. Interactive sessions connect to 127.0.0.1:7878 by default.
+    #[arg(short, long)]
+    addr: Option<String>,
+    /// ID to log in with as soon as the server asks.
+    #[arg(short, long)]
+    id: Option<String>,
+    /// Runs the commands in a file instead of reading them from the terminal, then exits.
+    #[arg(short, long)]
+    script: Option<PathBuf>,
+    /// Shows the game in the terminal, moving with the arrow keys.
+    #[arg(short, long, conflicts_with = "script")]
+    tui: bool,
+}
 
 fn main() {
-    let host_server = HostServer::new("127.0.0.1:7878", 10);
-    host_server.start();
+    // Only warnings by default, so logs don't drown out the console.
+    logging::init("warn");
+    let args = Args::parse();
+    let mut repl = Repl::new(10);
+
+    let mut startup = Vec::new();
+    if let Some(id) = args.id {
+        startup.push(Command::Login(id));
+    }
+    match (args.addr, &args.script) {
+        (Some(addr), _) => startup.push(Command::Connect(addr)),
+        (None, None) => startup.push(Command::Connect(String::from("127.0.0.1:7878"))),
+        (None, Some(_)) => (),
+    }
+    for command in startup {
+        if let Err(e) = repl.execute(command) {
+            eprintln!("{}", e);
+        }
+    }
+
+    if args.tui {
+        if let Err(e) = tui::run(&mut repl) {
+            eprintln!("Terminal error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let result = match &args.script {
+        Some(path) => match File::open(path) {
+            Ok(file) => repl.run_script(BufReader::new(file)),
+            Err(e) => {
+                eprintln!("Failed to open {}: {}", path.display(), e);
+                process::exit(2);
+            }
+        },
+        None => repl.run_interactive(),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,267 @@
+extern crate multiplayer;
+use clap::Parser;
+use multiplayer::comms::message::PlayerInput;
+use multiplayer::host_side::bot::{Bot, BotConfig, InputPlan, JoinPlan, Latencies};
+use multiplayer::host_side::console;
+use multiplayer::logging;
+use multiplayer::metrics::scrape;
+use multiplayer::threading::threadpool::ThreadPool;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often every bot is given the chance to send.
+const STEP: Duration = Duration::from_millis(10);
+/// Workers left over for handling messages once every bot has a listener.
+const HANDLER_WORKERS: usize = 16;
+
+/// Connects many bots to a server, plays for a while, then reports how the server coped.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Server to connect to.
+    #[arg(short, long, default_value = "127.0.0.1:7878")]
+    addr: String,
+    /// Bots to connect. The server needs max_clients and threads to match.
+    #[arg(short, long, default_value_t = 10)]
+    bots: usize,
+    /// Seconds to play for once every bot has connected.
+    #[arg(short, long, default_value_t = 30.0)]
+    duration: f64,
+    /// Seconds over which the bots connect, so the server isn't hit all at once.
+    #[arg(long, default_value_t = 1.0)]
+    ramp: f64,
+    /// How bots get into games: matchmake, matchmake:<mode>, create, lobby or a game id.
+    #[arg(short, long, default_value = "matchmake")]
+    join: String,
+    /// File of inputs to send in turn, one "x y" pair a line. Inputs are random without one.
+    #[arg(short, long)]
+    script: Option<PathBuf>,
+    /// Sends no inputs, only pings.
+    #[arg(long, conflicts_with = "script")]
+    idle: bool,
+    /// Inputs each bot sends a second.
+    #[arg(long, default_value_t = 5.0)]
+    input_rate: f64,
+    /// Milliseconds between the pings each bot measures latency with.
+    #[arg(long, default_value_t = 1000)]
+    ping_interval: u64,
+    /// Address the server serves metrics on, to report its throughput.
+    #[arg(short, long)]
+    metrics: Option<String>,
+    /// Bots log in as <prefix>-<n>.
+    #[arg(long, default_value = "bot")]
+    prefix: String,
+}
+
+fn join_plan(join: &str) -> Result<JoinPlan, String> {
+    match join {
+        "lobby" => Ok(JoinPlan::Lobby),
+        "create" => Ok(JoinPlan::Create),
+        "matchmake" => Ok(JoinPlan::Matchmake(String::from("default"))),
+        other => match other.strip_prefix("matchmake:") {
+            Some(mode) => Ok(JoinPlan::Matchmake(mode.to_owned())),
+            None => other
+                .parse()
+                .map(JoinPlan::Join)
+                .map_err(|_| format!("Unknown join plan {}", other)),
+        },
+    }
+}
+
+fn read_script(path: &PathBuf) -> Result<Vec<PlayerInput>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut inputs = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let input = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [x, y] => x.parse().ok().zip(y.parse().ok()),
+            _ => None,
+        };
+        match input {
+            Some((x, y)) => inputs.push(PlayerInput { x, y }),
+            None => return Err(format!("Line {}: expected \"x y\"", number + 1)),
+        }
+    }
+    if inputs.is_empty() {
+        return Err(format!("{} has no inputs", path.display()));
+    }
+    Ok(inputs)
+}
+
+fn bot_config(args: &Args) -> Result<BotConfig, String> {
+    let inputs = match (&args.script, args.idle) {
+        (Some(path), _) => InputPlan::Script(read_script(path)?),
+        (None, true) => InputPlan::Idle,
+        (None, false) => InputPlan::Random,
+    };
+    if !(args.input_rate > 0.0 && args.input_rate.is_finite()) {
+        return Err(String::from("--input-rate must be above 0"));
+    }
+    if args.ping_interval == 0 {
+        return Err(String::from("--ping-interval must be above 0"));
+    }
+    Ok(BotConfig {
+        join: join_plan(&args.join)?,
+        inputs,
+        input_rate: args.input_rate,
+        ping_interval: Duration::from_millis(args.ping_interval),
+        ..BotConfig::default()
+    })
+}
+
+/// What happened to the bots over a run.
+#[derive(Default)]
+struct Tally {
+    connected: usize,
+    failed: Vec<String>,
+    logged_in: usize,
+    in_game: usize,
+    dropped: usize,
+    inputs_sent: u64,
+    latencies: Vec<Duration>,
+}
+
+impl Tally {
+    /// Counts a bot which is leaving the run.
+    fn retire(&mut self, bot: &Bot) {
+        self.logged_in += bot.logged_in() as usize;
+        self.in_game += bot.in_game() as usize;
+        self.inputs_sent += bot.inputs_sent();
+        self.latencies.extend(bot.take_latencies());
+        bot.disconnect();
+    }
+}
+
+/// Steps every bot, retiring the ones which lost their connection.
+fn step_all(bots: &mut Vec<Bot>, tally: &mut Tally) {
+    let now = Instant::now();
+    bots.retain_mut(|bot| match bot.step(now) {
+        Ok(()) => true,
+        Err(_) => {
+            tally.dropped += 1;
+            tally.retire(bot);
+            false
+        }
+    });
+}
+
+fn scrape(addr: &Option<String>) -> Option<BTreeMap<String, f64>> {
+    let addr = addr.as_ref()?;
+    match scrape::fetch(addr) {
+        Ok(text) => Some(scrape::totals(&text)),
+        Err(e) => {
+            eprintln!("Failed to scrape metrics from {}: {}", addr, e);
+            None
+        }
+    }
+}
+
+fn report_server(before: &BTreeMap<String, f64>, after: &BTreeMap<String, f64>, secs: f64) {
+    let delta = |name: &str| {
+        let name = format!("multiplayer_{}", name);
+        after.get(&name).unwrap_or(&0.0) - before.get(&name).unwrap_or(&0.0)
+    };
+    let rate = |name: &str| delta(name) / secs;
+    let gauge = |name: &str| *after.get(&format!("multiplayer_{}", name)).unwrap_or(&0.0);
+    println!(
+        "Server:     {:.0} msgs/s in, {:.0} msgs/s out, {:.1} KiB/s in, {:.1} KiB/s out",
+        rate("messages_received_total"),
+        rate("messages_sent_total"),
+        rate("bytes_received_total") / 1024.0,
+        rate("bytes_sent_total") / 1024.0,
+    );
+    let ticks = delta("tick_duration_seconds_count");
+    let tick_ms = match ticks > 0.0 {
+        true => delta("tick_duration_seconds_sum") / ticks * 1000.0,
+        false => 0.0,
+    };
+    println!(
+        "            {:.0} clients, {:.0} games, {:.3}ms a tick on average",
+        gauge("clients_connected"),
+        gauge("games_running"),
+        tick_ms
+    );
+}
+
+fn main() {
+    // Bots losing their connection at the end of a run isn't worth a warning.
+    logging::init("error");
+    let args = Args::parse();
+    let config = bot_config(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    // Bots never print, their results are reported at the end.
+    console::set_printer(|_| ());
+
+    let pool = ThreadPool::new(args.bots + HANDLER_WORKERS);
+    let before = scrape(&args.metrics);
+
+    let mut bots = Vec::with_capacity(args.bots);
+    let mut tally = Tally::default();
+    let ramp = Duration::from_secs_f64(args.ramp.max(0.0)) / args.bots.max(1) as u32;
+    println!("Connecting {} bots to {}", args.bots, args.addr);
+    for n in 0..args.bots {
+        let id = format!("{}-{}", args.prefix, n);
+        match Bot::connect(&args.addr, id, config.clone(), pool.dispatcher.clone()) {
+            Ok(bot) => {
+                tally.connected += 1;
+                bots.push(bot);
+            }
+            Err(e) => tally.failed.push(e.to_string()),
+        }
+        let next = Instant::now() + ramp;
+        while Instant::now() < next {
+            step_all(&mut bots, &mut tally);
+            thread::sleep(STEP.min(next.saturating_duration_since(Instant::now())));
+        }
+    }
+
+    let started = Instant::now();
+    let end = started + Duration::from_secs_f64(args.duration.max(0.0));
+    println!("Playing for {:.0}s", args.duration);
+    while Instant::now() < end {
+        step_all(&mut bots, &mut tally);
+        thread::sleep(STEP);
+    }
+    let elapsed = started.elapsed().as_secs_f64().max(f64::EPSILON);
+    let after = scrape(&args.metrics);
+    for bot in bots.iter() {
+        tally.retire(bot);
+    }
+
+    println!(
+        "Bots:       {} connected, {} failed, {} logged in, {} in a game, {} dropped",
+        tally.connected,
+        tally.failed.len(),
+        tally.logged_in,
+        tally.in_game,
+        tally.dropped
+    );
+    if let Some(reason) = tally.failed.first() {
+        println!("            first failure: {}", reason);
+    }
+    println!(
+        "Inputs:     {} sent, {:.1}/s",
+        tally.inputs_sent,
+        tally.inputs_sent as f64 / elapsed
+    );
+    println!(
+        "Latency:    {}",
+        Latencies::from_samples(std::mem::take(&mut tally.latencies))
+    );
+    if let (Some(before), Some(after)) = (before, after) {
+        report_server(&before, &after, elapsed);
+    }
+    if tally.connected == 0 {
+        process::exit(1);
+    }
+}
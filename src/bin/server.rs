@@ -1,7 +1,151 @@
 extern crate multiplayer;
+use clap::Parser;
+use multiplayer::logging;
+use multiplayer::metrics::exporter;
+use multiplayer::server_side::config::ServerConfig;
 use multiplayer::server_side::server;
+use std::path::PathBuf;
+use std::process;
+
+/// Runs the multiplayer server. Settings come from the defaults, then the config file, then
+/// MULTIPLAYER_* environment variables, then these flags.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// TOML file to read the config from.
+    #[arg(short, long, env = "MULTIPLAYER_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address clients connect to, e.g. 0.0.0.0:7878.
+    #[arg(long)]
+    addr: Option<String>,
+    /// Most worker threads in the pool.
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Worker threads kept even when idle.
+    #[arg(long)]
+    min_threads: Option<usize>,
+    /// Game ticks a second.
+    #[arg(long)]
+    tick_rate: Option<u32>,
+    /// Snapshots published to clients a second.
+    #[arg(long)]
+    publish_rate: Option<u32>,
+    /// Seconds snapshots are held back from spectators.
+    #[arg(long)]
+    spectator_delay: Option<u64>,
+    /// Most clients connected at once.
+    #[arg(long)]
+    max_clients: Option<usize>,
+    /// Most games running at once.
+    #[arg(long)]
+    max_games: Option<usize>,
+    /// Seconds a new connection has to identify itself, 0 for no limit.
+    #[arg(long)]
+    handshake_timeout: Option<u64>,
+    /// Seconds a client may be quiet before being dropped, 0 for no limit.
+    #[arg(long)]
+    idle_timeout: Option<u64>,
+    /// Directory games are saved to.
+    #[arg(long)]
+    save_dir: Option<PathBuf>,
+    /// Directory games are recorded to.
+    #[arg(long)]
+    replay_dir: Option<PathBuf>,
+    /// Address the admin port listens on. The token comes from the config or environment.
+    #[arg(long)]
+    admin_addr: Option<String>,
+    /// Address Prometheus metrics are served on.
+    #[arg(long)]
+    metrics_addr: Option<String>,
+    /// Turns a feature off, e.g. --disable chat. May be repeated.
+    #[arg(long, value_name = "FEATURE")]
+    disable: Vec<String>,
+    /// Checks the config and exits without starting the server.
+    #[arg(long)]
+    check: bool,
+}
+
+impl Args {
+    /// Overrides a config with the flags which were given.
+    fn apply(&self, config: &mut ServerConfig) -> Result<(), String> {
+        if let Some(addr) = &self.addr {
+            config.addr = addr.clone();
+        }
+        if let Some(threads) = self.threads {
+            config.threads = threads;
+        }
+        if let Some(threads) = self.min_threads {
+            config.min_threads = threads;
+        }
+        if let Some(rate) = self.tick_rate {
+            config.tick_rate = rate;
+        }
+        if let Some(rate) = self.publish_rate {
+            config.publish_rate = rate;
+        }
+        if let Some(delay) = self.spectator_delay {
+            config.spectator_delay_secs = delay;
+        }
+        if let Some(max) = self.max_clients {
+            config.limits.max_clients = max;
+        }
+        if let Some(max) = self.max_games {
+            config.limits.max_games = max;
+        }
+        if let Some(secs) = self.handshake_timeout {
+            config.limits.handshake_timeout_secs = secs;
+        }
+        if let Some(secs) = self.idle_timeout {
+            config.limits.idle_timeout_secs = secs;
+        }
+        if let Some(dir) = &self.save_dir {
+            config.save_dir = Some(dir.clone());
+        }
+        if let Some(dir) = &self.replay_dir {
+            config.replay_dir = Some(dir.clone());
+        }
+        if let Some(addr) = &self.metrics_addr {
+            config.metrics_addr = Some(addr.clone());
+        }
+        config.set_admin(self.admin_addr.clone(), None);
+        for feature in self.disable.iter() {
+            config
+                .features
+                .set(feature, false)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds the config from every source, in order of precedence.
+fn load_config(args: &Args) -> Result<ServerConfig, String> {
+    let mut config = match &args.config {
+        Some(path) => ServerConfig::load_file(path).map_err(|e| e.to_string())?,
+        None => ServerConfig::default(),
+    };
+    config.apply_env().map_err(|e| e.to_string())?;
+    args.apply(&mut config)?;
+    config.validate().map_err(|e| e.to_string())?;
+    Ok(config)
+}
 
 fn main() {
-    let svr = server::Server::new("127.0.0.1:7878", 100);
+    let args = Args::parse();
+    let config = load_config(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    if args.check {
+        println!("Config is valid");
+        return;
+    }
+
+    logging::init("info");
+    let svr = server::Server::from_config(&config);
+    // Expose metrics for Prometheus to scrape.
+    if let Some(addr) = &config.metrics_addr {
+        exporter::serve(addr).expect("Failed to serve metrics");
+    }
     svr.start();
 }
@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+use serde_json::de::IoRead;
+use serde_json::{StreamDeserializer, Value};
+use std::io::{self, BufReader};
+use std::net::{IpAddr, TcpStream};
+
+use crate::comms::message::{self, GameSummary, Message};
+use crate::game::GameID;
+use crate::server_side::client::{ClientID, ClientState};
+
+pub const ADMIN_LOGIN_IDENTIFIER: &str = "AdminLogin";
+pub const ADMIN_REQUEST_IDENTIFIER: &str = "AdminRequest";
+pub const ADMIN_RESPONSE_IDENTIFIER: &str = "AdminResponse";
+
+#[derive(Deserialize, Serialize)]
+/// First message on an admin connection. The connection is closed if the token is wrong.
+pub struct AdminLogin {
+    pub token: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+/// Commands an operator can run against a server
+/// * ListClients - Every connected client and their ClientState.
+/// * ListGames - Every game and its GameState.
+/// * Kick - Disconnects a client.
+/// * Ban - Disconnects a client and refuses them until unbanned.
+/// * BanIp - Disconnects every client from an address and refuses it until unbanned.
+/// * Unban - Lets a banned client connect again.
+/// * UnbanIp - Lets a banned address connect again.
+/// * Pause - Pauses a game.
+/// * Resume - Resumes a paused game.
+/// * Broadcast - Sends a system message to every connected client.
+pub enum AdminCommand {
+    ListClients,
+    ListGames,
+    Kick(ClientID),
+    Ban(ClientID),
+    BanIp(IpAddr),
+    Unban(ClientID),
+    UnbanIp(IpAddr),
+    Pause(GameID),
+    Resume(GameID),
+    Broadcast(String),
+}
+
+#[derive(Deserialize, Serialize)]
+/// Message to run an AdminCommand
+pub struct AdminRequest {
+    pub command: AdminCommand,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Summary of a single connected client
+/// * addr - The address the client connected from, if it is still known.
+pub struct ClientSummary {
+    pub client_id: ClientID,
+    pub state: ClientState,
+    pub game_id: Option<GameID>,
+    pub addr: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Response to an AdminLogin or AdminRequest
+/// * Done - The command succeeded, with a description of what it did.
+/// * Clients - Response to ListClients.
+/// * Games - Response to ListGames.
+/// * Error - The login or command failed.
+pub enum AdminResponse {
+    Done(String),
+    Clients(Vec<ClientSummary>),
+    Games(Vec<GameSummary>),
+    Error(String),
+}
+
+impl Message<'static> for AdminLogin {
+    const MSG_TYPE: &'static str = ADMIN_LOGIN_IDENTIFIER;
+}
+impl Message<'static> for AdminRequest {
+    const MSG_TYPE: &'static str = ADMIN_REQUEST_IDENTIFIER;
+}
+impl Message<'static> for AdminResponse {
+    const MSG_TYPE: &'static str = ADMIN_RESPONSE_IDENTIFIER;
+}
+
+/// Reads the messages of an admin connection. The socket is buffered once for the whole
+/// connection, and every message is read from the same stream, so nothing read past the end of
+/// a message is lost.
+pub struct MessageReader {
+    values: StreamDeserializer<'static, IoRead<BufReader<TcpStream>>, Value>,
+}
+
+impl MessageReader {
+    pub fn new(socket: TcpStream) -> MessageReader {
+        MessageReader {
+            values: serde_json::Deserializer::from_reader(BufReader::new(socket)).into_iter(),
+        }
+    }
+
+    /// Reads the next message.
+    ///
+    /// # Returns
+    ///
+    /// * The msg_type and data of the message, or None if the connection closed.
+    /// * An InvalidData error if the message isn't json, or has no msg_type.
+    pub fn next_message(&mut self) -> io::Result<Option<(String, Value)>> {
+        let v = match self.values.next() {
+            Some(v) => v?,
+            None => return Ok(None),
+        };
+        match (v.get("msg_type"), v.get("data")) {
+            (Some(Value::String(msg_type)), Some(data)) => {
+                Ok(Some((msg_type.clone(), data.clone())))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Admin message has no msg_type",
+            )),
+        }
+    }
+}
+
+/// A connection to a server's admin port.
+///
+/// # Example
+///
+/// ```no_run
+/// extern crate multiplayer;
+/// use multiplayer::comms::admin::{AdminClient, AdminCommand};
+///
+/// let mut admin = AdminClient::connect("127.0.0.1:7879", "secret").unwrap();
+/// let response = admin.request(AdminCommand::ListClients).unwrap();
+/// println!("{:?}", response);
+/// ```
+pub struct AdminClient {
+    socket: TcpStream,
+    reader: MessageReader,
+}
+
+impl AdminClient {
+    /// Connects to an admin port and logs in.
+    ///
+    /// # Returns
+    ///
+    /// * A PermissionDenied error if the server refused the token.
+    pub fn connect(addr: &str, token: &str) -> io::Result<AdminClient> {
+        let mut socket = TcpStream::connect(addr)?;
+        message::try_send_json(
+            &AdminLogin {
+                token: token.to_owned(),
+            },
+            &mut socket,
+        )?;
+
+        let reader = MessageReader::new(socket.try_clone()?);
+        let mut client = AdminClient { socket, reader };
+        match client.response()? {
+            AdminResponse::Error(e) => Err(io::Error::new(io::ErrorKind::PermissionDenied, e)),
+            _ => Ok(client),
+        }
+    }
+
+    /// Runs a command and waits for its response.
+    pub fn request(&mut self, command: AdminCommand) -> io::Result<AdminResponse> {
+        message::try_send_json(&AdminRequest { command }, &mut self.socket)?;
+        self.response()
+    }
+
+    fn response(&mut self) -> io::Result<AdminResponse> {
+        match self.reader.next_message()? {
+            Some((msg_type, data)) if msg_type == ADMIN_RESPONSE_IDENTIFIER => {
+                Ok(serde_json::from_value(data)?)
+            }
+            Some((msg_type, _)) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected AdminResponse, got {}", msg_type),
+            )),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Admin connection closed",
+            )),
+        }
+    }
+}
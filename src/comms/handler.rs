@@ -1,10 +1,89 @@
 use crate::comms::message;
+use crate::errors::MessageError;
+use crate::metrics::registry::{registry, UNKNOWN_LABEL};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
+use tracing::{debug_span, info, trace, warn};
 
 pub trait TryClone: std::marker::Sized {
     fn try_clone(&self) -> std::io::Result<Self>;
 }
 
+/// The most of an unfinished message a JsonStream holds before giving up on it.
+pub const MAX_PENDING: usize = 16 * message::MSG_SIZE;
+
+/// Splits the bytes read from a socket into json messages. Reads are a fixed size, so a message
+/// may be split between two of them. The start of a message is kept until the rest arrives.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::comms::handler::JsonStream;
+///
+/// let mut stream = JsonStream::new();
+/// let first = stream.push(br#"{"msg_type": "Ping"} {"msg_ty"#);
+/// assert_eq!(first.len(), 1);
+///
+/// let second = stream.push(br#"pe": "Pong"}"#);
+/// assert_eq!(second[0].as_ref().unwrap()["msg_type"], "Pong");
+///
+/// let bad = stream.push(b"not json");
+/// assert!(bad[0].is_err());
+/// ```
+#[derive(Debug, Default)]
+pub struct JsonStream {
+    pending: Vec<u8>,
+}
+
+impl JsonStream {
+    pub fn new() -> JsonStream {
+        JsonStream::default()
+    }
+
+    /// Adds bytes read from a socket. Reads into a zeroed buffer may be padded with zeroes, which
+    /// are ignored.
+    ///
+    /// # Returns
+    ///
+    /// * Every message the bytes complete, in order. Anything which isn't json gives a
+    ///   MessageError, and everything after it so far is dropped, since there is no telling where
+    ///   the next message starts. So is an unfinished message longer than MAX_PENDING.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Result<Value, MessageError>> {
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        self.pending.extend_from_slice(&bytes[..end]);
+
+        let mut messages = Vec::new();
+        let consumed = {
+            let mut values = serde_json::Deserializer::from_slice(&self.pending).into_iter();
+            loop {
+                let offset = values.byte_offset();
+                match values.next() {
+                    Some(Ok(value)) => messages.push(Ok(value)),
+                    // The rest of the message hasn't arrived yet.
+                    Some(Err(e)) if e.is_eof() => break offset,
+                    Some(Err(e)) => {
+                        messages.push(Err(MessageError {
+                            reason: e.to_string(),
+                        }));
+                        break self.pending.len();
+                    }
+                    None => break self.pending.len(),
+                }
+            }
+        };
+        self.pending.drain(..consumed);
+
+        if self.pending.len() > MAX_PENDING {
+            self.pending.clear();
+            messages.push(Err(MessageError {
+                reason: format!("Message longer than {} bytes", MAX_PENDING),
+            }));
+        }
+        messages
+    }
+}
+
 /// Default implementer of Handler
 #[derive(Clone)]
 pub struct DefaultHandler;
@@ -17,10 +96,13 @@ impl TryClone for DefaultHandler {
 
 impl Handler for DefaultHandler {
     fn handle_text_msg(&mut self, msg: message::TextMessage) {
-        println!("Text: {}", msg.text);
+        info!(text = %msg.text, "Text message");
     }
     fn handle_request_client_id(&mut self, msg: message::RequestClientID) {}
     fn handle_request_client_id_response(&mut self, msg: message::RequestClientIDResponse) {}
+    fn handle_chat_broadcast(&mut self, msg: message::ChatBroadcast) {
+        info!(text = %msg.text, "Chat message");
+    }
 }
 
 /// Trait to define how to handle different types of messages.
@@ -51,66 +133,493 @@ pub trait Handler: TryClone {
     fn handle_text_msg(&mut self, msg: message::TextMessage) {}
     fn handle_request_client_id(&mut self, msg: message::RequestClientID) {}
     fn handle_request_client_id_response(&mut self, msg: message::RequestClientIDResponse) {}
+    fn handle_chat_msg(&mut self, msg: message::ChatMessage) {}
+    fn handle_chat_broadcast(&mut self, msg: message::ChatBroadcast) {}
+    fn handle_create_game(&mut self, msg: message::CreateGame) {}
+    fn handle_join_game(&mut self, msg: message::JoinGame) {}
+    fn handle_spectate_game(&mut self, msg: message::SpectateGame) {}
+    fn handle_leave_game(&mut self, msg: message::LeaveGame) {}
+    fn handle_game_joined(&mut self, msg: message::GameJoined) {}
+    fn handle_game_left(&mut self, msg: message::GameLeft) {}
+    fn handle_request_game_list(&mut self, msg: message::RequestGameList) {}
+    fn handle_game_list(&mut self, msg: message::GameList) {}
+    fn handle_game_snapshot(&mut self, msg: message::GameSnapshot) {}
+    fn handle_player_input(&mut self, msg: message::PlayerInput) {}
+    fn handle_enqueue_matchmaking(&mut self, msg: message::EnqueueMatchmaking) {}
+    fn handle_leave_matchmaking(&mut self, msg: message::LeaveMatchmaking) {}
+    fn handle_matchmaking_status(&mut self, msg: message::MatchmakingStatus) {}
+    fn handle_create_party(&mut self, msg: message::CreateParty) {}
+    fn handle_invite_to_party(&mut self, msg: message::InviteToParty) {}
+    fn handle_party_invite(&mut self, msg: message::PartyInvite) {}
+    fn handle_accept_party_invite(&mut self, msg: message::AcceptPartyInvite) {}
+    fn handle_decline_party_invite(&mut self, msg: message::DeclinePartyInvite) {}
+    fn handle_leave_party(&mut self, msg: message::LeaveParty) {}
+    fn handle_party_update(&mut self, msg: message::PartyUpdate) {}
+    fn handle_host_game(&mut self, msg: message::HostGame) {}
+    fn handle_game_hosted(&mut self, msg: message::GameHosted) {}
+    fn handle_request_hosted_game_list(&mut self, msg: message::RequestHostedGameList) {}
+    fn handle_hosted_game_list(&mut self, msg: message::HostedGameList) {}
+    fn handle_join_hosted_game(&mut self, msg: message::JoinHostedGame) {}
+    fn handle_leave_hosted_game(&mut self, msg: message::LeaveHostedGame) {}
+    fn handle_host_assigned(&mut self, msg: message::HostAssigned) {}
+    fn handle_peer_joined(&mut self, msg: message::PeerJoined) {}
+    fn handle_peer_left(&mut self, msg: message::PeerLeft) {}
+    fn handle_relay(&mut self, msg: message::Relay) {}
+    fn handle_relayed(&mut self, msg: message::Relayed) {}
+    fn handle_create_lockstep_game(&mut self, msg: message::CreateLockstepGame) {}
+    fn handle_lockstep_start(&mut self, msg: message::LockstepStart) {}
+    fn handle_lockstep_input(&mut self, msg: message::LockstepInput) {}
+    fn handle_input_bundle(&mut self, msg: message::InputBundle) {}
+    fn handle_state_checksum(&mut self, msg: message::StateChecksum) {}
+    fn handle_desync_detected(&mut self, msg: message::DesyncDetected) {}
+    fn handle_remote_input(&mut self, msg: message::RemoteInput) {}
+    fn handle_request_replay_list(&mut self, msg: message::RequestReplayList) {}
+    fn handle_replay_list(&mut self, msg: message::ReplayList) {}
+    fn handle_watch_replay(&mut self, msg: message::WatchReplay) {}
+    fn handle_replay_control(&mut self, msg: message::ReplayControl) {}
+    fn handle_replay_status(&mut self, msg: message::ReplayStatus) {}
+    fn handle_vote_pause(&mut self, msg: message::VotePause) {}
+    fn handle_vote_resume(&mut self, msg: message::VoteResume) {}
+    fn handle_pause_vote_status(&mut self, msg: message::PauseVoteStatus) {}
+    fn handle_pause_countdown(&mut self, msg: message::PauseCountdown) {}
+    fn handle_game_state_changed(&mut self, msg: message::GameStateChanged) {}
+    fn handle_ping(&mut self, msg: message::Ping) {}
+    fn handle_pong(&mut self, msg: message::Pong) {}
 
-    fn receive_json(&mut self, buff: &Vec<u8>) {
-        let v = self.parse_json(buff);
-        let identifier = v.get("msg_type").unwrap();
-        let data = v.get("data").unwrap();
-        let data_string = serde_json::to_string(data).expect("Failed to convert data");
+    /// Handles every message in a buffer. A single read from a socket may contain several
+    /// messages written back to back, so each json value is handled in turn.
+    fn receive_json(&mut self, buff: &[u8]) {
+        for v in self.parse_json_stream(buff) {
+            self.receive_value(v);
+        }
+    }
+
+    /// Handles the messages completed by a read into a JsonStream, reporting anything which
+    /// isn't json.
+    fn receive_messages(&mut self, messages: Vec<Result<Value, MessageError>>) {
+        for message in messages {
+            match message {
+                Ok(v) => self.receive_value(v),
+                Err(e) => warn!(error = %e, "Dropped malformed message"),
+            }
+        }
+    }
 
-        println!("Received Json: {}", v);
+    /// Handles a single json message.
+    fn receive_value(&mut self, v: Value) {
+        let (identifier, data) = match (v.get("msg_type"), v.get("data")) {
+            (Some(identifier), Some(data)) => (identifier, data),
+            _ => {
+                warn!(json = %v, "Message needs a msg_type and data");
+                return;
+            }
+        };
+
+        trace!(json = %v, "Received json");
         match identifier {
             Value::String(text) => {
+                let _span = debug_span!("receive", msg_type = %text).entered();
+                // Labels are chosen by the sender, so only known ones are kept apart.
+                match message::MSG_TYPES.contains(&text.as_str()) {
+                    true => registry().messages_received.inc(text),
+                    false => registry().messages_received.inc(UNKNOWN_LABEL),
+                }
                 match text.as_str() {
                     message::TEXT_MESSAGE_IDENTIFIER => {
                         // handle text message
-                        let msg: message::TextMessage = serde_json::from_str(data_string.as_str())
-                            .expect("Failed to parse TextMessage");
-                        self.handle_text_msg(msg);
+                        if let Some(msg) = decode::<message::TextMessage>(text, data) {
+                            self.handle_text_msg(msg);
+                        }
                     }
                     message::REQUEST_CLIENT_ID_IDENTIFIER => {
                         // handle client id request
-                        let msg: message::RequestClientID =
-                            serde_json::from_str(data_string.as_str())
-                                .expect("Failed to parse RequestClientID");
-                        self.handle_request_client_id(msg);
+                        if let Some(msg) = decode::<message::RequestClientID>(text, data) {
+                            self.handle_request_client_id(msg);
+                        }
                     }
                     message::REQUEST_CLIENT_ID_RESPONSE_IDENTIFIER => {
                         // handle client id request response
-                        let msg: message::TextMessage = serde_json::from_str(data_string.as_str())
-                            .expect("Failed to parse RequestClientIDResponse");
-                        self.handle_text_msg(msg);
+                        if let Some(msg) = decode::<message::RequestClientIDResponse>(text, data) {
+                            self.handle_request_client_id_response(msg);
+                        }
+                    }
+                    message::CHAT_MESSAGE_IDENTIFIER => {
+                        // handle chat message
+                        if let Some(msg) = decode::<message::ChatMessage>(text, data) {
+                            self.handle_chat_msg(msg);
+                        }
+                    }
+                    message::CHAT_BROADCAST_IDENTIFIER => {
+                        // handle chat broadcast
+                        if let Some(msg) = decode::<message::ChatBroadcast>(text, data) {
+                            self.handle_chat_broadcast(msg);
+                        }
+                    }
+                    message::CREATE_GAME_IDENTIFIER => {
+                        // handle create game request
+                        if let Some(msg) = decode::<message::CreateGame>(text, data) {
+                            self.handle_create_game(msg);
+                        }
+                    }
+                    message::JOIN_GAME_IDENTIFIER => {
+                        // handle join game request
+                        if let Some(msg) = decode::<message::JoinGame>(text, data) {
+                            self.handle_join_game(msg);
+                        }
+                    }
+                    message::SPECTATE_GAME_IDENTIFIER => {
+                        // handle spectate game request
+                        if let Some(msg) = decode::<message::SpectateGame>(text, data) {
+                            self.handle_spectate_game(msg);
+                        }
+                    }
+                    message::LEAVE_GAME_IDENTIFIER => {
+                        // handle leave game request
+                        if let Some(msg) = decode::<message::LeaveGame>(text, data) {
+                            self.handle_leave_game(msg);
+                        }
+                    }
+                    message::GAME_JOINED_IDENTIFIER => {
+                        // handle game joined notification
+                        if let Some(msg) = decode::<message::GameJoined>(text, data) {
+                            self.handle_game_joined(msg);
+                        }
+                    }
+                    message::GAME_LEFT_IDENTIFIER => {
+                        // handle game left notification
+                        if let Some(msg) = decode::<message::GameLeft>(text, data) {
+                            self.handle_game_left(msg);
+                        }
+                    }
+                    message::REQUEST_GAME_LIST_IDENTIFIER => {
+                        // handle game list request
+                        if let Some(msg) = decode::<message::RequestGameList>(text, data) {
+                            self.handle_request_game_list(msg);
+                        }
+                    }
+                    message::GAME_LIST_IDENTIFIER => {
+                        // handle game list
+                        if let Some(msg) = decode::<message::GameList>(text, data) {
+                            self.handle_game_list(msg);
+                        }
+                    }
+                    message::GAME_SNAPSHOT_IDENTIFIER => {
+                        // handle game snapshot
+                        if let Some(msg) = decode::<message::GameSnapshot>(text, data) {
+                            self.handle_game_snapshot(msg);
+                        }
+                    }
+                    message::PLAYER_INPUT_IDENTIFIER => {
+                        // handle player input
+                        if let Some(msg) = decode::<message::PlayerInput>(text, data) {
+                            self.handle_player_input(msg);
+                        }
+                    }
+                    message::ENQUEUE_MATCHMAKING_IDENTIFIER => {
+                        // handle matchmaking request
+                        if let Some(msg) = decode::<message::EnqueueMatchmaking>(text, data) {
+                            self.handle_enqueue_matchmaking(msg);
+                        }
+                    }
+                    message::LEAVE_MATCHMAKING_IDENTIFIER => {
+                        // handle leave matchmaking request
+                        if let Some(msg) = decode::<message::LeaveMatchmaking>(text, data) {
+                            self.handle_leave_matchmaking(msg);
+                        }
                     }
-                    _ => println!("Unknown Message Identifier"),
+                    message::MATCHMAKING_STATUS_IDENTIFIER => {
+                        // handle matchmaking status
+                        if let Some(msg) = decode::<message::MatchmakingStatus>(text, data) {
+                            self.handle_matchmaking_status(msg);
+                        }
+                    }
+                    message::CREATE_PARTY_IDENTIFIER => {
+                        // handle create party request
+                        if let Some(msg) = decode::<message::CreateParty>(text, data) {
+                            self.handle_create_party(msg);
+                        }
+                    }
+                    message::INVITE_TO_PARTY_IDENTIFIER => {
+                        // handle party invite request
+                        if let Some(msg) = decode::<message::InviteToParty>(text, data) {
+                            self.handle_invite_to_party(msg);
+                        }
+                    }
+                    message::PARTY_INVITE_IDENTIFIER => {
+                        // handle party invite
+                        if let Some(msg) = decode::<message::PartyInvite>(text, data) {
+                            self.handle_party_invite(msg);
+                        }
+                    }
+                    message::ACCEPT_PARTY_INVITE_IDENTIFIER => {
+                        // handle accepted party invite
+                        if let Some(msg) = decode::<message::AcceptPartyInvite>(text, data) {
+                            self.handle_accept_party_invite(msg);
+                        }
+                    }
+                    message::DECLINE_PARTY_INVITE_IDENTIFIER => {
+                        // handle declined party invite
+                        if let Some(msg) = decode::<message::DeclinePartyInvite>(text, data) {
+                            self.handle_decline_party_invite(msg);
+                        }
+                    }
+                    message::LEAVE_PARTY_IDENTIFIER => {
+                        // handle leave party request
+                        if let Some(msg) = decode::<message::LeaveParty>(text, data) {
+                            self.handle_leave_party(msg);
+                        }
+                    }
+                    message::PARTY_UPDATE_IDENTIFIER => {
+                        // handle party update
+                        if let Some(msg) = decode::<message::PartyUpdate>(text, data) {
+                            self.handle_party_update(msg);
+                        }
+                    }
+                    message::HOST_GAME_IDENTIFIER => {
+                        // handle host game request
+                        if let Some(msg) = decode::<message::HostGame>(text, data) {
+                            self.handle_host_game(msg);
+                        }
+                    }
+                    message::GAME_HOSTED_IDENTIFIER => {
+                        // handle game hosted notification
+                        if let Some(msg) = decode::<message::GameHosted>(text, data) {
+                            self.handle_game_hosted(msg);
+                        }
+                    }
+                    message::REQUEST_HOSTED_GAME_LIST_IDENTIFIER => {
+                        // handle hosted game list request
+                        if let Some(msg) = decode::<message::RequestHostedGameList>(text, data) {
+                            self.handle_request_hosted_game_list(msg);
+                        }
+                    }
+                    message::HOSTED_GAME_LIST_IDENTIFIER => {
+                        // handle hosted game list
+                        if let Some(msg) = decode::<message::HostedGameList>(text, data) {
+                            self.handle_hosted_game_list(msg);
+                        }
+                    }
+                    message::JOIN_HOSTED_GAME_IDENTIFIER => {
+                        // handle join hosted game request
+                        if let Some(msg) = decode::<message::JoinHostedGame>(text, data) {
+                            self.handle_join_hosted_game(msg);
+                        }
+                    }
+                    message::LEAVE_HOSTED_GAME_IDENTIFIER => {
+                        // handle leave hosted game request
+                        if let Some(msg) = decode::<message::LeaveHostedGame>(text, data) {
+                            self.handle_leave_hosted_game(msg);
+                        }
+                    }
+                    message::HOST_ASSIGNED_IDENTIFIER => {
+                        // handle host assignment
+                        if let Some(msg) = decode::<message::HostAssigned>(text, data) {
+                            self.handle_host_assigned(msg);
+                        }
+                    }
+                    message::PEER_JOINED_IDENTIFIER => {
+                        // handle peer joined notification
+                        if let Some(msg) = decode::<message::PeerJoined>(text, data) {
+                            self.handle_peer_joined(msg);
+                        }
+                    }
+                    message::PEER_LEFT_IDENTIFIER => {
+                        // handle peer left notification
+                        if let Some(msg) = decode::<message::PeerLeft>(text, data) {
+                            self.handle_peer_left(msg);
+                        }
+                    }
+                    message::RELAY_IDENTIFIER => {
+                        // handle relay request
+                        if let Some(msg) = decode::<message::Relay>(text, data) {
+                            self.handle_relay(msg);
+                        }
+                    }
+                    message::RELAYED_IDENTIFIER => {
+                        // handle relayed message
+                        if let Some(msg) = decode::<message::Relayed>(text, data) {
+                            self.handle_relayed(msg);
+                        }
+                    }
+                    message::CREATE_LOCKSTEP_GAME_IDENTIFIER => {
+                        // handle lockstep game request
+                        if let Some(msg) = decode::<message::CreateLockstepGame>(text, data) {
+                            self.handle_create_lockstep_game(msg);
+                        }
+                    }
+                    message::LOCKSTEP_START_IDENTIFIER => {
+                        // handle lockstep start
+                        if let Some(msg) = decode::<message::LockstepStart>(text, data) {
+                            self.handle_lockstep_start(msg);
+                        }
+                    }
+                    message::LOCKSTEP_INPUT_IDENTIFIER => {
+                        // handle lockstep input
+                        if let Some(msg) = decode::<message::LockstepInput>(text, data) {
+                            self.handle_lockstep_input(msg);
+                        }
+                    }
+                    message::INPUT_BUNDLE_IDENTIFIER => {
+                        // handle input bundle
+                        if let Some(msg) = decode::<message::InputBundle>(text, data) {
+                            self.handle_input_bundle(msg);
+                        }
+                    }
+                    message::STATE_CHECKSUM_IDENTIFIER => {
+                        // handle state checksum
+                        if let Some(msg) = decode::<message::StateChecksum>(text, data) {
+                            self.handle_state_checksum(msg);
+                        }
+                    }
+                    message::DESYNC_DETECTED_IDENTIFIER => {
+                        // handle desync notification
+                        if let Some(msg) = decode::<message::DesyncDetected>(text, data) {
+                            self.handle_desync_detected(msg);
+                        }
+                    }
+                    message::REMOTE_INPUT_IDENTIFIER => {
+                        // handle remote input
+                        if let Some(msg) = decode::<message::RemoteInput>(text, data) {
+                            self.handle_remote_input(msg);
+                        }
+                    }
+                    message::REQUEST_REPLAY_LIST_IDENTIFIER => {
+                        // handle request replay list
+                        if let Some(msg) = decode::<message::RequestReplayList>(text, data) {
+                            self.handle_request_replay_list(msg);
+                        }
+                    }
+                    message::REPLAY_LIST_IDENTIFIER => {
+                        // handle replay list
+                        if let Some(msg) = decode::<message::ReplayList>(text, data) {
+                            self.handle_replay_list(msg);
+                        }
+                    }
+                    message::WATCH_REPLAY_IDENTIFIER => {
+                        // handle watch replay
+                        if let Some(msg) = decode::<message::WatchReplay>(text, data) {
+                            self.handle_watch_replay(msg);
+                        }
+                    }
+                    message::REPLAY_CONTROL_IDENTIFIER => {
+                        // handle replay control
+                        if let Some(msg) = decode::<message::ReplayControl>(text, data) {
+                            self.handle_replay_control(msg);
+                        }
+                    }
+                    message::REPLAY_STATUS_IDENTIFIER => {
+                        // handle replay status
+                        if let Some(msg) = decode::<message::ReplayStatus>(text, data) {
+                            self.handle_replay_status(msg);
+                        }
+                    }
+                    message::VOTE_PAUSE_IDENTIFIER => {
+                        // handle vote pause
+                        if let Some(msg) = decode::<message::VotePause>(text, data) {
+                            self.handle_vote_pause(msg);
+                        }
+                    }
+                    message::VOTE_RESUME_IDENTIFIER => {
+                        // handle vote resume
+                        if let Some(msg) = decode::<message::VoteResume>(text, data) {
+                            self.handle_vote_resume(msg);
+                        }
+                    }
+                    message::PAUSE_VOTE_STATUS_IDENTIFIER => {
+                        // handle pause vote status
+                        if let Some(msg) = decode::<message::PauseVoteStatus>(text, data) {
+                            self.handle_pause_vote_status(msg);
+                        }
+                    }
+                    message::PAUSE_COUNTDOWN_IDENTIFIER => {
+                        // handle pause countdown
+                        if let Some(msg) = decode::<message::PauseCountdown>(text, data) {
+                            self.handle_pause_countdown(msg);
+                        }
+                    }
+                    message::GAME_STATE_CHANGED_IDENTIFIER => {
+                        // handle game state changed
+                        if let Some(msg) = decode::<message::GameStateChanged>(text, data) {
+                            self.handle_game_state_changed(msg);
+                        }
+                    }
+                    message::PING_IDENTIFIER => {
+                        // handle ping
+                        if let Some(msg) = decode::<message::Ping>(text, data) {
+                            self.handle_ping(msg);
+                        }
+                    }
+                    message::PONG_IDENTIFIER => {
+                        // handle pong
+                        if let Some(msg) = decode::<message::Pong>(text, data) {
+                            self.handle_pong(msg);
+                        }
+                    }
+                    _ => warn!("Unknown message identifier"),
                 }
             }
-            _ => println!("No Identifier Provided"),
+            _ => warn!(json = %v, "No identifier provided"),
         }
     }
 
     /// Returns a Value from a buffer.
-    fn parse_json(&self, buff: &Vec<u8>) -> Value {
-        let msg = buff
-            .clone()
+    ///
+    /// # Returns
+    ///
+    /// * MessageError if the buffer isn't json.
+    fn parse_json(&self, buff: &[u8]) -> Result<Value, MessageError> {
+        let end = buff.iter().position(|&x| x == 0).unwrap_or(buff.len());
+        serde_json::from_slice(&buff[..end]).map_err(|e| MessageError {
+            reason: e.to_string(),
+        })
+    }
+
+    /// Returns every Value in a buffer of concatenated json messages. Anything which isn't json,
+    /// or is cut off at the end of the buffer, is reported and dropped. Use a JsonStream to keep
+    /// a message cut off between reads.
+    fn parse_json_stream(&self, buff: &[u8]) -> Vec<Value> {
+        let mut stream = JsonStream::new();
+        let values = stream
+            .push(buff)
             .into_iter()
-            .take_while(|&x| x != 0)
-            .collect::<Vec<_>>();
-        let string = String::from_utf8(msg).expect("Invlaid utf8 message");
-        let v: Value = serde_json::from_str(string.as_str()).expect("Unable to convert to json");
-        v
+            .filter_map(|message| {
+                message
+                    .map_err(|e| warn!(error = %e, "Dropped malformed message"))
+                    .ok()
+            })
+            .collect();
+        if !stream.pending.is_empty() {
+            warn!(bytes = stream.pending.len(), "Dropped unfinished message");
+        }
+        values
     }
 
-    /// Checks if message identifier matches any of the IDENTIFIER constants.
-    fn is_type(&self, buff: &Vec<u8>, id: &str) -> bool {
-        let v = self.parse_json(buff);
-        let identifier = v.get("msg_type").unwrap();
-        let data = v.get("data").unwrap();
-        let data_string = serde_json::to_string(data).expect("Failed to convert data");
+    /// Checks if message identifier matches any of the IDENTIFIER constants. False if the buffer
+    /// isn't a message.
+    fn is_type(&self, buff: &[u8], id: &str) -> bool {
+        let v = match self.parse_json(buff) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!(error = %e, "Dropped malformed message");
+                return false;
+            }
+        };
 
-        println!("Received Json: {}", v);
-        match identifier {
-            Value::String(text) => text == id,
+        trace!(json = %v, "Received json");
+        match (v.get("msg_type"), v.get("data")) {
+            (Some(Value::String(text)), Some(_)) => text == id,
             _ => false,
         }
     }
 }
+
+/// Reads the data of a message into its type.
+///
+/// # Returns
+///
+/// * None if the data doesn't fit the type, which is reported. The message is dropped.
+fn decode<T: DeserializeOwned>(msg_type: &str, data: &Value) -> Option<T> {
+    T::deserialize(data)
+        .map_err(|e| warn!(msg_type, error = %e, "Dropped message with invalid data"))
+        .ok()
+}
@@ -1,14 +1,129 @@
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use std::io::prelude::*;
 use std::net::TcpStream;
 
+use crate::game::model::GameState;
+use crate::game::GameID;
+use crate::metrics::registry::registry;
 use crate::server_side::client::ClientID;
+use crate::server_side::party::PartyID;
 
 pub const MSG_SIZE: usize = 4096;
 pub const TEXT_MESSAGE_IDENTIFIER: &str = "Text";
 pub const REQUEST_CLIENT_ID_IDENTIFIER: &str = "RequestClientID";
 pub const REQUEST_CLIENT_ID_RESPONSE_IDENTIFIER: &str = "RequestClientIDResponse";
+pub const CHAT_MESSAGE_IDENTIFIER: &str = "Chat";
+pub const CHAT_BROADCAST_IDENTIFIER: &str = "ChatBroadcast";
+pub const CREATE_GAME_IDENTIFIER: &str = "CreateGame";
+pub const JOIN_GAME_IDENTIFIER: &str = "JoinGame";
+pub const SPECTATE_GAME_IDENTIFIER: &str = "SpectateGame";
+pub const LEAVE_GAME_IDENTIFIER: &str = "LeaveGame";
+pub const GAME_JOINED_IDENTIFIER: &str = "GameJoined";
+pub const GAME_LEFT_IDENTIFIER: &str = "GameLeft";
+pub const REQUEST_GAME_LIST_IDENTIFIER: &str = "RequestGameList";
+pub const GAME_LIST_IDENTIFIER: &str = "GameList";
+pub const GAME_SNAPSHOT_IDENTIFIER: &str = "GameSnapshot";
+pub const PLAYER_INPUT_IDENTIFIER: &str = "PlayerInput";
+pub const ENQUEUE_MATCHMAKING_IDENTIFIER: &str = "EnqueueMatchmaking";
+pub const LEAVE_MATCHMAKING_IDENTIFIER: &str = "LeaveMatchmaking";
+pub const MATCHMAKING_STATUS_IDENTIFIER: &str = "MatchmakingStatus";
+pub const CREATE_PARTY_IDENTIFIER: &str = "CreateParty";
+pub const INVITE_TO_PARTY_IDENTIFIER: &str = "InviteToParty";
+pub const PARTY_INVITE_IDENTIFIER: &str = "PartyInvite";
+pub const ACCEPT_PARTY_INVITE_IDENTIFIER: &str = "AcceptPartyInvite";
+pub const DECLINE_PARTY_INVITE_IDENTIFIER: &str = "DeclinePartyInvite";
+pub const LEAVE_PARTY_IDENTIFIER: &str = "LeaveParty";
+pub const PARTY_UPDATE_IDENTIFIER: &str = "PartyUpdate";
+pub const HOST_GAME_IDENTIFIER: &str = "HostGame";
+pub const GAME_HOSTED_IDENTIFIER: &str = "GameHosted";
+pub const REQUEST_HOSTED_GAME_LIST_IDENTIFIER: &str = "RequestHostedGameList";
+pub const HOSTED_GAME_LIST_IDENTIFIER: &str = "HostedGameList";
+pub const JOIN_HOSTED_GAME_IDENTIFIER: &str = "JoinHostedGame";
+pub const LEAVE_HOSTED_GAME_IDENTIFIER: &str = "LeaveHostedGame";
+pub const HOST_ASSIGNED_IDENTIFIER: &str = "HostAssigned";
+pub const PEER_JOINED_IDENTIFIER: &str = "PeerJoined";
+pub const PEER_LEFT_IDENTIFIER: &str = "PeerLeft";
+pub const RELAY_IDENTIFIER: &str = "Relay";
+pub const RELAYED_IDENTIFIER: &str = "Relayed";
+pub const CREATE_LOCKSTEP_GAME_IDENTIFIER: &str = "CreateLockstepGame";
+pub const LOCKSTEP_START_IDENTIFIER: &str = "LockstepStart";
+pub const LOCKSTEP_INPUT_IDENTIFIER: &str = "LockstepInput";
+pub const INPUT_BUNDLE_IDENTIFIER: &str = "InputBundle";
+pub const STATE_CHECKSUM_IDENTIFIER: &str = "StateChecksum";
+pub const DESYNC_DETECTED_IDENTIFIER: &str = "DesyncDetected";
+pub const REMOTE_INPUT_IDENTIFIER: &str = "RemoteInput";
+pub const REQUEST_REPLAY_LIST_IDENTIFIER: &str = "RequestReplayList";
+pub const REPLAY_LIST_IDENTIFIER: &str = "ReplayList";
+pub const WATCH_REPLAY_IDENTIFIER: &str = "WatchReplay";
+pub const REPLAY_CONTROL_IDENTIFIER: &str = "ReplayControl";
+pub const REPLAY_STATUS_IDENTIFIER: &str = "ReplayStatus";
+pub const VOTE_PAUSE_IDENTIFIER: &str = "VotePause";
+pub const VOTE_RESUME_IDENTIFIER: &str = "VoteResume";
+pub const PAUSE_VOTE_STATUS_IDENTIFIER: &str = "PauseVoteStatus";
+pub const PAUSE_COUNTDOWN_IDENTIFIER: &str = "PauseCountdown";
+pub const GAME_STATE_CHANGED_IDENTIFIER: &str = "GameStateChanged";
+pub const PING_IDENTIFIER: &str = "Ping";
+pub const PONG_IDENTIFIER: &str = "Pong";
+
+/// Every msg_type a message can have.
+pub const MSG_TYPES: &[&str] = &[
+    TEXT_MESSAGE_IDENTIFIER,
+    REQUEST_CLIENT_ID_IDENTIFIER,
+    REQUEST_CLIENT_ID_RESPONSE_IDENTIFIER,
+    CHAT_MESSAGE_IDENTIFIER,
+    CHAT_BROADCAST_IDENTIFIER,
+    CREATE_GAME_IDENTIFIER,
+    JOIN_GAME_IDENTIFIER,
+    SPECTATE_GAME_IDENTIFIER,
+    LEAVE_GAME_IDENTIFIER,
+    GAME_JOINED_IDENTIFIER,
+    GAME_LEFT_IDENTIFIER,
+    REQUEST_GAME_LIST_IDENTIFIER,
+    GAME_LIST_IDENTIFIER,
+    GAME_SNAPSHOT_IDENTIFIER,
+    PLAYER_INPUT_IDENTIFIER,
+    ENQUEUE_MATCHMAKING_IDENTIFIER,
+    LEAVE_MATCHMAKING_IDENTIFIER,
+    MATCHMAKING_STATUS_IDENTIFIER,
+    CREATE_PARTY_IDENTIFIER,
+    INVITE_TO_PARTY_IDENTIFIER,
+    PARTY_INVITE_IDENTIFIER,
+    ACCEPT_PARTY_INVITE_IDENTIFIER,
+    DECLINE_PARTY_INVITE_IDENTIFIER,
+    LEAVE_PARTY_IDENTIFIER,
+    PARTY_UPDATE_IDENTIFIER,
+    HOST_GAME_IDENTIFIER,
+    GAME_HOSTED_IDENTIFIER,
+    REQUEST_HOSTED_GAME_LIST_IDENTIFIER,
+    HOSTED_GAME_LIST_IDENTIFIER,
+    JOIN_HOSTED_GAME_IDENTIFIER,
+    LEAVE_HOSTED_GAME_IDENTIFIER,
+    HOST_ASSIGNED_IDENTIFIER,
+    PEER_JOINED_IDENTIFIER,
+    PEER_LEFT_IDENTIFIER,
+    RELAY_IDENTIFIER,
+    RELAYED_IDENTIFIER,
+    CREATE_LOCKSTEP_GAME_IDENTIFIER,
+    LOCKSTEP_START_IDENTIFIER,
+    LOCKSTEP_INPUT_IDENTIFIER,
+    INPUT_BUNDLE_IDENTIFIER,
+    STATE_CHECKSUM_IDENTIFIER,
+    DESYNC_DETECTED_IDENTIFIER,
+    REMOTE_INPUT_IDENTIFIER,
+    REQUEST_REPLAY_LIST_IDENTIFIER,
+    REPLAY_LIST_IDENTIFIER,
+    WATCH_REPLAY_IDENTIFIER,
+    REPLAY_CONTROL_IDENTIFIER,
+    REPLAY_STATUS_IDENTIFIER,
+    VOTE_PAUSE_IDENTIFIER,
+    VOTE_RESUME_IDENTIFIER,
+    PAUSE_VOTE_STATUS_IDENTIFIER,
+    PAUSE_COUNTDOWN_IDENTIFIER,
+    GAME_STATE_CHANGED_IDENTIFIER,
+    PING_IDENTIFIER,
+    PONG_IDENTIFIER,
+];
 
 /// Trait to define behaviour of a message. MSG_TYPE must be a unique identifier for the Message.
 pub trait Message<'a>: Serialize + Deserialize<'a> {
@@ -55,6 +170,424 @@ pub struct RequestClientIDResponse {
     pub id: ClientID,
 }
 
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+/// Channels a chat message can be sent on
+/// * Lobby - Global channel, seen by every connected client
+/// * Game - Channel scoped to the clients of a single game
+/// * Whisper - Direct message to a single client
+/// * System - Server originated announcements. Clients can't send on this channel.
+pub enum ChatChannel {
+    Lobby,
+    Game(GameID),
+    Whisper(ClientID),
+    System,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Chat message sent from a client to the server
+pub struct ChatMessage {
+    pub channel: ChatChannel,
+    pub text: String,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+/// Chat message relayed by the server to the recipients of a channel
+/// * sender - The ClientID of the sender. None for system announcements.
+/// * timestamp - Seconds since the UNIX epoch when the server received the message.
+pub struct ChatBroadcast {
+    pub channel: ChatChannel,
+    pub sender: Option<ClientID>,
+    pub text: String,
+    pub timestamp: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request to create a new game. The client joins the game as a player once it's created.
+pub struct CreateGame;
+
+#[derive(Deserialize, Serialize)]
+/// Request to join a game as a player
+pub struct JoinGame {
+    pub game_id: GameID,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request to attach to a running game as a spectator
+pub struct SpectateGame {
+    pub game_id: GameID,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request to leave the current game, as either a player or a spectator
+pub struct LeaveGame;
+
+#[derive(Deserialize, Serialize)]
+/// Sent to a client once it has been added to a game
+/// * spectating - True if the client is attached as a spectator rather than a player.
+pub struct GameJoined {
+    pub game_id: GameID,
+    pub spectating: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Sent to a client once it has been removed from a game
+pub struct GameLeft {
+    pub game_id: GameID,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request for a listing of every game on the server
+pub struct RequestGameList;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Summary of a single game in a GameList
+pub struct GameSummary {
+    pub game_id: GameID,
+    pub state: GameState,
+    pub players: usize,
+    pub spectators: usize,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Response to the RequestGameList message
+pub struct GameList {
+    pub games: Vec<GameSummary>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// State of a single entity in a GameSnapshot
+/// * player - The ClientID controlling the entity. None if the entity isn't a player.
+pub struct EntitySnapshot {
+    pub id: u32,
+    pub x: f32,
+    pub y: f32,
+    pub player: Option<ClientID>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// State of a game, published to its players and spectators
+pub struct GameSnapshot {
+    pub game_id: GameID,
+    pub tick: u64,
+    pub entities: Vec<EntitySnapshot>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+/// Input from a player, applied to the velocity of their entity
+pub struct PlayerInput {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request to enter the matchmaking queue
+/// * mode - The game mode to be matched for. Only clients queued for the same mode are grouped.
+/// * rating - The skill rating of the client.
+pub struct EnqueueMatchmaking {
+    pub mode: String,
+    pub rating: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request to leave the matchmaking queue
+pub struct LeaveMatchmaking;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Periodic report sent to each client in the matchmaking queue
+/// * position - Position of the client's ticket in the queue, starting at 1.
+/// * queued - Number of tickets in the queue.
+/// * estimated_wait_secs - Estimated seconds until a match. None until a match has been formed.
+/// * rating_range - The rating difference currently accepted for the client.
+pub struct MatchmakingStatus {
+    pub position: usize,
+    pub queued: usize,
+    pub waited_secs: u64,
+    pub estimated_wait_secs: Option<u64>,
+    pub rating_range: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request to create a new party, led by the sender
+pub struct CreateParty;
+
+#[derive(Deserialize, Serialize)]
+/// Request from a party leader to invite another client to their party
+pub struct InviteToParty {
+    pub client_id: ClientID,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Sent to a client who has been invited to a party
+pub struct PartyInvite {
+    pub party_id: PartyID,
+    pub from: ClientID,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Accepts an invite to a party
+pub struct AcceptPartyInvite {
+    pub party_id: PartyID,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Declines an invite to a party
+pub struct DeclinePartyInvite {
+    pub party_id: PartyID,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request to leave the current party
+pub struct LeaveParty;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Sent to every member of a party when its membership changes
+pub struct PartyUpdate {
+    pub party_id: PartyID,
+    pub leader: ClientID,
+    pub members: Vec<ClientID>,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request to register a new peer-hosted game, run by the sender
+pub struct HostGame;
+
+#[derive(Deserialize, Serialize)]
+/// Sent to a client once the server has registered the game it hosts
+pub struct GameHosted {
+    pub game_id: GameID,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request for a listing of every peer-hosted game
+pub struct RequestHostedGameList;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Summary of a single peer-hosted game in a HostedGameList
+pub struct HostedGameSummary {
+    pub game_id: GameID,
+    pub host: ClientID,
+    pub peers: usize,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Response to the RequestHostedGameList message
+pub struct HostedGameList {
+    pub games: Vec<HostedGameSummary>,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request to join a peer-hosted game
+pub struct JoinHostedGame {
+    pub game_id: GameID,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request to leave the current peer-hosted game
+pub struct LeaveHostedGame;
+
+#[derive(Deserialize, Serialize)]
+/// Sent to every client in a peer-hosted game when they join, and whenever the host changes
+/// * host - The client who now runs the game.
+/// * snapshot - The latest snapshot from the previous host, which the new host restores from.
+pub struct HostAssigned {
+    pub game_id: GameID,
+    pub host: ClientID,
+    pub snapshot: Option<GameSnapshot>,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Sent to the host of a peer-hosted game when a peer joins
+pub struct PeerJoined {
+    pub game_id: GameID,
+    pub client_id: ClientID,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Sent to the host of a peer-hosted game when a peer leaves
+pub struct PeerLeft {
+    pub game_id: GameID,
+    pub client_id: ClientID,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request for the server to forward a message to the other clients of a peer-hosted game
+/// * to - The recipient. None sends a host's message to every peer, or a peer's message to the host.
+/// * payload - A complete message, as produced by Message::to_json_string.
+pub struct Relay {
+    pub to: Option<ClientID>,
+    pub payload: Value,
+}
+
+#[derive(Deserialize, Serialize)]
+/// A message forwarded by the server from another client of a peer-hosted game
+pub struct Relayed {
+    pub game_id: GameID,
+    pub from: ClientID,
+    pub payload: Value,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request to create a lockstep game. Every peer simulates the game from the same inputs, so the
+/// game only starts once it is full.
+/// * players - How many players the game waits for before starting.
+/// * rollback - Run the game with rollback, so players don't wait for each other's input.
+pub struct CreateLockstepGame {
+    pub players: u32,
+    #[serde(default)]
+    pub rollback: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Sent to every player of a lockstep game once it is full
+/// * players - Every player, in the order their entities are spawned.
+/// * rollback - True if the game runs with rollback.
+pub struct LockstepStart {
+    pub game_id: GameID,
+    pub players: Vec<ClientID>,
+    #[serde(default)]
+    pub rollback: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+/// A player's input for a single tick of a lockstep game
+pub struct LockstepInput {
+    pub tick: u64,
+    pub input: PlayerInput,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Every player's input for a single tick of a lockstep game, broadcast once all of them arrived
+/// * inputs - Inputs sorted by ClientID, so every peer applies them in the same order.
+/// * left - Players who left before this tick. Their entities are removed before it is simulated.
+pub struct InputBundle {
+    pub game_id: GameID,
+    pub tick: u64,
+    pub inputs: Vec<(ClientID, PlayerInput)>,
+    pub left: Vec<ClientID>,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Checksum of a peer's world after simulating a tick of a lockstep game
+pub struct StateChecksum {
+    pub tick: u64,
+    pub checksum: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Sent to every player of a lockstep game when a peer's world no longer matches the server's
+/// * client_id - The peer who desynced.
+/// * expected - The checksum of the server's world.
+/// * checksum - The checksum reported by the peer.
+pub struct DesyncDetected {
+    pub game_id: GameID,
+    pub tick: u64,
+    pub client_id: ClientID,
+    pub expected: u64,
+    pub checksum: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Another player's input in a rollback game, forwarded as soon as the server receives it
+pub struct RemoteInput {
+    pub game_id: GameID,
+    pub player: ClientID,
+    pub tick: u64,
+    pub input: PlayerInput,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request for a listing of every replay recorded by the server
+pub struct RequestReplayList;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Response to the RequestReplayList message, containing the name of every replay
+pub struct ReplayList {
+    pub replays: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Request to watch a replay. It is streamed to the client like a game being spectated.
+pub struct WatchReplay {
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+/// Controls for the replay a client is watching
+/// * Pause - Stops the replay on its current tick.
+/// * Resume - Continues a paused replay.
+/// * Seek - Moves the replay to a tick.
+/// * Speed - Sets how many times faster than real time the replay plays.
+pub enum ReplayAction {
+    Pause,
+    Resume,
+    Seek(u64),
+    Speed(f32),
+}
+
+#[derive(Deserialize, Serialize)]
+/// Message to control the replay a client is watching
+pub struct ReplayControl {
+    pub action: ReplayAction,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Sent to a client watching a replay whenever it is controlled
+pub struct ReplayStatus {
+    pub tick: u64,
+    pub end_tick: u64,
+    pub paused: bool,
+    pub speed: f32,
+}
+
+#[derive(Deserialize, Serialize)]
+/// Vote to pause the game the client is playing
+pub struct VotePause;
+
+#[derive(Deserialize, Serialize)]
+/// Vote to resume the paused game the client is playing
+pub struct VoteResume;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Sent to everyone in a game when a player votes to pause or resume it
+/// * state - The state being voted for.
+/// * countdown - Seconds until the game changes state, if the vote passed.
+pub struct PauseVoteStatus {
+    pub game_id: GameID,
+    pub client_id: ClientID,
+    pub state: GameState,
+    pub votes: usize,
+    pub needed: usize,
+    pub countdown: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Sent to everyone in a game every second while it counts down to pausing or resuming
+pub struct PauseCountdown {
+    pub game_id: GameID,
+    pub state: GameState,
+    pub seconds: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Sent to everyone in a game whenever its GameState changes
+pub struct GameStateChanged {
+    pub game_id: GameID,
+    pub state: GameState,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+/// Sent by a client to measure its round trip time to the server
+/// * sent_at_us - When the client sent the ping, in microseconds on the client's own clock.
+pub struct Ping {
+    pub sent_at_us: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+/// Sent back to a client for each Ping, echoing when it was sent
+pub struct Pong {
+    pub sent_at_us: u64,
+}
+
 impl Message<'static> for TextMessage {
     const MSG_TYPE: &'static str = TEXT_MESSAGE_IDENTIFIER;
 }
@@ -64,6 +597,167 @@ impl Message<'static> for RequestClientID {
 impl Message<'static> for RequestClientIDResponse {
     const MSG_TYPE: &'static str = REQUEST_CLIENT_ID_RESPONSE_IDENTIFIER;
 }
+impl Message<'static> for ChatMessage {
+    const MSG_TYPE: &'static str = CHAT_MESSAGE_IDENTIFIER;
+}
+impl Message<'static> for ChatBroadcast {
+    const MSG_TYPE: &'static str = CHAT_BROADCAST_IDENTIFIER;
+}
+impl Message<'static> for CreateGame {
+    const MSG_TYPE: &'static str = CREATE_GAME_IDENTIFIER;
+}
+impl Message<'static> for JoinGame {
+    const MSG_TYPE: &'static str = JOIN_GAME_IDENTIFIER;
+}
+impl Message<'static> for SpectateGame {
+    const MSG_TYPE: &'static str = SPECTATE_GAME_IDENTIFIER;
+}
+impl Message<'static> for LeaveGame {
+    const MSG_TYPE: &'static str = LEAVE_GAME_IDENTIFIER;
+}
+impl Message<'static> for GameJoined {
+    const MSG_TYPE: &'static str = GAME_JOINED_IDENTIFIER;
+}
+impl Message<'static> for GameLeft {
+    const MSG_TYPE: &'static str = GAME_LEFT_IDENTIFIER;
+}
+impl Message<'static> for RequestGameList {
+    const MSG_TYPE: &'static str = REQUEST_GAME_LIST_IDENTIFIER;
+}
+impl Message<'static> for GameList {
+    const MSG_TYPE: &'static str = GAME_LIST_IDENTIFIER;
+}
+impl Message<'static> for GameSnapshot {
+    const MSG_TYPE: &'static str = GAME_SNAPSHOT_IDENTIFIER;
+}
+impl Message<'static> for PlayerInput {
+    const MSG_TYPE: &'static str = PLAYER_INPUT_IDENTIFIER;
+}
+impl Message<'static> for EnqueueMatchmaking {
+    const MSG_TYPE: &'static str = ENQUEUE_MATCHMAKING_IDENTIFIER;
+}
+impl Message<'static> for LeaveMatchmaking {
+    const MSG_TYPE: &'static str = LEAVE_MATCHMAKING_IDENTIFIER;
+}
+impl Message<'static> for MatchmakingStatus {
+    const MSG_TYPE: &'static str = MATCHMAKING_STATUS_IDENTIFIER;
+}
+impl Message<'static> for CreateParty {
+    const MSG_TYPE: &'static str = CREATE_PARTY_IDENTIFIER;
+}
+impl Message<'static> for InviteToParty {
+    const MSG_TYPE: &'static str = INVITE_TO_PARTY_IDENTIFIER;
+}
+impl Message<'static> for PartyInvite {
+    const MSG_TYPE: &'static str = PARTY_INVITE_IDENTIFIER;
+}
+impl Message<'static> for AcceptPartyInvite {
+    const MSG_TYPE: &'static str = ACCEPT_PARTY_INVITE_IDENTIFIER;
+}
+impl Message<'static> for DeclinePartyInvite {
+    const MSG_TYPE: &'static str = DECLINE_PARTY_INVITE_IDENTIFIER;
+}
+impl Message<'static> for LeaveParty {
+    const MSG_TYPE: &'static str = LEAVE_PARTY_IDENTIFIER;
+}
+impl Message<'static> for PartyUpdate {
+    const MSG_TYPE: &'static str = PARTY_UPDATE_IDENTIFIER;
+}
+impl Message<'static> for HostGame {
+    const MSG_TYPE: &'static str = HOST_GAME_IDENTIFIER;
+}
+impl Message<'static> for GameHosted {
+    const MSG_TYPE: &'static str = GAME_HOSTED_IDENTIFIER;
+}
+impl Message<'static> for RequestHostedGameList {
+    const MSG_TYPE: &'static str = REQUEST_HOSTED_GAME_LIST_IDENTIFIER;
+}
+impl Message<'static> for HostedGameList {
+    const MSG_TYPE: &'static str = HOSTED_GAME_LIST_IDENTIFIER;
+}
+impl Message<'static> for JoinHostedGame {
+    const MSG_TYPE: &'static str = JOIN_HOSTED_GAME_IDENTIFIER;
+}
+impl Message<'static> for LeaveHostedGame {
+    const MSG_TYPE: &'static str = LEAVE_HOSTED_GAME_IDENTIFIER;
+}
+impl Message<'static> for HostAssigned {
+    const MSG_TYPE: &'static str = HOST_ASSIGNED_IDENTIFIER;
+}
+impl Message<'static> for PeerJoined {
+    const MSG_TYPE: &'static str = PEER_JOINED_IDENTIFIER;
+}
+impl Message<'static> for PeerLeft {
+    const MSG_TYPE: &'static str = PEER_LEFT_IDENTIFIER;
+}
+impl Message<'static> for Relay {
+    const MSG_TYPE: &'static str = RELAY_IDENTIFIER;
+}
+impl Message<'static> for Relayed {
+    const MSG_TYPE: &'static str = RELAYED_IDENTIFIER;
+}
+
+impl Message<'static> for CreateLockstepGame {
+    const MSG_TYPE: &'static str = CREATE_LOCKSTEP_GAME_IDENTIFIER;
+}
+impl Message<'static> for LockstepStart {
+    const MSG_TYPE: &'static str = LOCKSTEP_START_IDENTIFIER;
+}
+impl Message<'static> for LockstepInput {
+    const MSG_TYPE: &'static str = LOCKSTEP_INPUT_IDENTIFIER;
+}
+impl Message<'static> for InputBundle {
+    const MSG_TYPE: &'static str = INPUT_BUNDLE_IDENTIFIER;
+}
+impl Message<'static> for StateChecksum {
+    const MSG_TYPE: &'static str = STATE_CHECKSUM_IDENTIFIER;
+}
+impl Message<'static> for DesyncDetected {
+    const MSG_TYPE: &'static str = DESYNC_DETECTED_IDENTIFIER;
+}
+
+impl Message<'static> for RemoteInput {
+    const MSG_TYPE: &'static str = REMOTE_INPUT_IDENTIFIER;
+}
+
+impl Message<'static> for RequestReplayList {
+    const MSG_TYPE: &'static str = REQUEST_REPLAY_LIST_IDENTIFIER;
+}
+impl Message<'static> for ReplayList {
+    const MSG_TYPE: &'static str = REPLAY_LIST_IDENTIFIER;
+}
+impl Message<'static> for WatchReplay {
+    const MSG_TYPE: &'static str = WATCH_REPLAY_IDENTIFIER;
+}
+impl Message<'static> for ReplayControl {
+    const MSG_TYPE: &'static str = REPLAY_CONTROL_IDENTIFIER;
+}
+impl Message<'static> for ReplayStatus {
+    const MSG_TYPE: &'static str = REPLAY_STATUS_IDENTIFIER;
+}
+
+impl Message<'static> for VotePause {
+    const MSG_TYPE: &'static str = VOTE_PAUSE_IDENTIFIER;
+}
+impl Message<'static> for VoteResume {
+    const MSG_TYPE: &'static str = VOTE_RESUME_IDENTIFIER;
+}
+impl Message<'static> for PauseVoteStatus {
+    const MSG_TYPE: &'static str = PAUSE_VOTE_STATUS_IDENTIFIER;
+}
+impl Message<'static> for PauseCountdown {
+    const MSG_TYPE: &'static str = PAUSE_COUNTDOWN_IDENTIFIER;
+}
+impl Message<'static> for GameStateChanged {
+    const MSG_TYPE: &'static str = GAME_STATE_CHANGED_IDENTIFIER;
+}
+
+impl Message<'static> for Ping {
+    const MSG_TYPE: &'static str = PING_IDENTIFIER;
+}
+impl Message<'static> for Pong {
+    const MSG_TYPE: &'static str = PONG_IDENTIFIER;
+}
 
 impl TextMessage {
     pub fn new<S: Into<String>>(text: S) -> TextMessage {
@@ -71,9 +765,39 @@ impl TextMessage {
     }
 }
 
+impl Relay {
+    /// Wraps a message so it can be relayed through the server.
+    pub fn new<M: Message<'static>>(to: Option<ClientID>, msg: &M) -> Relay {
+        let payload = json!({
+            "msg_type": M::MSG_TYPE.to_owned(),
+            "data": msg,
+        });
+        Relay { to, payload }
+    }
+}
+
+impl ChatMessage {
+    pub fn new<S: Into<String>>(channel: ChatChannel, text: S) -> ChatMessage {
+        ChatMessage {
+            channel,
+            text: text.into(),
+        }
+    }
+}
+
 /// Sends a generic message to a specified stream.
 pub fn send_json<M: Message<'static>>(msg: M, socket: &mut TcpStream) {
+    try_send_json(&msg, socket).expect("Failed to write to socket!");
+}
+
+/// Sends a generic message to a specified stream, returning any error instead of panicking.
+pub fn try_send_json<M: Message<'static>>(msg: &M, socket: &mut TcpStream) -> std::io::Result<()> {
     let json_string = msg.to_json_string();
     let buff = json_string.into_bytes();
-    socket.write_all(&buff).expect("Failed to write to socket!");
+    socket.write_all(&buff)?;
+
+    let metrics = registry();
+    metrics.messages_sent.inc(M::MSG_TYPE);
+    metrics.bytes_sent.add(buff.len() as u64);
+    Ok(())
 }
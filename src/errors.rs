@@ -13,6 +13,76 @@ pub struct InputHandleError;
 #[derive(Debug, Clone)]
 pub struct UnexpectedError;
 
+#[derive(Debug, Clone)]
+pub struct ChatError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct LobbyError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct PartyError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RelayError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct LockstepError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RollbackError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct AdminError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct PauseError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct StateError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionError {
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MessageError {
+    pub reason: String,
+}
+
 impl fmt::Display for ClientDisconnectError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Client {} Disconnected", self.client_id)
@@ -31,6 +101,90 @@ impl fmt::Display for UnexpectedError {
     }
 }
 
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Chat Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for LobbyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Lobby Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for PartyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Party Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for RelayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Relay Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for LockstepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Lockstep Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for RollbackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Rollback Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Admin Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Replay Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for PauseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Pause Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "State Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Config Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Command Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Session Error: {}", self.reason)
+    }
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Message Error: {}", self.reason)
+    }
+}
+
 impl error::Error for ClientDisconnectError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         // Generic error, underlying cause isn't tracked.
@@ -52,5 +206,103 @@ impl error::Error for UnexpectedError {
     }
 }
 
+impl error::Error for ChatError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for LobbyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for PartyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for RelayError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for LockstepError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for RollbackError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for AdminError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for ReplayError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for PauseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for StateError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for CommandError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for SessionError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
+impl error::Error for MessageError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
 pub type ConnectionStatus = std::result::Result<(), ClientDisconnectError>;
 pub type ExpectedSuccess = std::result::Result<(), UnexpectedError>;
@@ -1,27 +1,442 @@
-use crate::game::model::GameModel;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::comms::message::{GameSnapshot, GameSummary, InputBundle, LockstepStart, PlayerInput};
+use crate::errors::{LockstepError, PauseError, StateError};
+use crate::game::fixed::Fixed;
+use crate::game::lockstep::Lockstep;
+use crate::game::model::{GameModel, GameState, SavedModel};
+use crate::game::pause::{PauseRules, PauseVotes, VoteProgress};
+use crate::game::replay::{ReplayEventKind, ReplayRecorder};
+use crate::game::GameID;
+use crate::metrics::registry::registry;
+use crate::server_side::client::ClientID;
+use crate::state::{State, Transition};
 use specs::{RunNow, WorldExt};
+use std::sync::mpsc::Receiver;
+use tracing::warn;
+
+/// Version of the save file format. Bumped whenever SavedGame changes incompatibly.
+pub const SAVE_VERSION: u32 = 1;
+
+/// A game in the form written to disk by GameController::save_to_file.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SavedGame {
+    pub version: u32,
+    pub tick: u64,
+    pub model: SavedModel,
+}
 
+/// Runs the systems of a game.
+/// * model - The GameModel holding the world and its players.
+/// * tick - The number of times the systems have been dispatched.
+/// * spectator_buffer - Snapshots waiting to be published to spectators.
+/// * lockstep - Collects player inputs when the game runs in lockstep mode. None if the server
+///   simulates the game on its own.
+/// * recorder - Records the game for replays. None if the game isn't being recorded.
+/// * pause - The votes of players to pause or resume the game.
+/// * transitions - Transitions of the model which haven't been announced yet.
 pub struct GameController {
     pub model: GameModel,
+    pub tick: u64,
+    pub spectator_buffer: VecDeque<(Instant, GameSnapshot)>,
+    pub lockstep: Option<Lockstep>,
+    pub recorder: Option<ReplayRecorder>,
+    pub pause: PauseVotes,
+    transitions: Receiver<Transition<GameState>>,
+}
+
+impl Default for GameController {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GameController {
     pub fn new() -> GameController {
-        let model = GameModel::new();
+        GameController::from_model(GameModel::new(), 0)
+    }
+
+    fn from_model(mut model: GameModel, tick: u64) -> GameController {
+        let transitions = model.state_machine().subscribe();
+        GameController {
+            model,
+            tick,
+            spectator_buffer: VecDeque::new(),
+            lockstep: None,
+            recorder: None,
+            pause: PauseVotes::new(),
+            transitions,
+        }
+    }
 
-        GameController { model }
+    /// Returns a controller for a lockstep game, which waits in PendingPlayers until `players`
+    /// players have joined.
+    pub fn new_lockstep(players: usize) -> GameController {
+        let mut controller = GameController::new();
+        let lockstep = Lockstep::new(players);
+        controller
+            .model
+            .reset_state(GameState::PendingPlayers(lockstep.pending_players()));
+        controller.lockstep = Some(lockstep);
+        controller
     }
 
+    /// Runs the systems of the game once. Nothing is simulated unless the game is Active.
     pub fn dispatch(&mut self) {
+        if self.model.state() != GameState::Active {
+            return;
+        }
+        let started = Instant::now();
         systems::HelloWorld.run_now(&self.model.world);
         systems::UpdatePos.run_now(&self.model.world);
-        systems::Friction { drag: 0.1 }.run_now(&self.model.world);
+        systems::Friction {
+            drag: Fixed::from_ratio(1, 10).expect("A tenth fits in a Fixed"),
+        }
+        .run_now(&self.model.world);
 
         self.model.world.maintain();
+        self.tick += 1;
+        registry().tick_duration.observe(started.elapsed());
+    }
+
+    /// Rebuilds a controller from a snapshot, continuing from the snapshot's tick.
+    pub fn from_snapshot(snapshot: &GameSnapshot) -> GameController {
+        GameController::from_model(GameModel::from_snapshot(&snapshot.entities), snapshot.tick)
+    }
+
+    /// Returns a controller for a lockstep game whose players run it with rollback.
+    pub fn new_rollback(players: usize) -> GameController {
+        let mut controller = GameController::new_lockstep(players);
+        if let Some(lockstep) = controller.lockstep.as_mut() {
+            lockstep.rollback = true;
+        }
+        controller
+    }
+
+    /// Adds a player to the game. Players of a lockstep game are held back until it is full, and
+    /// then spawned in a fixed order so every peer builds the same world.
+    ///
+    /// # Returns
+    ///
+    /// * The LockstepStart to send to every player if this player filled a lockstep game.
+    /// * LockstepError if the lockstep game already started.
+    pub fn add_player(
+        &mut self,
+        game_id: GameID,
+        player_id: ClientID,
+    ) -> Result<Option<LockstepStart>, LockstepError> {
+        let lockstep = match self.lockstep.as_mut() {
+            Some(lockstep) => lockstep,
+            None => {
+                self.record(ReplayEventKind::Join(player_id.clone()));
+                self.model.add_player(player_id);
+                return Ok(None);
+            }
+        };
+
+        let players = match lockstep.join(&player_id)? {
+            Some(players) => players,
+            None => {
+                let pending = lockstep.pending_players();
+                self.set_state(GameState::PendingPlayers(pending));
+                return Ok(None);
+            }
+        };
+        let rollback = lockstep.rollback;
+        self.start_lockstep(&players);
+        Ok(Some(LockstepStart {
+            game_id,
+            players,
+            rollback,
+        }))
+    }
+
+    /// Removes a player from the game. Returns true if the client was a player.
+    pub fn remove_player(&mut self, player_id: &ClientID) -> bool {
+        self.pause.remove_player(player_id);
+        match self.lockstep.as_mut() {
+            // Entities of a started lockstep game are removed by the next InputBundle.
+            Some(lockstep) if lockstep.is_started() => lockstep.leave(player_id),
+            Some(lockstep) => {
+                let removed = lockstep.leave(player_id);
+                let pending = lockstep.pending_players();
+                self.set_state(GameState::PendingPlayers(pending));
+                removed
+            }
+            None => {
+                self.record(ReplayEventKind::Leave(player_id.clone()));
+                self.model.remove_player(player_id)
+            }
+        }
+    }
+
+    /// Applies a player's input to the game.
+    pub fn apply_input(&mut self, player_id: &ClientID, input: PlayerInput) {
+        self.record(ReplayEventKind::Input(player_id.clone(), input));
+        self.model.apply_input(player_id, input);
+    }
+
+    /// Starts recording the game for replays, from its current state.
+    pub fn start_recording(&mut self, game_id: GameID) {
+        self.recorder = Some(ReplayRecorder::new(game_id, self.save()));
+    }
+
+    fn record(&mut self, event: ReplayEventKind) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(self.tick, event);
+        }
+    }
+
+    /// Spawns the players of a lockstep game in order, and activates it.
+    pub fn start_lockstep(&mut self, players: &[ClientID]) {
+        for player in players {
+            self.record(ReplayEventKind::Join(player.clone()));
+            self.model.add_player(player.clone());
+        }
+        self.set_state(GameState::Active);
+    }
+
+    /// Simulates a single tick of a lockstep game from the inputs of every player.
+    ///
+    /// # Returns
+    ///
+    /// * The checksum of the world after the tick.
+    pub fn step(&mut self, bundle: &InputBundle) -> u64 {
+        for player in bundle.left.iter() {
+            self.record(ReplayEventKind::Leave(player.clone()));
+            self.model.remove_player(player);
+        }
+        for (player, input) in bundle.inputs.iter() {
+            self.apply_input(player, *input);
+        }
+        self.dispatch();
+        self.model.checksum()
+    }
+
+    /// Closes every tick of a lockstep game which has all of its inputs, simulating each one.
+    /// Ticks aren't closed while the game is paused, so its peers wait for it to resume.
+    ///
+    /// # Returns
+    ///
+    /// * The bundles to broadcast to the players, in tick order.
+    pub fn advance_lockstep(&mut self, game_id: GameID) -> Vec<InputBundle> {
+        let mut bundles = Vec::new();
+        if self.model.state() != GameState::Active {
+            return bundles;
+        }
+        while let Some(bundle) = self.lockstep.as_mut().and_then(|l| l.close_tick(game_id)) {
+            let checksum = self.step(&bundle);
+            if let Some(lockstep) = self.lockstep.as_mut() {
+                lockstep.record_checksum(bundle.tick, checksum);
+            }
+            bundles.push(bundle);
+        }
+        bundles
+    }
+
+    /// Records a player's vote to pause or resume the game. The game changes state once the
+    /// countdown of a passed vote is over, which may be straight away.
+    ///
+    /// # Returns
+    ///
+    /// * PauseError if the client isn't a player of the game, or the vote was refused.
+    pub fn vote_state(
+        &mut self,
+        player_id: &ClientID,
+        state: GameState,
+        rules: &PauseRules,
+    ) -> Result<VoteProgress, PauseError> {
+        let is_player = match &self.lockstep {
+            Some(lockstep) => lockstep.players.contains(player_id),
+            None => self.model.players.contains(player_id),
+        };
+        if !is_player {
+            return Err(PauseError {
+                reason: String::from("Only players can vote"),
+            });
+        }
+
+        let now = Instant::now();
+        let progress = self.pause.vote(
+            player_id,
+            state,
+            self.model.state(),
+            self.player_count(),
+            rules,
+            now,
+        )?;
+        self.update_pause(now);
+        Ok(progress)
+    }
+
+    /// Changes the state of the game if a pause or resume countdown is over.
+    ///
+    /// # Returns
+    ///
+    /// * The state the game changed to, if it changed.
+    pub fn update_pause(&mut self, now: Instant) -> Option<GameState> {
+        let state = self.pause.poll(now)?;
+        self.change_state(state).ok()?;
+        Some(state)
+    }
+
+    /// Returns every state the game changed to since the last call, oldest first.
+    pub fn take_transitions(&mut self) -> Vec<GameState> {
+        self.transitions.try_iter().map(|t| t.to).collect()
+    }
+
+    /// Changes state for the game's own bookkeeping, such as lockstep players joining, which
+    /// only makes legal transitions.
+    fn set_state(&mut self, state: GameState) {
+        if let Err(e) = self.change_state(state) {
+            warn!(error = %e, "Game made an illegal transition");
+        }
+    }
+
+    /// Returns the players and spectators of the game, including players waiting for a lockstep
+    /// game to fill.
+    pub fn audience(&self) -> Vec<ClientID> {
+        let mut audience = match &self.lockstep {
+            Some(lockstep) => lockstep.players.clone(),
+            None => self.model.players.iter().cloned().collect(),
+        };
+        audience.extend(self.model.spectators.iter().cloned());
+        audience
+    }
+
+    /// Returns the game in a form which can be written to disk.
+    pub fn save(&self) -> SavedGame {
+        SavedGame {
+            version: SAVE_VERSION,
+            tick: self.tick,
+            model: self.model.save(),
+        }
+    }
+
+    /// Rebuilds a controller from a save, continuing from the saved tick.
+    pub fn restore(saved: &SavedGame) -> GameController {
+        GameController::from_model(GameModel::restore(&saved.model), saved.tick)
+    }
+
+    /// Writes the game to a file as json. Lockstep games can't be saved, since their peers hold
+    /// their own copy of the world.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate multiplayer;
+    /// use multiplayer::game::controller::GameController;
+    ///
+    /// let mut game = GameController::new();
+    /// game.model.add_player(String::from("alice"));
+    /// game.dispatch();
+    ///
+    /// let path = std::env::temp_dir().join("multiplayer_save_example.json");
+    /// game.save_to_file(&path).unwrap();
+    /// let loaded = GameController::load_from_file(&path).unwrap();
+    ///
+    /// assert_eq!(loaded.tick, game.tick);
+    /// assert_eq!(loaded.model.checksum(), game.model.checksum());
+    /// ```
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        if self.lockstep.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Lockstep games can't be saved",
+            ));
+        }
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, &self.save())?;
+        Ok(())
+    }
+
+    /// Reads a game written by save_to_file.
+    ///
+    /// # Returns
+    ///
+    /// * An InvalidData error if the file isn't a save, or was written by another version.
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<GameController> {
+        let reader = BufReader::new(File::open(path)?);
+        let saved: SavedGame = serde_json::from_reader(reader)?;
+        if saved.version != SAVE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Save version {} doesn't match {}",
+                    saved.version, SAVE_VERSION
+                ),
+            ));
+        }
+        Ok(GameController::restore(&saved))
+    }
+
+    /// Returns a snapshot of the current state of the game.
+    pub fn snapshot(&self, game_id: GameID) -> GameSnapshot {
+        GameSnapshot {
+            game_id,
+            tick: self.tick,
+            entities: self.model.entity_snapshots(),
+        }
+    }
+
+    /// Returns a summary of the game for game listings.
+    pub fn summary(&self, game_id: GameID) -> GameSummary {
+        GameSummary {
+            game_id,
+            state: self.model.state(),
+            players: self.player_count(),
+            spectators: self.model.spectators.len(),
+        }
+    }
+
+    /// Returns the number of players, including those waiting for a lockstep game to fill.
+    pub fn player_count(&self) -> usize {
+        match &self.lockstep {
+            Some(lockstep) => lockstep.players.len(),
+            None => self.model.players.len(),
+        }
+    }
+
+    /// Buffers a snapshot for spectators, and returns every buffered snapshot which is at least
+    /// `delay` old. With no delay the snapshot is returned straight away.
+    pub fn delay_for_spectators(
+        &mut self,
+        snapshot: GameSnapshot,
+        delay: Duration,
+    ) -> Vec<GameSnapshot> {
+        let now = Instant::now();
+        self.spectator_buffer.push_back((now, snapshot));
+
+        let mut ready = Vec::new();
+        while let Some((time, _)) = self.spectator_buffer.front() {
+            if now.duration_since(*time) < delay {
+                break;
+            }
+            if let Some((_, snapshot)) = self.spectator_buffer.pop_front() {
+                ready.push(snapshot);
+            }
+        }
+        ready
+    }
+}
+
+impl State for GameController {
+    type StateEnum = GameState;
+    /// Changes the state of the model, and forgets any vote for the new state.
+    fn change_state(&mut self, new_state: GameState) -> Result<(), StateError> {
+        self.model.change_state(new_state)?;
+        self.pause.settle(new_state);
+        Ok(())
     }
 }
 
 pub mod systems {
+    use crate::game::fixed::Fixed;
     use crate::game::model::components;
     use specs::{ReadStorage, System, WriteStorage};
 
@@ -37,7 +452,7 @@ pub mod systems {
             use specs::Join;
 
             for (pos, vel) in (&pos, &vel).join() {
-                println!("entity at {:?} is going {:?} km/h", pos, vel)
+                tracing::trace!(?pos, ?vel, "Entity moving")
             }
         }
     }
@@ -53,14 +468,15 @@ pub mod systems {
         fn run(&mut self, (vel, mut pos): Self::SystemData) {
             use specs::Join;
             for (vel, pos) in (&vel, &mut pos).join() {
-                pos.x += vel.x * 0.05;
-                pos.y += vel.y * 0.05;
+                let step = Fixed::from_ratio(1, 20).expect("A twentieth fits in a Fixed");
+                pos.x += vel.x * step;
+                pos.y += vel.y * step;
             }
         }
     }
 
     pub struct Friction {
-        pub drag: f32,
+        pub drag: Fixed,
     }
 
     impl<'a> System<'a> for Friction {
@@ -72,14 +488,14 @@ pub mod systems {
         fn run(&mut self, (mut vel, drag): Self::SystemData) {
             use specs::Join;
             for (vel, _) in (&mut vel, &drag).join() {
-                match (vel.x.abs() < self.drag, vel.x > 0.0) {
-                    (true, _) => vel.x = 0.0,
+                match (vel.x.abs() < self.drag, vel.x > Fixed::ZERO) {
+                    (true, _) => vel.x = Fixed::ZERO,
                     (false, true) => vel.x -= self.drag,
                     (false, false) => vel.x += self.drag,
                 }
 
-                match (vel.y.abs() < self.drag, vel.y > 0.0) {
-                    (true, _) => vel.y = 0.0,
+                match (vel.y.abs() < self.drag, vel.y > Fixed::ZERO) {
+                    (true, _) => vel.y = Fixed::ZERO,
                     (false, true) => vel.y -= self.drag,
                     (false, false) => vel.y += self.drag,
                 }
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+const FRAC_BITS: u32 = 16;
+const ONE: i32 = 1 << FRAC_BITS;
+
+/// A signed 16.16 fixed-point number. Fixed-point arithmetic gives the same result on every
+/// machine, so lockstep peers advancing a world with the same inputs stay in sync. Arithmetic
+/// wraps on overflow rather than panicking.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::game::fixed::Fixed;
+///
+/// let speed = Fixed::from_int(3);
+/// let step = Fixed::from_ratio(1, 2).unwrap();
+///
+/// assert_eq!(speed * step, Fixed::from_f32(1.5));
+/// assert_eq!(Fixed::from_ratio(1, 0), None);
+/// assert_eq!(Fixed::from_ratio(1 << 20, 1), None);
+/// assert_eq!((speed - Fixed::from_int(5)).abs(), Fixed::from_int(2));
+/// ```
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i32);
+
+impl Fixed {
+    pub const ZERO: Fixed = Fixed(0);
+
+    /// Returns a Fixed from its raw 16.16 representation.
+    pub const fn from_raw(raw: i32) -> Fixed {
+        Fixed(raw)
+    }
+
+    pub const fn from_int(value: i32) -> Fixed {
+        Fixed(value << FRAC_BITS)
+    }
+
+    /// Returns `num / den`, rounded towards zero.
+    ///
+    /// # Returns
+    ///
+    /// * None if `den` is zero, or the ratio is outside the range of a Fixed.
+    pub fn from_ratio(num: i32, den: i32) -> Option<Fixed> {
+        let raw = (i64::from(num) << FRAC_BITS).checked_div(i64::from(den))?;
+        i32::try_from(raw).ok().map(Fixed)
+    }
+
+    /// Converts a float to the nearest Fixed. Only use this at the edges of the simulation, e.g.
+    /// for inputs; the conversion is deterministic, but float arithmetic before it may not be.
+    pub fn from_f32(value: f32) -> Fixed {
+        Fixed((value * ONE as f32).round() as i32)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / ONE as f32
+    }
+
+    /// Returns the raw 16.16 representation.
+    pub fn raw(self) -> i32 {
+        self.0
+    }
+
+    pub fn abs(self) -> Fixed {
+        Fixed(self.0.wrapping_abs())
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+    fn add(self, other: Fixed) -> Fixed {
+        Fixed(self.0.wrapping_add(other.0))
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, other: Fixed) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+    fn sub(self, other: Fixed) -> Fixed {
+        Fixed(self.0.wrapping_sub(other.0))
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, other: Fixed) {
+        *self = *self - other;
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+    fn mul(self, other: Fixed) -> Fixed {
+        Fixed(((i64::from(self.0) * i64::from(other.0)) >> FRAC_BITS) as i32)
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+    fn neg(self) -> Fixed {
+        Fixed(self.0.wrapping_neg())
+    }
+}
+
+impl fmt::Debug for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_f32())
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_f32())
+    }
+}
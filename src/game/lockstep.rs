@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::comms::message::{InputBundle, PlayerInput};
+use crate::errors::LockstepError;
+use crate::game::GameID;
+use crate::server_side::client::ClientID;
+
+/// How many ticks ahead of the next open tick a player may send input for. Rollback peers run
+/// ahead of the server by up to their input delay and rollback window.
+pub const MAX_INPUT_LEAD: u64 = 32;
+/// How many ticks of checksums are kept to compare against peers.
+pub const CHECKSUM_WINDOW: u64 = 64;
+
+/// Collects the inputs of a lockstep game. A tick is closed once every player sent their input
+/// for it, and the bundle of inputs is broadcast so each peer can simulate the tick itself.
+/// * expected_players - How many players the game waits for before starting.
+/// * players - Every player in the game, sorted once the game starts.
+/// * next_tick - The first tick which hasn't been closed yet.
+/// * rollback - True if players run the game with rollback. Their inputs are forwarded to each
+///   other as soon as they arrive, instead of waiting for the tick to close.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::comms::message::PlayerInput;
+/// use multiplayer::game::lockstep::Lockstep;
+///
+/// let alice = String::from("alice");
+/// let bob = String::from("bob");
+///
+/// let mut lockstep = Lockstep::new(2);
+/// lockstep.join(&bob).unwrap();
+/// assert_eq!(lockstep.join(&alice).unwrap(), Some(vec![alice.clone(), bob.clone()]));
+///
+/// lockstep.submit_input(&alice, 0, PlayerInput { x: 1.0, y: 0.0 }).unwrap();
+/// assert!(lockstep.close_tick(0).is_none());
+///
+/// lockstep.submit_input(&bob, 0, PlayerInput::default()).unwrap();
+/// let bundle = lockstep.close_tick(0).unwrap();
+/// assert_eq!(bundle.tick, 0);
+/// assert_eq!(bundle.inputs[0].0, alice);
+/// assert_eq!(lockstep.next_tick, 1);
+/// ```
+pub struct Lockstep {
+    pub expected_players: usize,
+    pub players: Vec<ClientID>,
+    pub next_tick: u64,
+    pub rollback: bool,
+    started: bool,
+    left: Vec<ClientID>,
+    inputs: BTreeMap<u64, HashMap<ClientID, PlayerInput>>,
+    checksums: BTreeMap<u64, u64>,
+}
+
+impl Lockstep {
+    pub fn new(expected_players: usize) -> Lockstep {
+        Lockstep {
+            expected_players,
+            players: Vec::new(),
+            next_tick: 0,
+            rollback: false,
+            started: false,
+            left: Vec::new(),
+            inputs: BTreeMap::new(),
+            checksums: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a player to a game which hasn't started yet.
+    ///
+    /// # Returns
+    ///
+    /// * The players in spawn order if this player filled the game, which starts it.
+    /// * LockstepError if the game already started, or the player already joined.
+    pub fn join(&mut self, player_id: &ClientID) -> Result<Option<Vec<ClientID>>, LockstepError> {
+        if self.started {
+            return Err(LockstepError {
+                reason: String::from("Lockstep game already started"),
+            });
+        }
+        if self.players.contains(player_id) {
+            return Err(LockstepError {
+                reason: format!("{} already joined", player_id),
+            });
+        }
+
+        self.players.push(player_id.clone());
+        if self.players.len() < self.expected_players {
+            return Ok(None);
+        }
+        self.started = true;
+        self.players.sort();
+        Ok(Some(self.players.clone()))
+    }
+
+    /// Removes a player. Once the game has started, the player's entity is removed by the next
+    /// bundle so every peer drops it on the same tick.
+    ///
+    /// # Returns
+    ///
+    /// * True if the client was a player.
+    pub fn leave(&mut self, player_id: &ClientID) -> bool {
+        let before = self.players.len();
+        self.players.retain(|p| p != player_id);
+        if before == self.players.len() {
+            return false;
+        }
+
+        for inputs in self.inputs.values_mut() {
+            inputs.remove(player_id);
+        }
+        if self.started {
+            self.left.push(player_id.clone());
+        }
+        true
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Returns how many more players must join before the game starts.
+    pub fn pending_players(&self) -> u32 {
+        self.expected_players.saturating_sub(self.players.len()) as u32
+    }
+
+    /// Records a player's input for a tick.
+    ///
+    /// # Returns
+    ///
+    /// * LockstepError if the game hasn't started, the client isn't a player, or the tick is
+    ///   already closed or too far ahead.
+    pub fn submit_input(
+        &mut self,
+        player_id: &ClientID,
+        tick: u64,
+        input: PlayerInput,
+    ) -> Result<(), LockstepError> {
+        if !self.started {
+            return Err(LockstepError {
+                reason: String::from("Lockstep game hasn't started"),
+            });
+        }
+        if !self.players.contains(player_id) {
+            return Err(LockstepError {
+                reason: format!("{} isn't a player", player_id),
+            });
+        }
+        if tick < self.next_tick || tick > self.next_tick + MAX_INPUT_LEAD {
+            return Err(LockstepError {
+                reason: format!(
+                    "Input for tick {} outside of ticks {} to {}",
+                    tick,
+                    self.next_tick,
+                    self.next_tick + MAX_INPUT_LEAD
+                ),
+            });
+        }
+
+        self.inputs
+            .entry(tick)
+            .or_default()
+            .insert(player_id.clone(), input);
+        Ok(())
+    }
+
+    /// Closes the next tick if every player sent their input for it.
+    ///
+    /// # Returns
+    ///
+    /// * The bundle of inputs for the tick, or None if the tick is still waiting for inputs.
+    pub fn close_tick(&mut self, game_id: GameID) -> Option<InputBundle> {
+        if !self.started || self.players.is_empty() {
+            return None;
+        }
+        let ready = self
+            .inputs
+            .get(&self.next_tick)
+            .is_some_and(|inputs| self.players.iter().all(|p| inputs.contains_key(p)));
+        if !ready {
+            return None;
+        }
+
+        let mut inputs: Vec<_> = self
+            .inputs
+            .remove(&self.next_tick)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        inputs.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let bundle = InputBundle {
+            game_id,
+            tick: self.next_tick,
+            inputs,
+            left: std::mem::take(&mut self.left),
+        };
+        self.next_tick += 1;
+        Some(bundle)
+    }
+
+    /// Records the checksum of the authoritative world after a tick, forgetting old checksums.
+    pub fn record_checksum(&mut self, tick: u64, checksum: u64) {
+        self.checksums.insert(tick, checksum);
+        if tick >= CHECKSUM_WINDOW {
+            self.checksums = self.checksums.split_off(&(tick - CHECKSUM_WINDOW));
+        }
+    }
+
+    /// Compares a peer's checksum against the authoritative world.
+    ///
+    /// # Returns
+    ///
+    /// * The expected checksum if the peer desynced. Checksums for ticks outside the window are
+    ///   ignored.
+    pub fn verify(&self, tick: u64, checksum: u64) -> Option<u64> {
+        match self.checksums.get(&tick) {
+            Some(expected) if *expected != checksum => Some(*expected),
+            _ => None,
+        }
+    }
+}
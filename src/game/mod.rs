@@ -1,4 +1,9 @@
 pub mod controller;
+pub mod fixed;
+pub mod lockstep;
 pub mod model;
+pub mod pause;
+pub mod replay;
+pub mod rollback;
 
 pub type GameID = u32;
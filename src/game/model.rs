@@ -1,19 +1,66 @@
-use specs::{Builder, World, WorldExt};
+use serde::{Deserialize, Serialize};
+use specs::{Builder, Entity, Join, World, WorldExt};
 use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
 
-use crate::server_side::client::{ClientCollection, ClientID};
-use crate::state::State;
+use crate::comms::message::{EntitySnapshot, PlayerInput};
+use crate::errors::StateError;
+use crate::game::fixed::Fixed;
+use crate::server_side::client::ClientID;
+use crate::state::{State, StateMachine, Transitions};
 
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
 pub enum GameState {
     PendingPlayers(u32),
     Active,
     Paused,
 }
 
+impl Transitions for GameState {
+    fn can_transition_to(&self, next: &GameState) -> bool {
+        matches!(
+            (self, next),
+            (GameState::PendingPlayers(_), GameState::PendingPlayers(_))
+                | (GameState::PendingPlayers(_), GameState::Active)
+                | (GameState::Active, GameState::Paused)
+                | (GameState::Paused, GameState::Active)
+        )
+    }
+}
+
+/// The simulated state of every entity, saved so a world can be rolled back to an earlier frame.
+#[derive(Clone, Debug)]
+pub struct WorldState {
+    entities: Vec<(Entity, components::Position, Option<components::Velocity>)>,
+}
+
+/// Every component of a single entity in a SavedModel. Empty components are left out.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SavedEntity {
+    pub position: Option<components::Position>,
+    pub velocity: Option<components::Velocity>,
+    pub player: Option<components::Player>,
+    pub drag: bool,
+}
+
+/// A GameModel in a form which can be written to disk.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SavedModel {
+    pub state: GameState,
+    pub entities: Vec<SavedEntity>,
+}
+
 pub struct GameModel {
     pub world: World,
-    pub players: ClientCollection,
+    pub players: HashSet<ClientID>,
+    pub spectators: HashSet<ClientID>,
+    state: StateMachine<GameState>,
+}
+
+impl Default for GameModel {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GameModel {
@@ -28,61 +75,357 @@ impl GameModel {
 
         world.maintain();
 
-        let players: HashSet<ClientID> = HashSet::new();
-        let players = Arc::new(Mutex::new(players));
-
-        GameModel { world, players }
+        GameModel {
+            world,
+            players: HashSet::new(),
+            spectators: HashSet::new(),
+            state: StateMachine::new(GameState::Active),
+        }
     }
 
+    /// Adds a player to the game. A player restored from a save keeps their entity.
     pub fn add_player(&mut self, player_id: ClientID) {
+        if self.has_entity(&player_id) {
+            debug!(client_id = %player_id, "Player rejoined their entity");
+            self.players.insert(player_id);
+            return;
+        }
+        self.spawn_player(
+            player_id,
+            components::Position {
+                x: Fixed::ZERO,
+                y: Fixed::ZERO,
+            },
+            components::Velocity {
+                x: Fixed::from_int(1),
+                y: Fixed::from_int(1),
+            },
+        );
+    }
+
+    /// Returns true if a player controls an entity in the world.
+    fn has_entity(&self, player_id: &ClientID) -> bool {
+        let players = self.world.read_storage::<components::Player>();
+        players.join().any(|p| &p.id == player_id)
+    }
+
+    /// Returns every component of every entity, along with the state of the game, so the game can
+    /// be written to disk. Spectators aren't saved.
+    pub fn save(&self) -> SavedModel {
+        let ents = self.world.entities();
+        let positions = self.world.read_storage::<components::Position>();
+        let velocities = self.world.read_storage::<components::Velocity>();
+        let players = self.world.read_storage::<components::Player>();
+        let drags = self.world.read_storage::<components::Drag>();
+
+        let entities = (
+            &ents,
+            positions.maybe(),
+            velocities.maybe(),
+            players.maybe(),
+            drags.maybe(),
+        )
+            .join()
+            .map(|(_, position, velocity, player, drag)| SavedEntity {
+                position: position.cloned(),
+                velocity: velocity.cloned(),
+                player: player.cloned(),
+                drag: drag.is_some(),
+            })
+            .collect();
+
+        SavedModel {
+            state: self.state(),
+            entities,
+        }
+    }
+
+    /// Rebuilds a game from a save. Entities are created in the order they were saved, so entity
+    /// ids may differ from the original world.
+    pub fn restore(saved: &SavedModel) -> GameModel {
+        let mut model = GameModel::new();
+        model.reset_state(saved.state);
+
+        for entity in saved.entities.iter() {
+            let mut builder = model.world.create_entity();
+            if let Some(position) = entity.position {
+                builder = builder.with(position);
+            }
+            if let Some(velocity) = entity.velocity {
+                builder = builder.with(velocity);
+            }
+            if let Some(player) = &entity.player {
+                builder = builder.with(player.clone());
+            }
+            if entity.drag {
+                builder = builder.with(components::Drag);
+            }
+            builder.build();
+
+            if let Some(player) = &entity.player {
+                model.players.insert(player.id.clone());
+            }
+        }
+        model.world.maintain();
+        model
+    }
+
+    /// Rebuilds a game from the entities of a snapshot. Used when authority over a game moves to
+    /// a new host. Velocities aren't part of a snapshot, so restored entities start at rest.
+    pub fn from_snapshot(entities: &[EntitySnapshot]) -> GameModel {
+        let mut model = GameModel::new();
+        for entity in entities {
+            let position = components::Position {
+                x: Fixed::from_f32(entity.x),
+                y: Fixed::from_f32(entity.y),
+            };
+            match &entity.player {
+                Some(player_id) => model.spawn_player(
+                    player_id.clone(),
+                    position,
+                    components::Velocity {
+                        x: Fixed::ZERO,
+                        y: Fixed::ZERO,
+                    },
+                ),
+                None => {
+                    model.world.create_entity().with(position).build();
+                }
+            }
+        }
+        model.world.maintain();
+        model
+    }
+
+    fn spawn_player(
+        &mut self,
+        player_id: ClientID,
+        position: components::Position,
+        velocity: components::Velocity,
+    ) {
         self.world
             .create_entity()
-            .with(components::Position { x: 0.0, y: 0.0 })
-            .with(components::Velocity { x: 1.0, y: 1.0 })
-            .with(components::Player)
+            .with(position)
+            .with(velocity)
+            .with(components::Player {
+                id: player_id.clone(),
+            })
             .with(components::Drag)
             .build();
 
-        let mut players = self.players.lock().unwrap();
-        if players.insert(player_id) {
-            println!("Player added successfully");
+        if self.players.insert(player_id.clone()) {
+            debug!(client_id = %player_id, "Player added");
         } else {
-            println!("Player already in HashSet");
+            warn!(client_id = %player_id, "Player already in HashSet");
+        }
+    }
+
+    /// Removes a player and their entity from the game. Returns true if the player was in the game.
+    pub fn remove_player(&mut self, player_id: &ClientID) -> bool {
+        let entities: Vec<_> = {
+            let ents = self.world.entities();
+            let players = self.world.read_storage::<components::Player>();
+            (&ents, &players)
+                .join()
+                .filter(|(_, p)| &p.id == player_id)
+                .map(|(e, _)| e)
+                .collect()
+        };
+        for entity in entities {
+            if let Err(e) = self.world.delete_entity(entity) {
+                warn!(client_id = %player_id, error = %e, "Failed to delete entity of player");
+            }
         }
+        self.world.maintain();
+
+        self.players.remove(player_id)
+    }
+
+    /// Adds a spectator to the game. Spectators receive snapshots but have no entity.
+    pub fn add_spectator(&mut self, spectator_id: ClientID) {
+        if !self.spectators.insert(spectator_id.clone()) {
+            warn!(client_id = %spectator_id, "Spectator already in HashSet");
+        }
+    }
+
+    /// Removes a spectator from the game. Returns true if the spectator was watching the game.
+    pub fn remove_spectator(&mut self, spectator_id: &ClientID) -> bool {
+        self.spectators.remove(spectator_id)
+    }
+
+    /// Applies a player's input to the velocity of their entity.
+    pub fn apply_input(&mut self, player_id: &ClientID, input: PlayerInput) {
+        let players = self.world.read_storage::<components::Player>();
+        let mut velocities = self.world.write_storage::<components::Velocity>();
+        for (player, vel) in (&players, &mut velocities).join() {
+            if &player.id == player_id {
+                vel.x += Fixed::from_f32(input.x);
+                vel.y += Fixed::from_f32(input.y);
+            }
+        }
+    }
+
+    /// Returns the current state of the game.
+    pub fn state(&self) -> GameState {
+        self.state.state()
+    }
+
+    /// Returns the StateMachine driving the game, to register callbacks or subscribe to its
+    /// transitions.
+    pub fn state_machine(&mut self) -> &mut StateMachine<GameState> {
+        &mut self.state
+    }
+
+    /// Puts the game in a state without a transition, for games being built or restored.
+    pub fn reset_state(&mut self, state: GameState) {
+        self.state.reset(state);
+        *self.world.write_resource::<GameState>() = state;
+    }
+
+    /// Returns the position of every entity in the world.
+    pub fn entity_snapshots(&self) -> Vec<EntitySnapshot> {
+        let ents = self.world.entities();
+        let positions = self.world.read_storage::<components::Position>();
+        let players = self.world.read_storage::<components::Player>();
+        (&ents, &positions, players.maybe())
+            .join()
+            .map(|(e, pos, player)| EntitySnapshot {
+                id: e.id(),
+                x: pos.x.to_f32(),
+                y: pos.y.to_f32(),
+                player: player.map(|p| p.id.clone()),
+            })
+            .collect()
+    }
+
+    /// Saves the position and velocity of every entity.
+    pub fn save_state(&self) -> WorldState {
+        let ents = self.world.entities();
+        let positions = self.world.read_storage::<components::Position>();
+        let velocities = self.world.read_storage::<components::Velocity>();
+        let entities = (&ents, &positions, velocities.maybe())
+            .join()
+            .map(|(e, pos, vel)| (e, *pos, vel.cloned()))
+            .collect();
+        WorldState { entities }
+    }
+
+    /// Restores the position and velocity of every entity from a saved state. Entities created
+    /// after the state was saved are deleted. Entities deleted since can't be brought back, so
+    /// rolling back only works while the set of players is unchanged.
+    pub fn restore_state(&mut self, state: &WorldState) {
+        let created: Vec<_> = {
+            let ents = self.world.entities();
+            let positions = self.world.read_storage::<components::Position>();
+            (&ents, &positions)
+                .join()
+                .map(|(e, _)| e)
+                .filter(|e| !state.entities.iter().any(|(saved, _, _)| saved == e))
+                .collect()
+        };
+        for entity in created {
+            if let Err(e) = self.world.delete_entity(entity) {
+                warn!(error = %e, "Failed to delete entity while restoring");
+            }
+        }
+
+        {
+            let mut positions = self.world.write_storage::<components::Position>();
+            let mut velocities = self.world.write_storage::<components::Velocity>();
+            for (entity, pos, vel) in state.entities.iter() {
+                if !self.world.is_alive(*entity) {
+                    continue;
+                }
+                if let Err(e) = positions.insert(*entity, *pos) {
+                    warn!(error = %e, "Failed to restore position");
+                }
+                match vel {
+                    Some(vel) => {
+                        if let Err(e) = velocities.insert(*entity, *vel) {
+                            warn!(error = %e, "Failed to restore velocity");
+                        }
+                    }
+                    None => {
+                        velocities.remove(*entity);
+                    }
+                }
+            }
+        }
+        self.world.maintain();
+    }
+
+    /// Returns a checksum of the simulated state of the world: every entity's id, position,
+    /// velocity and player. Lockstep peers compare checksums to detect a desync.
+    pub fn checksum(&self) -> u64 {
+        let ents = self.world.entities();
+        let positions = self.world.read_storage::<components::Position>();
+        let velocities = self.world.read_storage::<components::Velocity>();
+        let players = self.world.read_storage::<components::Player>();
+
+        // FNV-1a, which unlike the std hasher is guaranteed to be stable between builds.
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= u64::from(*byte);
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        };
+        for (e, pos, vel, player) in (&ents, &positions, velocities.maybe(), players.maybe()).join()
+        {
+            write(&e.id().to_le_bytes());
+            write(&pos.x.raw().to_le_bytes());
+            write(&pos.y.raw().to_le_bytes());
+            if let Some(vel) = vel {
+                write(&vel.x.raw().to_le_bytes());
+                write(&vel.y.raw().to_le_bytes());
+            }
+            if let Some(player) = player {
+                write(player.id.as_bytes());
+            }
+        }
+        hash
     }
 }
 
 impl State for GameModel {
     type StateEnum = GameState;
-    fn change_state(&mut self, new_state: GameState) {
-        let mut state = self.world.write_resource::<GameState>();
-        *state = new_state;
+    /// Moves the game to a new state. The GameState resource of the world follows it, for
+    /// systems which read it.
+    fn change_state(&mut self, new_state: GameState) -> Result<(), StateError> {
+        self.state.transition(new_state)?;
+        *self.world.write_resource::<GameState>() = new_state;
+        Ok(())
     }
 }
 
 pub mod components {
 
+    use crate::game::fixed::Fixed;
+    use crate::server_side::client::ClientID;
+    use serde::{Deserialize, Serialize};
     use specs::{Component, NullStorage, VecStorage};
 
-    #[derive(Component, Debug)]
+    #[derive(Component, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
     #[storage(VecStorage)]
     pub struct Position {
-        pub x: f32,
-        pub y: f32,
+        pub x: Fixed,
+        pub y: Fixed,
     }
 
-    #[derive(Component, Debug)]
+    #[derive(Component, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
     #[storage(VecStorage)]
     pub struct Velocity {
-        pub x: f32,
-        pub y: f32,
+        pub x: Fixed,
+        pub y: Fixed,
     }
 
-    #[derive(Component, Debug, Default)]
-    #[storage(NullStorage)]
-    pub struct Player;
+    #[derive(Component, Deserialize, Serialize, Clone, Debug)]
+    #[storage(VecStorage)]
+    pub struct Player {
+        pub id: ClientID,
+    }
 
-    #[derive(Component, Default)]
+    #[derive(Component, Deserialize, Serialize, Default)]
     #[storage(NullStorage)]
     pub struct Drag;
 }
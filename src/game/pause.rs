@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::errors::PauseError;
+use crate::game::model::GameState;
+use crate::server_side::client::ClientID;
+
+/// Rules for pausing and resuming a game by vote.
+/// * vote_fraction - The fraction of players who must vote before a vote passes. At least one
+///   vote is always needed, e.g. 0.5 for half of the players, 1.0 for all of them.
+/// * pause_countdown - How long after a pause vote passes the game pauses.
+/// * resume_countdown - How long after a resume vote passes the game resumes, so players can get
+///   ready.
+/// * pauses_per_player - How many times each player may vote to pause. None for no limit.
+#[derive(Clone, Copy, Debug)]
+pub struct PauseRules {
+    pub vote_fraction: f32,
+    pub pause_countdown: Duration,
+    pub resume_countdown: Duration,
+    pub pauses_per_player: Option<u32>,
+}
+
+impl Default for PauseRules {
+    fn default() -> Self {
+        PauseRules {
+            vote_fraction: 0.5,
+            pause_countdown: Duration::from_secs(0),
+            resume_countdown: Duration::from_secs(3),
+            pauses_per_player: Some(3),
+        }
+    }
+}
+
+impl PauseRules {
+    /// Returns how many votes are needed in a game with `players` players.
+    pub fn votes_needed(&self, players: usize) -> usize {
+        let needed = (players as f32 * self.vote_fraction).ceil() as usize;
+        needed.clamp(1, players.max(1))
+    }
+}
+
+/// How a vote stands after a player voted.
+/// * state - The state being voted for.
+/// * votes - Votes cast so far. Once the vote passes this is the number which passed it.
+/// * needed - Votes needed for the vote to pass.
+/// * countdown - How long until the game changes state, if the vote passed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoteProgress {
+    pub state: GameState,
+    pub votes: usize,
+    pub needed: usize,
+    pub countdown: Option<Duration>,
+}
+
+/// Tracks the votes of players to pause or resume a game. A vote which passes starts a
+/// countdown, and the game changes state once `poll` sees that the countdown is over.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::game::model::GameState;
+/// use multiplayer::game::pause::{PauseRules, PauseVotes};
+/// use std::time::{Duration, Instant};
+///
+/// let rules = PauseRules::default();
+/// let mut votes = PauseVotes::new();
+/// let now = Instant::now();
+/// let alice = String::from("alice");
+/// let bob = String::from("bob");
+///
+/// // One of two players is half, so alice pauses the game straight away.
+/// let progress = votes.vote(&alice, GameState::Paused, GameState::Active, 2, &rules, now).unwrap();
+/// assert_eq!(progress.countdown, Some(Duration::from_secs(0)));
+/// assert_eq!(votes.poll(now), Some(GameState::Paused));
+///
+/// // Resuming counts down first.
+/// votes.vote(&bob, GameState::Active, GameState::Paused, 2, &rules, now).unwrap();
+/// assert_eq!(votes.countdown(now), Some((GameState::Active, 3)));
+/// assert_eq!(votes.poll(now), None);
+/// assert_eq!(votes.poll(now + Duration::from_secs(3)), Some(GameState::Active));
+/// ```
+#[derive(Default)]
+pub struct PauseVotes {
+    state: Option<GameState>,
+    votes: HashSet<ClientID>,
+    countdown: Option<(GameState, Instant)>,
+    announced: Option<(GameState, u64)>,
+    pauses: HashMap<ClientID, u32>,
+}
+
+impl PauseVotes {
+    pub fn new() -> PauseVotes {
+        PauseVotes::default()
+    }
+
+    /// Records a player's vote for the game to become Paused or Active. A vote for the other
+    /// state throws away the votes cast so far.
+    ///
+    /// # Arguments
+    ///
+    /// * 'player_id' - The player voting.
+    /// * 'state' - The state voted for.
+    /// * 'current' - The state the game is in.
+    /// * 'players' - The number of players in the game.
+    /// * 'rules' - The rules the vote runs under.
+    /// * 'now' - When the vote was cast.
+    ///
+    /// # Returns
+    ///
+    /// * PauseError if the game is waiting for players or already in that state, a countdown to
+    ///   that state is running, the player already voted, or they have used up their pauses.
+    pub fn vote(
+        &mut self,
+        player_id: &ClientID,
+        state: GameState,
+        current: GameState,
+        players: usize,
+        rules: &PauseRules,
+        now: Instant,
+    ) -> Result<VoteProgress, PauseError> {
+        let countdown = match state {
+            GameState::Paused => rules.pause_countdown,
+            GameState::Active => rules.resume_countdown,
+            GameState::PendingPlayers(_) => {
+                return Err(PauseError {
+                    reason: String::from("Games can only be voted paused or active"),
+                })
+            }
+        };
+        if let GameState::PendingPlayers(_) = current {
+            return Err(PauseError {
+                reason: String::from("The game is waiting for players"),
+            });
+        }
+        if self.countdown.is_some_and(|(s, _)| s == state) {
+            return Err(PauseError {
+                reason: format!("The game is already counting down to {:?}", state),
+            });
+        }
+        if current == state {
+            return Err(PauseError {
+                reason: format!("The game is already {:?}", state),
+            });
+        }
+        let pauses = self.pauses.get(player_id).cloned().unwrap_or(0);
+        if state == GameState::Paused && rules.pauses_per_player.is_some_and(|max| pauses >= max) {
+            return Err(PauseError {
+                reason: String::from("No pauses left"),
+            });
+        }
+
+        if self.state != Some(state) {
+            self.state = Some(state);
+            self.votes.clear();
+        }
+        if !self.votes.insert(player_id.clone()) {
+            return Err(PauseError {
+                reason: format!("Already voted for {:?}", state),
+            });
+        }
+        if state == GameState::Paused {
+            self.pauses.insert(player_id.clone(), pauses + 1);
+        }
+
+        let mut progress = VoteProgress {
+            state,
+            votes: self.votes.len(),
+            needed: rules.votes_needed(players),
+            countdown: None,
+        };
+        if progress.votes >= progress.needed {
+            // A passed vote replaces a countdown to the other state.
+            self.countdown = Some((state, now + countdown));
+            self.state = None;
+            self.votes.clear();
+            progress.countdown = Some(countdown);
+        }
+        Ok(progress)
+    }
+
+    /// Ends the countdown if it is over.
+    ///
+    /// # Returns
+    ///
+    /// * The state the game should change to, if the countdown is over.
+    pub fn poll(&mut self, now: Instant) -> Option<GameState> {
+        match self.countdown {
+            Some((state, at)) if at <= now => {
+                self.countdown = None;
+                Some(state)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the state being counted down to, and the whole seconds left.
+    pub fn countdown(&self, now: Instant) -> Option<(GameState, u64)> {
+        self.countdown
+            .map(|(state, at)| (state, whole_seconds(at.saturating_duration_since(now))))
+    }
+
+    /// Returns the same as `countdown`, but only when the whole seconds left have changed since
+    /// the last call, so a countdown is announced once a second however fast games tick.
+    pub fn countdown_changed(&mut self, now: Instant) -> Option<(GameState, u64)> {
+        let countdown = self.countdown(now);
+        if countdown == self.announced {
+            return None;
+        }
+        self.announced = countdown;
+        countdown
+    }
+
+    /// Forgets the votes and countdown for a state the game has changed to some other way.
+    pub fn settle(&mut self, state: GameState) {
+        if self.state == Some(state) {
+            self.state = None;
+            self.votes.clear();
+        }
+        if self.countdown.is_some_and(|(s, _)| s == state) {
+            self.countdown = None;
+        }
+    }
+
+    /// Withdraws the vote of a player who left the game.
+    pub fn remove_player(&mut self, player_id: &ClientID) {
+        self.votes.remove(player_id);
+    }
+}
+
+/// Rounds a countdown up to whole seconds, so it only reads 0 once it is over.
+pub fn whole_seconds(left: Duration) -> u64 {
+    left.as_secs() + (left.subsec_nanos() > 0) as u64
+}
@@ -0,0 +1,316 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::comms::message::{GameSnapshot, PlayerInput};
+use crate::game::controller::{GameController, SavedGame, SAVE_VERSION};
+use crate::game::model::GameState;
+use crate::game::GameID;
+use crate::server_side::client::ClientID;
+
+/// The slowest a replay can be played, as a multiple of real time.
+pub const MIN_SPEED: f32 = 0.1;
+/// The fastest a replay can be played, as a multiple of real time.
+pub const MAX_SPEED: f32 = 16.0;
+
+/// Something which changed a game, applied before the systems ran on a tick
+/// * Join - A player's entity was added.
+/// * Leave - A player's entity was removed.
+/// * Input - A player's input was applied.
+/// * Checkpoint - Marks how far the game had run when the replay was written. Changes nothing.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum ReplayEventKind {
+    Join(ClientID),
+    Leave(ClientID),
+    Input(ClientID, PlayerInput),
+    Checkpoint,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ReplayEvent {
+    pub tick: u64,
+    pub event: ReplayEventKind,
+}
+
+/// The first line of a replay file: the game as it was when recording started.
+/// * started_at - Seconds since the UNIX epoch when recording started.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ReplayHeader {
+    pub game_id: GameID,
+    pub started_at: u64,
+    pub initial: SavedGame,
+}
+
+/// A recorded game: its initial state and every event since, in order. Replay files are json
+/// lines, the header followed by one event per line, so recordings can be appended to.
+#[derive(Clone, Debug)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub events: Vec<ReplayEvent>,
+}
+
+impl Replay {
+    /// Returns the last tick the replay covers.
+    pub fn end_tick(&self) -> u64 {
+        self.events
+            .iter()
+            .map(|e| e.tick)
+            .max()
+            .unwrap_or(self.header.initial.tick)
+    }
+
+    /// Reads a replay file.
+    ///
+    /// # Returns
+    ///
+    /// * An InvalidData error if the file isn't a replay, or was written by another version.
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<Replay> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header = match lines.next() {
+            Some(line) => serde_json::from_str::<ReplayHeader>(&line?)?,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Replay file is empty",
+                ))
+            }
+        };
+        if header.initial.version != SAVE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Replay version {} doesn't match {}",
+                    header.initial.version, SAVE_VERSION
+                ),
+            ));
+        }
+
+        let mut events = Vec::new();
+        for line in lines {
+            events.push(serde_json::from_str(&line?)?);
+        }
+        Ok(Replay { header, events })
+    }
+
+    /// Writes the replay to a file, replacing anything already there.
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let chunk = ReplayChunk {
+            header: Some(self.header.clone()),
+            events: self.events.clone(),
+        };
+        chunk.write_to_file(path)
+    }
+}
+
+/// Part of a replay which hasn't been written yet. The header is only included in the first chunk.
+pub struct ReplayChunk {
+    pub header: Option<ReplayHeader>,
+    pub events: Vec<ReplayEvent>,
+}
+
+impl ReplayChunk {
+    /// Writes the chunk to a replay file. A chunk with a header starts a new file, any other chunk
+    /// is appended.
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = match self.header {
+            Some(_) => File::create(path)?,
+            None => OpenOptions::new().append(true).open(path)?,
+        };
+        let mut writer = BufWriter::new(file);
+        if let Some(header) = &self.header {
+            serde_json::to_writer(&mut writer, header)?;
+            writer.write_all(b"\n")?;
+        }
+        for event in self.events.iter() {
+            serde_json::to_writer(&mut writer, event)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    }
+}
+
+/// Records the events of a game as it runs. Events are held until they are drained into a chunk
+/// and written to disk.
+pub struct ReplayRecorder {
+    name: String,
+    header: Option<ReplayHeader>,
+    pending: Vec<ReplayEvent>,
+}
+
+impl ReplayRecorder {
+    pub fn new(game_id: GameID, initial: SavedGame) -> ReplayRecorder {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        ReplayRecorder {
+            name: format!("replay_{}_{}", game_id, started_at),
+            header: Some(ReplayHeader {
+                game_id,
+                started_at,
+                initial,
+            }),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns the name of the replay file this recorder writes to, without its extension.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn record(&mut self, tick: u64, event: ReplayEventKind) {
+        self.pending.push(ReplayEvent { tick, event });
+    }
+
+    /// Takes every event recorded since the last drain, marking that the game has run until `tick`.
+    pub fn drain(&mut self, tick: u64) -> ReplayChunk {
+        self.record(tick, ReplayEventKind::Checkpoint);
+        ReplayChunk {
+            header: self.header.take(),
+            events: std::mem::take(&mut self.pending),
+        }
+    }
+}
+
+/// Plays a replay back by feeding its events through a GameController.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::comms::message::PlayerInput;
+/// use multiplayer::game::controller::GameController;
+/// use multiplayer::game::replay::{Replay, ReplayPlayer};
+///
+/// let alice = String::from("alice");
+/// let mut game = GameController::new();
+/// game.start_recording(0);
+/// game.add_player(0, alice.clone()).unwrap();
+/// for _ in 0..5 {
+///     game.apply_input(&alice, PlayerInput { x: 1.0, y: 0.0 });
+///     game.dispatch();
+/// }
+///
+/// let chunk = game.recorder.as_mut().unwrap().drain(game.tick);
+/// let replay = Replay { header: chunk.header.unwrap(), events: chunk.events };
+///
+/// let mut player = ReplayPlayer::new(replay);
+/// player.seek(5);
+/// assert_eq!(player.controller.model.checksum(), game.model.checksum());
+///
+/// player.seek(2);
+/// assert_eq!(player.tick(), 2);
+/// ```
+pub struct ReplayPlayer {
+    pub replay: Replay,
+    pub controller: GameController,
+    pub paused: bool,
+    pub speed: f32,
+    next_event: usize,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> ReplayPlayer {
+        let controller = initial_controller(&replay);
+        ReplayPlayer {
+            replay,
+            controller,
+            paused: false,
+            speed: 1.0,
+            next_event: 0,
+        }
+    }
+
+    /// Returns the tick the replay is at.
+    pub fn tick(&self) -> u64 {
+        self.controller.tick
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.tick() >= self.replay.end_tick()
+    }
+
+    /// Applies the events of the current tick and runs the systems once.
+    ///
+    /// # Returns
+    ///
+    /// * False if the replay has already finished.
+    pub fn step(&mut self) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+
+        let tick = self.controller.tick;
+        while let Some(event) = self.replay.events.get(self.next_event) {
+            if event.tick > tick {
+                break;
+            }
+            match &event.event {
+                ReplayEventKind::Join(player) => self.controller.model.add_player(player.clone()),
+                ReplayEventKind::Leave(player) => {
+                    self.controller.model.remove_player(player);
+                }
+                ReplayEventKind::Input(player, input) => {
+                    self.controller.model.apply_input(player, *input)
+                }
+                ReplayEventKind::Checkpoint => (),
+            }
+            self.next_event += 1;
+        }
+        self.controller.dispatch();
+        true
+    }
+
+    /// Moves the replay to a tick. Seeking backwards plays the replay again from the start.
+    pub fn seek(&mut self, tick: u64) {
+        if tick < self.tick() {
+            self.controller = initial_controller(&self.replay);
+            self.next_event = 0;
+        }
+        while self.tick() < tick && self.step() {}
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Sets how many times faster than real time the replay plays, clamped between MIN_SPEED and
+    /// MAX_SPEED. Non-positive and non-finite speeds are ignored.
+    pub fn set_speed(&mut self, speed: f32) {
+        if speed.is_finite() && speed > 0.0 {
+            self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        }
+    }
+
+    /// Returns how long to wait between ticks, given how long a tick lasted in the live game.
+    pub fn tick_interval(&self, live_interval: Duration) -> Duration {
+        interval_at(self.speed, live_interval)
+    }
+
+    /// Returns a snapshot of the replay at its current tick.
+    pub fn snapshot(&self) -> GameSnapshot {
+        self.controller.snapshot(self.replay.header.game_id)
+    }
+}
+
+/// Returns how long to wait between ticks of a replay played at `speed`, given how long a tick
+/// lasted in the live game. Saturates rather than overflowing.
+pub fn interval_at(speed: f32, live_interval: Duration) -> Duration {
+    Duration::try_from_secs_f64(live_interval.as_secs_f64() / f64::from(speed))
+        .unwrap_or(Duration::MAX)
+}
+
+/// Rebuilds the game a replay starts from. Only simulated ticks are recorded, so the game is
+/// played back as Active whatever state it was recorded in.
+fn initial_controller(replay: &Replay) -> GameController {
+    let mut controller = GameController::restore(&replay.header.initial);
+    controller.model.reset_state(GameState::Active);
+    controller
+}
@@ -0,0 +1,343 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::comms::message::PlayerInput;
+use crate::errors::RollbackError;
+use crate::game::controller::GameController;
+use crate::game::model::WorldState;
+use crate::server_side::client::ClientID;
+
+/// Describes how a RollbackSession trades latency against rollbacks
+/// * input_delay - How many frames local input is held back before it is simulated. Higher delays
+///   give remote input more time to arrive, so fewer frames are rolled back.
+/// * max_rollback_frames - How many frames the session may predict ahead of the last confirmed
+///   frame. The session stalls instead of running further ahead.
+///
+/// Together the delay and window must stay below lockstep::MAX_INPUT_LEAD, or the server will
+/// refuse input which is too far ahead.
+#[derive(Clone, Copy, Debug)]
+pub struct RollbackConfig {
+    pub input_delay: u64,
+    pub max_rollback_frames: u64,
+}
+
+impl Default for RollbackConfig {
+    fn default() -> Self {
+        RollbackConfig {
+            input_delay: 2,
+            max_rollback_frames: 8,
+        }
+    }
+}
+
+/// Counters describing how often a RollbackSession had to predict and roll back
+/// * frames - Frames simulated for the first time.
+/// * predictions - Remote inputs which were predicted because they hadn't arrived yet.
+/// * mispredictions - Predicted inputs which turned out to be wrong.
+/// * rollbacks - How many times the session rolled back.
+/// * frames_resimulated - Frames simulated again after a rollback.
+/// * max_rollback - The most frames rolled back at once.
+/// * stalls - Frames skipped because remote input was too far behind.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RollbackStats {
+    pub frames: u64,
+    pub predictions: u64,
+    pub mispredictions: u64,
+    pub rollbacks: u64,
+    pub frames_resimulated: u64,
+    pub max_rollback: u64,
+    pub stalls: u64,
+}
+
+/// Runs a game GGPO-style. Local input is simulated straight away, remote input is predicted by
+/// repeating the last input received, and when a late input doesn't match its prediction the
+/// world is restored to that frame and simulated again.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::comms::message::PlayerInput;
+/// use multiplayer::game::controller::GameController;
+/// use multiplayer::game::rollback::{RollbackConfig, RollbackSession};
+///
+/// let alice = String::from("alice");
+/// let bob = String::from("bob");
+/// let players = vec![alice.clone(), bob.clone()];
+///
+/// let mut controller = GameController::new();
+/// controller.start_lockstep(&players);
+/// let config = RollbackConfig { input_delay: 0, max_rollback_frames: 8 };
+/// let mut session = RollbackSession::new(controller, alice, players, config);
+///
+/// // Bob's input for frame 0 hasn't arrived, so it is predicted.
+/// session.add_local_input(PlayerInput::default());
+/// session.advance().unwrap();
+///
+/// // It arrives late and differs from the prediction, so frame 0 is simulated again.
+/// session.add_remote_input(&bob, 0, PlayerInput { x: 1.0, y: 0.0 }).unwrap();
+/// session.add_local_input(PlayerInput::default());
+/// session.advance().unwrap();
+///
+/// assert_eq!(session.stats.rollbacks, 1);
+/// assert_eq!(session.stats.frames_resimulated, 1);
+/// ```
+pub struct RollbackSession {
+    pub config: RollbackConfig,
+    pub controller: GameController,
+    pub stats: RollbackStats,
+    local: ClientID,
+    players: Vec<ClientID>,
+    /// The next frame to simulate.
+    frame: u64,
+    /// The next frame local input will be scheduled for.
+    local_next: u64,
+    last_local: PlayerInput,
+    /// Inputs known to be correct, by player and frame.
+    inputs: HashMap<ClientID, BTreeMap<u64, PlayerInput>>,
+    /// How many frames of each player's input have been received without gaps.
+    confirmed: HashMap<ClientID, u64>,
+    /// The frame each departed player stopped sending input from.
+    departed: HashMap<ClientID, u64>,
+    /// The inputs each unconfirmed frame was simulated with, to spot mispredictions.
+    used: BTreeMap<u64, Vec<(ClientID, PlayerInput)>>,
+    /// The world before each unconfirmed frame was simulated.
+    saved: BTreeMap<u64, WorldState>,
+    rollback_to: Option<u64>,
+}
+
+impl RollbackSession {
+    /// Returns a new RollbackSession. The first call to add_local_input also schedules empty
+    /// input for the first `input_delay` frames, so the session can start simulating straight away.
+    pub fn new(
+        controller: GameController,
+        local: ClientID,
+        mut players: Vec<ClientID>,
+        config: RollbackConfig,
+    ) -> RollbackSession {
+        players.sort();
+        let inputs = players
+            .iter()
+            .map(|p| (p.clone(), BTreeMap::new()))
+            .collect();
+        let confirmed = players.iter().map(|p| (p.clone(), 0)).collect();
+
+        RollbackSession {
+            config,
+            controller,
+            stats: RollbackStats::default(),
+            local,
+            players,
+            frame: 0,
+            local_next: 0,
+            last_local: PlayerInput::default(),
+            inputs,
+            confirmed,
+            departed: HashMap::new(),
+            used: BTreeMap::new(),
+            saved: BTreeMap::new(),
+            rollback_to: None,
+        }
+    }
+
+    /// Returns the next frame to simulate.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Returns the first frame which is still missing input from a remote player.
+    pub fn confirmed_frame(&self) -> u64 {
+        self.players
+            .iter()
+            .filter(|p| !self.departed.contains_key(*p))
+            .map(|p| self.confirmed.get(p).cloned().unwrap_or(0))
+            .min()
+            .unwrap_or(self.frame)
+    }
+
+    /// Returns true if remote input is too far behind to keep predicting.
+    pub fn is_stalled(&self) -> bool {
+        self.frame >= self.confirmed_frame() + self.config.max_rollback_frames
+    }
+
+    /// Changes the input delay. Raising the delay repeats the last local input to fill the gap,
+    /// lowering it takes effect gradually as frames are simulated.
+    pub fn set_input_delay(&mut self, input_delay: u64) {
+        self.config.input_delay = input_delay;
+    }
+
+    /// Schedules local input for the frame `input_delay` frames from now. Call once per frame.
+    ///
+    /// # Returns
+    ///
+    /// * Every frame and input scheduled, which must be sent to the other players.
+    pub fn add_local_input(&mut self, input: PlayerInput) -> Vec<(u64, PlayerInput)> {
+        let target = (self.frame + self.config.input_delay).max(self.local_next);
+        let mut scheduled = Vec::new();
+        for frame in self.local_next..=target {
+            let input = if frame == target {
+                input
+            } else {
+                self.last_local
+            };
+            self.confirm(&self.local.clone(), frame, input);
+            scheduled.push((frame, input));
+        }
+        self.local_next = target + 1;
+        self.last_local = input;
+        scheduled
+    }
+
+    /// Records a remote player's input. If the frame was already simulated with a different
+    /// prediction, the next advance rolls back to it.
+    ///
+    /// # Returns
+    ///
+    /// * RollbackError if the client isn't a remote player, or the input is older than the
+    ///   rollback window. Inputs which were already received are ignored.
+    pub fn add_remote_input(
+        &mut self,
+        player: &ClientID,
+        frame: u64,
+        input: PlayerInput,
+    ) -> Result<(), RollbackError> {
+        if player == &self.local || !self.players.contains(player) {
+            return Err(RollbackError {
+                reason: format!("{} isn't a remote player", player),
+            });
+        }
+        if self
+            .inputs
+            .get(player)
+            .is_some_and(|i| i.contains_key(&frame))
+        {
+            return Ok(());
+        }
+        if frame < self.frame && !self.saved.contains_key(&frame) {
+            return Err(RollbackError {
+                reason: format!("Input for frame {} arrived after it was confirmed", frame),
+            });
+        }
+
+        self.confirm(player, frame, input);
+        let used = self
+            .used
+            .get(&frame)
+            .and_then(|inputs| inputs.iter().find(|(p, _)| p == player));
+        if let Some((_, predicted)) = used {
+            if *predicted != input {
+                self.stats.mispredictions += 1;
+                self.schedule_rollback(frame);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops waiting for a player who left. Their input is ignored from `frame` onwards, on every
+    /// peer alike.
+    pub fn remove_player(&mut self, player: &ClientID, frame: u64) {
+        self.departed.insert(player.clone(), frame);
+        if frame < self.frame {
+            self.schedule_rollback(frame);
+        }
+    }
+
+    /// Rolls back if a misprediction was found, then simulates the next frame.
+    ///
+    /// # Returns
+    ///
+    /// * The frame which was simulated.
+    /// * RollbackError if remote input is too far behind to keep predicting. Nothing is simulated,
+    ///   the caller should try again next frame.
+    pub fn advance(&mut self) -> Result<u64, RollbackError> {
+        if let Some(frame) = self.rollback_to.take() {
+            self.rollback(frame);
+        }
+
+        if self.is_stalled() {
+            self.stats.stalls += 1;
+            return Err(RollbackError {
+                reason: format!(
+                    "Frame {} is too far ahead of confirmed frame {}",
+                    self.frame,
+                    self.confirmed_frame()
+                ),
+            });
+        }
+
+        let frame = self.frame;
+        self.simulate(frame);
+        self.frame += 1;
+        self.stats.frames += 1;
+
+        // Confirmed frames can never be rolled back to.
+        let confirmed = self.confirmed_frame();
+        self.saved = self.saved.split_off(&confirmed);
+        self.used = self.used.split_off(&confirmed);
+        Ok(frame)
+    }
+
+    fn confirm(&mut self, player: &ClientID, frame: u64, input: PlayerInput) {
+        let inputs = self.inputs.entry(player.clone()).or_default();
+        inputs.insert(frame, input);
+        let confirmed = self.confirmed.entry(player.clone()).or_insert(0);
+        while inputs.contains_key(confirmed) {
+            *confirmed += 1;
+        }
+    }
+
+    fn schedule_rollback(&mut self, frame: u64) {
+        if frame < self.frame {
+            let earliest = self.rollback_to.map_or(frame, |f| f.min(frame));
+            self.rollback_to = Some(earliest);
+        }
+    }
+
+    /// Restores the world from before `frame`, and simulates every frame since again.
+    fn rollback(&mut self, frame: u64) {
+        let state = match self.saved.get(&frame) {
+            Some(state) => state.clone(),
+            None => return,
+        };
+        self.controller.model.restore_state(&state);
+        self.controller.tick = frame;
+
+        let frames = self.frame - frame;
+        self.stats.rollbacks += 1;
+        self.stats.frames_resimulated += frames;
+        self.stats.max_rollback = self.stats.max_rollback.max(frames);
+        for f in frame..self.frame {
+            self.simulate(f);
+        }
+    }
+
+    /// Simulates a single frame, predicting any remote input which hasn't arrived yet.
+    fn simulate(&mut self, frame: u64) {
+        self.saved.insert(frame, self.controller.model.save_state());
+
+        let mut used = Vec::with_capacity(self.players.len());
+        for player in self.players.iter() {
+            if self.departed.get(player).is_some_and(|f| frame >= *f) {
+                continue;
+            }
+            let inputs = &self.inputs[player];
+            let input = match inputs.get(&frame) {
+                Some(input) => *input,
+                None => {
+                    self.stats.predictions += 1;
+                    inputs
+                        .range(..frame)
+                        .next_back()
+                        .map(|(_, input)| *input)
+                        .unwrap_or_default()
+                }
+            };
+            used.push((player.clone(), input));
+        }
+
+        for (player, input) in used.iter() {
+            self.controller.model.apply_input(player, *input);
+        }
+        self.controller.dispatch();
+        self.used.insert(frame, used);
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::Shutdown;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use crate::comms::handler::TryClone;
+use crate::comms::message::{self, PlayerInput};
+use crate::game::GameID;
+use crate::host_side::host_client::HostClient;
+use crate::host_side::host_server;
+use crate::server_side::client::ClientID;
+use crate::threading::dispatcher::Dispatcher;
+
+/// How far a random input moves a bot, in world units per tick.
+const SPEED: f32 = 1.0;
+
+/// How a bot gets into a game once it is logged in.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JoinPlan {
+    /// Stays in the lobby.
+    Lobby,
+    /// Creates a game of its own.
+    Create,
+    /// Joins a game which already exists.
+    Join(GameID),
+    /// Queues for matchmaking in a game mode.
+    Matchmake(String),
+}
+
+/// What a bot sends once it is in a game.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputPlan {
+    /// Sends nothing.
+    Idle,
+    /// Moves in a random direction, or stops, every input.
+    Random,
+    /// Sends these inputs in order, starting again from the first after the last.
+    Script(Vec<PlayerInput>),
+}
+
+/// Describes how a bot behaves
+/// * join - How the bot gets into a game.
+/// * inputs - What the bot sends once in a game.
+/// * input_rate - Inputs sent a second. Each gap is jittered by up to half, like a person's.
+/// * ping_interval - How often the round trip time is measured.
+/// * rating - The rating queued with when matchmaking.
+#[derive(Clone, Debug)]
+pub struct BotConfig {
+    pub join: JoinPlan,
+    pub inputs: InputPlan,
+    pub input_rate: f64,
+    pub ping_interval: Duration,
+    pub rating: u32,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        BotConfig {
+            join: JoinPlan::Matchmake(String::from("default")),
+            inputs: InputPlan::Random,
+            input_rate: 5.0,
+            ping_interval: Duration::from_secs(1),
+            rating: 1000,
+        }
+    }
+}
+
+/// A scripted player. Bots log in as soon as the server asks, join a game once the server has
+/// answered a ping, then send inputs and pings as they are stepped.
+pub struct Bot {
+    pub client: HostClient,
+    config: BotConfig,
+    joined: bool,
+    next_input: Instant,
+    next_ping: Instant,
+    step: usize,
+    inputs_sent: u64,
+    rng: u64,
+}
+
+impl Bot {
+    /// Connects a bot to a server and starts listening to it on the dispatcher. Every round trip
+    /// time measured is recorded, and nothing is printed.
+    pub fn connect(
+        addr: &str,
+        id: ClientID,
+        config: BotConfig,
+        dispatch: Dispatcher,
+    ) -> io::Result<Bot> {
+        let client = HostClient::connect(addr, dispatch)?;
+        // A seed which differs between bots and runs, so they don't all move in step.
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        Instant::now().hash(&mut hasher);
+
+        *client.id.lock().unwrap() = Some(id);
+        *client.ping_samples.lock().unwrap() = Some(Vec::new());
+        client.print_updates.store(false, Ordering::Relaxed);
+        let listener = client.try_clone()?;
+        client
+            .dispatch
+            .execute(move || host_server::listen(listener));
+
+        let now = Instant::now();
+        Ok(Bot {
+            client,
+            config,
+            joined: false,
+            next_input: now,
+            next_ping: now,
+            step: 0,
+            inputs_sent: 0,
+            rng: hasher.finish() | 1,
+        })
+    }
+
+    /// Sends whatever is due. Should be called far more often than inputs are sent.
+    ///
+    /// # Returns
+    ///
+    /// * An error if the connection to the server is lost.
+    pub fn step(&mut self, now: Instant) -> io::Result<()> {
+        // The server drops clients which send anything before their id.
+        if !self.client.logged_in.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        if now >= self.next_ping {
+            self.client.send_ping()?;
+            self.next_ping = now + self.config.ping_interval;
+        }
+        // A pong is only sent to clients which are logged in, so the first one means it's safe
+        // to ask for a game.
+        if !self.joined && self.logged_in() {
+            self.join()?;
+            self.joined = true;
+        }
+        if self.in_game() && now >= self.next_input {
+            if let Some(input) = self.next_input() {
+                self.client.send_input(input)?;
+                self.inputs_sent += 1;
+            }
+            self.next_input = now + self.input_gap();
+        }
+        Ok(())
+    }
+
+    fn join(&self) -> io::Result<()> {
+        match &self.config.join {
+            JoinPlan::Lobby => Ok(()),
+            JoinPlan::Create => self.client.send(&message::CreateGame),
+            JoinPlan::Join(game_id) => self.client.send(&message::JoinGame { game_id: *game_id }),
+            JoinPlan::Matchmake(mode) => self.client.send(&message::EnqueueMatchmaking {
+                mode: mode.clone(),
+                rating: self.config.rating,
+            }),
+        }
+    }
+
+    fn next_input(&mut self) -> Option<PlayerInput> {
+        let input = match &self.config.inputs {
+            InputPlan::Idle => return None,
+            InputPlan::Random => {
+                let (x, y) = match self.random() % 5 {
+                    0 => (0.0, SPEED),
+                    1 => (0.0, -SPEED),
+                    2 => (-SPEED, 0.0),
+                    3 => (SPEED, 0.0),
+                    _ => (0.0, 0.0),
+                };
+                PlayerInput { x, y }
+            }
+            InputPlan::Script(inputs) => *inputs.get(self.step % inputs.len().max(1))?,
+        };
+        self.step += 1;
+        Some(input)
+    }
+
+    /// The time until the next input, between half and one and a half times the average.
+    fn input_gap(&mut self) -> Duration {
+        let rate = self.config.input_rate.max(f64::MIN_POSITIVE);
+        let jitter = 0.5 + (self.random() % 1000) as f64 / 1000.0;
+        Duration::from_secs_f64((jitter / rate).min(3600.0))
+    }
+
+    /// xorshift64, plenty for choosing directions.
+    fn random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    /// Whether the server has answered a ping, which it only does once logged in.
+    pub fn logged_in(&self) -> bool {
+        self.client.ping.lock().unwrap().is_some()
+    }
+
+    /// Whether the bot is playing in a game.
+    pub fn in_game(&self) -> bool {
+        self.client.game.lock().unwrap().is_some()
+    }
+
+    pub fn inputs_sent(&self) -> u64 {
+        self.inputs_sent
+    }
+
+    /// Takes the round trip times measured since the last call.
+    pub fn take_latencies(&self) -> Vec<Duration> {
+        self.client
+            .ping_samples
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Closes the connection, which also ends the listener.
+    pub fn disconnect(&self) {
+        // The socket may already be closed by the server.
+        let _ = self.client.socket.shutdown(Shutdown::Both);
+    }
+}
+
+/// Percentiles of a set of round trip times.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Latencies {
+    pub count: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Latencies {
+    /// Summarises round trip times, using the nearest rank for each percentile.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate multiplayer;
+    /// use multiplayer::host_side::bot::Latencies;
+    /// use std::time::Duration;
+    ///
+    /// let samples = (1..=100).map(Duration::from_millis).collect();
+    /// let latencies = Latencies::from_samples(samples);
+    ///
+    /// assert_eq!(latencies.count, 100);
+    /// assert_eq!(latencies.p50, Duration::from_millis(50));
+    /// assert_eq!(latencies.p99, Duration::from_millis(99));
+    /// assert_eq!(latencies.max, Duration::from_millis(100));
+    /// ```
+    pub fn from_samples(mut samples: Vec<Duration>) -> Latencies {
+        if samples.is_empty() {
+            return Latencies::default();
+        }
+        samples.sort();
+        let rank = |p: f64| {
+            let index = ((p / 100.0) * samples.len() as f64).ceil() as usize;
+            samples[index.clamp(1, samples.len()) - 1]
+        };
+        Latencies {
+            count: samples.len(),
+            p50: rank(50.0),
+            p90: rank(90.0),
+            p99: rank(99.0),
+            max: samples[samples.len() - 1],
+        }
+    }
+}
+
+impl fmt::Display for Latencies {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "n={} p50={:.2}ms p90={:.2}ms p99={:.2}ms max={:.2}ms",
+            self.count,
+            ms(self.p50),
+            ms(self.p90),
+            ms(self.p99),
+            ms(self.max)
+        )
+    }
+}
@@ -0,0 +1,30 @@
+use std::sync::{Mutex, OnceLock};
+
+type Printer = Box<dyn FnMut(String) + Send>;
+
+fn printer() -> &'static Mutex<Option<Printer>> {
+    static PRINTER: OnceLock<Mutex<Option<Printer>>> = OnceLock::new();
+    PRINTER.get_or_init(|| Mutex::new(None))
+}
+
+/// Sends every line the host client prints to `print` instead of stdout, e.g. to draw it above
+/// a prompt which is being read.
+pub fn set_printer<F>(print: F)
+where
+    F: FnMut(String) + Send + 'static,
+{
+    *printer().lock().unwrap() = Some(Box::new(print));
+}
+
+/// Goes back to printing lines to stdout.
+pub fn clear_printer() {
+    *printer().lock().unwrap() = None;
+}
+
+/// Prints a line of host client output.
+pub fn print_line<S: Into<String>>(line: S) {
+    match printer().lock().unwrap().as_mut() {
+        Some(print) => print(line.into()),
+        None => println!("{}", line.into()),
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::Mutex;
+
+use crate::comms::message::{ChatBroadcast, GameJoined, GameLeft, GameList, GameSnapshot};
+
+type Callback<T> = Box<dyn FnMut(&T) + Send>;
+
+/// Every callback registered for one kind of event, run in the order they were added.
+pub struct Callbacks<T> {
+    callbacks: Mutex<Vec<Callback<T>>>,
+}
+
+impl<T> Default for Callbacks<T> {
+    fn default() -> Self {
+        Callbacks {
+            callbacks: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<T> Callbacks<T> {
+    /// Registers a callback. Callbacks mustn't register callbacks for the same event, which
+    /// would deadlock.
+    pub fn add<F>(&self, callback: F)
+    where
+        F: FnMut(&T) + Send + 'static,
+    {
+        self.callbacks.lock().unwrap().push(Box::new(callback));
+    }
+
+    /// Runs every callback with an event.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate multiplayer;
+    /// use multiplayer::host_side::events::Callbacks;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let seen = Arc::new(Mutex::new(Vec::new()));
+    /// let callbacks = Callbacks::default();
+    /// let sink = Arc::clone(&seen);
+    /// callbacks.add(move |n: &u32| sink.lock().unwrap().push(*n));
+    ///
+    /// callbacks.emit(&1);
+    /// callbacks.emit(&2);
+    /// assert_eq!(*seen.lock().unwrap(), vec![1, 2]);
+    /// ```
+    pub fn emit(&self, event: &T) {
+        for callback in self.callbacks.lock().unwrap().iter_mut() {
+            callback(event);
+        }
+    }
+}
+
+/// The events a HostClient reports as it handles messages from the server. Callbacks run on the
+/// client's thread pool, so should return quickly.
+/// * snapshot - A GameSnapshot of the game the client is in arrived.
+/// * chat - A chat message was sent to a channel the client can see.
+/// * game_joined - The client is playing or spectating a game.
+/// * game_left - The client is no longer in a game.
+/// * game_list - The games on the server, as asked for.
+/// * notice - A text message from the server, e.g. why it refused something.
+/// * disconnect - The connection to the server was lost or closed.
+#[derive(Default)]
+pub struct Events {
+    pub snapshot: Callbacks<GameSnapshot>,
+    pub chat: Callbacks<ChatBroadcast>,
+    pub game_joined: Callbacks<GameJoined>,
+    pub game_left: Callbacks<GameLeft>,
+    pub game_list: Callbacks<GameList>,
+    pub notice: Callbacks<String>,
+    pub disconnect: Callbacks<()>,
+}
@@ -1,18 +1,190 @@
 use crate::comms::handler::{Handler, TryClone};
-use crate::comms::message;
-use crate::errors::InputHandleError;
+use crate::comms::message::{self, Message};
+use crate::errors::{RelayError, RollbackError};
+use crate::game::rollback::RollbackConfig;
+use crate::game::GameID;
+use crate::host_side::console;
+use crate::host_side::events::Events;
+use crate::host_side::lockstep_peer::LockstepPeer;
+use crate::host_side::peer_host::PeerHost;
+use crate::host_side::rollback_peer::RollbackPeer;
+use crate::server_side::client::ClientID;
 use crate::threading::dispatcher::Dispatcher;
+use crate::threading::queue::Priority;
+use crate::threading::scheduler::Every;
+use serde_json::Value;
+use std::io;
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
 
+/// How long each frame of a rollback game lasts.
+pub const ROLLBACK_FRAME: Duration = Duration::from_millis(50);
+/// How often a peer-hosted game is advanced and relayed to its peers.
+pub const HOST_TICK: Duration = Duration::from_secs(1);
+
+/// Client side connection to a server.
+/// * id - The ClientID this client identifies itself with. Set before connecting, the client
+///   logs in as soon as the server asks.
+/// * logged_in - Whether the id has been sent. Nothing else may be sent before it.
+/// * game - The game on the server this client is playing or spectating, if any.
+/// * ping - The round trip time measured by the last Ping, if any.
+/// * ping_samples - Every round trip time measured, if they are being recorded.
+/// * snapshot - The last GameSnapshot received, if any.
+/// * print_updates - Whether snapshots and pongs are printed as they arrive, or only kept.
+/// * quiet - Whether nothing at all is printed, for front-ends which show events themselves.
+/// * events - Callbacks run as messages from the server are handled.
+/// * hosting - The peer-hosted game this client runs, if any.
+/// * hosted_game - The peer-hosted game this client is a peer in, if any.
+/// * host - The client hosting that game, the only peer snapshots are accepted from.
+/// * lockstep - The lockstep game this client simulates, if any.
+/// * rollback - The rollback game this client simulates, if any.
+/// * rollback_config - The input delay and rollback window used for rollback games.
+///
+/// HostClients are cloned for every received message, so state is shared behind mutexes.
 pub struct HostClient {
     pub dispatch: Dispatcher,
     pub socket: TcpStream,
+    pub id: Arc<Mutex<Option<ClientID>>>,
+    pub logged_in: Arc<AtomicBool>,
+    pub game: Arc<Mutex<Option<GameID>>>,
+    pub ping: Arc<Mutex<Option<Duration>>>,
+    pub ping_samples: Arc<Mutex<Option<Vec<Duration>>>>,
+    pub snapshot: Arc<Mutex<Option<message::GameSnapshot>>>,
+    pub print_updates: Arc<AtomicBool>,
+    pub quiet: Arc<AtomicBool>,
+    pub events: Arc<Events>,
+    pub hosting: Arc<Mutex<Option<PeerHost>>>,
+    pub hosted_game: Arc<Mutex<Option<GameID>>>,
+    pub host: Arc<Mutex<Option<ClientID>>>,
+    pub lockstep: Arc<Mutex<Option<LockstepPeer>>>,
+    pub rollback: Arc<Mutex<Option<RollbackPeer>>>,
+    pub rollback_config: Arc<Mutex<RollbackConfig>>,
 }
 
 impl HostClient {
     pub fn new(ip: &str, dispatch: Dispatcher) -> HostClient {
-        let socket = TcpStream::connect(ip).expect("Unable to connect to server");
-        HostClient { dispatch, socket }
+        HostClient::connect(ip, dispatch).expect("Unable to connect to server")
+    }
+
+    /// Connects to a server. Nothing is read from the socket until the client is listened to.
+    pub fn connect(ip: &str, dispatch: Dispatcher) -> io::Result<HostClient> {
+        let socket = TcpStream::connect(ip)?;
+        Ok(HostClient {
+            dispatch,
+            socket,
+            id: Arc::new(Mutex::new(None)),
+            logged_in: Arc::new(AtomicBool::new(false)),
+            game: Arc::new(Mutex::new(None)),
+            ping: Arc::new(Mutex::new(None)),
+            ping_samples: Arc::new(Mutex::new(None)),
+            snapshot: Arc::new(Mutex::new(None)),
+            print_updates: Arc::new(AtomicBool::new(true)),
+            quiet: Arc::new(AtomicBool::new(false)),
+            events: Arc::new(Events::default()),
+            hosting: Arc::new(Mutex::new(None)),
+            hosted_game: Arc::new(Mutex::new(None)),
+            host: Arc::new(Mutex::new(None)),
+            lockstep: Arc::new(Mutex::new(None)),
+            rollback: Arc::new(Mutex::new(None)),
+            rollback_config: Arc::new(Mutex::new(RollbackConfig::default())),
+        })
+    }
+
+    /// Sends a message to the server.
+    pub fn send<M: Message<'static>>(&self, msg: &M) -> io::Result<()> {
+        let mut socket = self.socket.try_clone()?;
+        message::try_send_json(msg, &mut socket)
+    }
+
+    /// Identifies this client to the server, answering its RequestClientID.
+    pub fn login(&self, id: ClientID) -> io::Result<()> {
+        *self.id.lock().unwrap() = Some(id.clone());
+        self.send(&message::RequestClientIDResponse { id })?;
+        self.logged_in.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Sends a Ping. The round trip time is stored in `ping` once the Pong arrives.
+    pub fn send_ping(&self) -> io::Result<()> {
+        self.send(&message::Ping {
+            sent_at_us: now_us(),
+        })
+    }
+
+    /// Prints a line to the console, unless the client is quiet.
+    fn print<S: Into<String>>(&self, line: S) {
+        if !self.quiet.load(Ordering::Relaxed) {
+            console::print_line(line);
+        }
+    }
+
+    /// Sets the input delay of rollback games, including the one being played.
+    pub fn set_input_delay(&self, input_delay: u64) {
+        self.rollback_config.lock().unwrap().input_delay = input_delay;
+        if let Some(peer) = self.rollback.lock().unwrap().as_mut() {
+            peer.session.set_input_delay(input_delay);
+        }
+    }
+
+    /// Sends a PlayerInput to whoever runs the game: applied locally when hosting, queued for the
+    /// next tick of a lockstep or rollback game, relayed to the host when a peer in a hosted game, or sent to
+    /// the server otherwise.
+    pub fn send_input(&self, input: message::PlayerInput) -> io::Result<()> {
+        if let Some(host) = self.hosting.lock().unwrap().as_mut() {
+            if let Some(id) = self.id.lock().unwrap().as_ref() {
+                host.apply_input(id, input);
+            }
+            return Ok(());
+        }
+        if let Some(peer) = self.lockstep.lock().unwrap().as_mut() {
+            peer.queue_input(input);
+            return Ok(());
+        }
+        if let Some(peer) = self.rollback.lock().unwrap().as_mut() {
+            peer.queue_input(input);
+            return Ok(());
+        }
+
+        if self.hosted_game.lock().unwrap().is_some() {
+            self.send(&message::Relay::new(None, &input))
+        } else {
+            self.send(&input)
+        }
+    }
+
+    /// Starts simulating a rollback game, and a job advancing it one frame at a time.
+    fn start_rollback(&self, msg: message::LockstepStart) {
+        let id = match self.id.lock().unwrap().clone() {
+            Some(id) => id,
+            None => return,
+        };
+        let config = *self.rollback_config.lock().unwrap();
+        let peer = RollbackPeer::new(msg.game_id, id, &msg.players, config);
+        *self.rollback.lock().unwrap() = Some(peer);
+
+        let rollback = Arc::clone(&self.rollback);
+        let mut socket = self.socket.try_clone().expect("Failed to clone socket");
+        let every = Every::fixed_rate(ROLLBACK_FRAME).named("rollback_frame");
+        self.dispatch
+            .with_priority(Priority::Realtime)
+            .schedule_every(every, move || rollback_frame(&rollback, &mut socket));
+    }
+
+    /// Starts running a peer-hosted game, and a job publishing its snapshots to the peers.
+    fn start_hosting(&self, host: PeerHost) {
+        self.print(format!("Hosting game {}", host.game_id));
+        *self.hosting.lock().unwrap() = Some(host);
+        *self.hosted_game.lock().unwrap() = None;
+        *self.host.lock().unwrap() = None;
+
+        let hosting = Arc::clone(&self.hosting);
+        let mut socket = self.socket.try_clone().expect("Failed to clone socket");
+        let every = Every::fixed_rate(HOST_TICK).named("host_tick");
+        self.dispatch
+            .schedule_every(every, move || host_tick(&hosting, &mut socket));
     }
 }
 
@@ -21,31 +193,406 @@ impl TryClone for HostClient {
         Ok(HostClient {
             dispatch: self.dispatch.clone(),
             socket: self.socket.try_clone()?,
+            id: Arc::clone(&self.id),
+            logged_in: Arc::clone(&self.logged_in),
+            game: Arc::clone(&self.game),
+            ping: Arc::clone(&self.ping),
+            ping_samples: Arc::clone(&self.ping_samples),
+            snapshot: Arc::clone(&self.snapshot),
+            print_updates: Arc::clone(&self.print_updates),
+            quiet: Arc::clone(&self.quiet),
+            events: Arc::clone(&self.events),
+            hosting: Arc::clone(&self.hosting),
+            hosted_game: Arc::clone(&self.hosted_game),
+            host: Arc::clone(&self.host),
+            lockstep: Arc::clone(&self.lockstep),
+            rollback: Arc::clone(&self.rollback),
+            rollback_config: Arc::clone(&self.rollback_config),
         })
     }
 }
 
+/// Simulates a frame of a rollback game, sending the scheduled local input to the server.
+///
+/// # Returns
+///
+/// * RollbackError once this client is no longer in a rollback game, which ends the loop.
+fn rollback_frame(
+    rollback: &Arc<Mutex<Option<RollbackPeer>>>,
+    socket: &mut TcpStream,
+) -> Result<(), RollbackError> {
+    let inputs = match rollback.lock().unwrap().as_mut() {
+        Some(peer) => peer.frame(),
+        None => {
+            return Err(RollbackError {
+                reason: String::from("No longer in a rollback game"),
+            })
+        }
+    };
+
+    for input in inputs {
+        if let Err(e) = message::try_send_json(&input, socket) {
+            return Err(RollbackError {
+                reason: e.to_string(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Advances a peer-hosted game by one tick and relays the snapshot to every peer.
+///
+/// # Returns
+///
+/// * RelayError once this client is no longer hosting, which ends the loop.
+fn host_tick(
+    hosting: &Arc<Mutex<Option<PeerHost>>>,
+    socket: &mut TcpStream,
+) -> Result<(), RelayError> {
+    let snapshot = match hosting.lock().unwrap().as_mut() {
+        Some(host) => host.tick(),
+        None => {
+            return Err(RelayError {
+                reason: String::from("No longer hosting"),
+            })
+        }
+    };
+
+    let relay = message::Relay::new(None, &snapshot);
+    if let Err(e) = message::try_send_json(&relay, socket) {
+        return Err(RelayError {
+            reason: e.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
 impl Handler for HostClient {
     fn handle_text_msg(&mut self, msg: message::TextMessage) {
-        println!("Received A Text Message: {}", msg.text);
+        self.print(format!("Received A Text Message: {}", msg.text));
+        self.events.notice.emit(&msg.text);
+    }
+
+    fn handle_chat_broadcast(&mut self, msg: message::ChatBroadcast) {
+        self.print(format_chat(&msg));
+        self.events.chat.emit(&msg);
+    }
+
+    fn handle_game_joined(&mut self, msg: message::GameJoined) {
+        *self.game.lock().unwrap() = Some(msg.game_id);
+        if msg.spectating {
+            self.print(format!("Spectating game {}", msg.game_id));
+        } else {
+            self.print(format!("Joined game {}", msg.game_id));
+        }
+        self.events.game_joined.emit(&msg);
+    }
+
+    fn handle_game_left(&mut self, msg: message::GameLeft) {
+        self.print(format!("Left game {}", msg.game_id));
+        *self.game.lock().unwrap() = None;
+        *self.snapshot.lock().unwrap() = None;
+        *self.lockstep.lock().unwrap() = None;
+        if let Some(peer) = self.rollback.lock().unwrap().take() {
+            self.print(format!("Rollback stats: {:?}", peer.session.stats));
+        }
+        self.events.game_left.emit(&msg);
+    }
+
+    fn handle_game_list(&mut self, msg: message::GameList) {
+        self.events.game_list.emit(&msg);
+        self.print(format!("{} game(s):", msg.games.len()));
+        for game in msg.games {
+            self.print(format!(
+                "  Game {} [{:?}] players: {} spectators: {}",
+                game.game_id, game.state, game.players, game.spectators
+            ));
+        }
+    }
+
+    fn handle_game_snapshot(&mut self, msg: message::GameSnapshot) {
+        if self.print_updates.load(Ordering::Relaxed) {
+            self.print(format!(
+                "Game {} tick {}: {} entities",
+                msg.game_id,
+                msg.tick,
+                msg.entities.len()
+            ));
+        }
+        self.events.snapshot.emit(&msg);
+        *self.snapshot.lock().unwrap() = Some(msg);
+    }
+
+    fn handle_matchmaking_status(&mut self, msg: message::MatchmakingStatus) {
+        let estimate = match msg.estimated_wait_secs {
+            Some(secs) => format!("~{}s", secs),
+            None => String::from("unknown"),
+        };
+        self.print(format!(
+            "Matchmaking: position {}/{}, waited {}s, estimated wait {}",
+            msg.position, msg.queued, msg.waited_secs, estimate
+        ));
+    }
+
+    fn handle_party_invite(&mut self, msg: message::PartyInvite) {
+        self.print(format!(
+            "{} invited you to party {}",
+            msg.from, msg.party_id
+        ));
+    }
+
+    fn handle_party_update(&mut self, msg: message::PartyUpdate) {
+        self.print(format!(
+            "Party {} (leader {}): {}",
+            msg.party_id,
+            msg.leader,
+            msg.members.join(", ")
+        ));
+    }
+
+    fn handle_game_hosted(&mut self, msg: message::GameHosted) {
+        let id = self.id.lock().unwrap().clone();
+        if let Some(id) = id {
+            self.start_hosting(PeerHost::new(msg.game_id, id));
+        }
+    }
+
+    fn handle_hosted_game_list(&mut self, msg: message::HostedGameList) {
+        self.print(format!("{} hosted game(s):", msg.games.len()));
+        for game in msg.games {
+            self.print(format!(
+                "  Game {} hosted by {} with {} peer(s)",
+                game.game_id, game.host, game.peers
+            ));
+        }
+    }
+
+    fn handle_host_assigned(&mut self, msg: message::HostAssigned) {
+        let id = self.id.lock().unwrap().clone();
+        if id.as_ref() != Some(&msg.host) {
+            self.print(format!("Game {} is hosted by {}", msg.game_id, msg.host));
+            *self.hosting.lock().unwrap() = None;
+            *self.hosted_game.lock().unwrap() = Some(msg.game_id);
+            *self.host.lock().unwrap() = Some(msg.host.clone());
+            return;
+        }
+
+        if self.hosting.lock().unwrap().is_some() {
+            return;
+        }
+        let host = match (&msg.snapshot, id) {
+            (Some(snapshot), Some(id)) => PeerHost::from_snapshot(msg.game_id, &id, snapshot),
+            (None, Some(id)) => PeerHost::new(msg.game_id, id),
+            (_, None) => return,
+        };
+        self.print(format!("Took over as host of game {}", msg.game_id));
+        self.start_hosting(host);
+    }
+
+    fn handle_peer_joined(&mut self, msg: message::PeerJoined) {
+        self.print(format!(
+            "{} joined hosted game {}",
+            msg.client_id, msg.game_id
+        ));
+        if let Some(host) = self.hosting.lock().unwrap().as_mut() {
+            host.add_peer(msg.client_id);
+        }
+    }
+
+    fn handle_peer_left(&mut self, msg: message::PeerLeft) {
+        self.print(format!(
+            "{} left hosted game {}",
+            msg.client_id, msg.game_id
+        ));
+        if let Some(host) = self.hosting.lock().unwrap().as_mut() {
+            host.remove_peer(&msg.client_id);
+        }
+    }
+
+    fn handle_relayed(&mut self, msg: message::Relayed) {
+        // Peers only send the host their inputs, and the host only sends snapshots. Anything else
+        // would be a peer posing as the server, so it is dropped.
+        let msg_type = msg.payload.get("msg_type").and_then(Value::as_str);
+        let data = msg.payload.get("data").cloned();
+        match (msg_type, data) {
+            (Some(message::PlayerInput::MSG_TYPE), Some(data)) => {
+                let input = serde_json::from_value(data);
+                match (self.hosting.lock().unwrap().as_mut(), input) {
+                    (Some(host), Ok(input)) => host.apply_input(&msg.from, input),
+                    _ => warn!(from = %msg.from, "Ignoring relayed input"),
+                }
+            }
+            (Some(message::GameSnapshot::MSG_TYPE), Some(data)) => {
+                let from_host = self.host.lock().unwrap().as_ref() == Some(&msg.from);
+                match serde_json::from_value(data) {
+                    Ok(snapshot) if from_host => self.handle_game_snapshot(snapshot),
+                    _ => warn!(from = %msg.from, "Ignoring relayed snapshot"),
+                }
+            }
+            _ => warn!(from = %msg.from, ?msg_type, "Ignoring relayed message"),
+        }
+    }
+
+    fn handle_lockstep_start(&mut self, msg: message::LockstepStart) {
+        self.print(format!(
+            "Lockstep game {} started with {:?}",
+            msg.game_id, msg.players
+        ));
+        if msg.rollback {
+            return self.start_rollback(msg);
+        }
+        let mut peer = LockstepPeer::new(msg.game_id, &msg.players);
+        let input = peer.take_input(0);
+        *self.lockstep.lock().unwrap() = Some(peer);
+
+        let mut socket = self.socket.try_clone().expect("Failed to clone socket");
+        message::send_json(input, &mut socket);
+    }
+
+    fn handle_input_bundle(&mut self, msg: message::InputBundle) {
+        if let Some(peer) = self.rollback.lock().unwrap().as_mut() {
+            if peer.game_id == msg.game_id {
+                peer.apply_departures(&msg);
+            }
+            return;
+        }
+
+        let (checksum, input) = match self.lockstep.lock().unwrap().as_mut() {
+            Some(peer) if peer.game_id == msg.game_id => {
+                (peer.step(&msg), peer.take_input(msg.tick + 1))
+            }
+            _ => return,
+        };
+
+        let mut socket = self.socket.try_clone().expect("Failed to clone socket");
+        message::send_json(checksum, &mut socket);
+        message::send_json(input, &mut socket);
+    }
+
+    fn handle_remote_input(&mut self, msg: message::RemoteInput) {
+        if let Some(peer) = self.rollback.lock().unwrap().as_mut() {
+            if let Err(e) = peer.receive(&msg) {
+                warn!(player = %msg.player, tick = msg.tick, error = %e, "Remote input refused");
+            }
+        }
+    }
+
+    fn handle_desync_detected(&mut self, msg: message::DesyncDetected) {
+        self.print(format!(
+            "Desync in game {} on tick {}: {} had checksum {:x}, expected {:x}",
+            msg.game_id, msg.tick, msg.client_id, msg.checksum, msg.expected
+        ));
+    }
+
+    fn handle_replay_list(&mut self, msg: message::ReplayList) {
+        self.print(format!("{} replay(s):", msg.replays.len()));
+        for name in msg.replays {
+            self.print(format!("  {}", name));
+        }
+    }
+
+    fn handle_replay_status(&mut self, msg: message::ReplayStatus) {
+        self.print(format!(
+            "Replay tick {}/{} at {}x{}",
+            msg.tick,
+            msg.end_tick,
+            msg.speed,
+            if msg.paused { " (paused)" } else { "" }
+        ));
+    }
+
+    fn handle_pause_vote_status(&mut self, msg: message::PauseVoteStatus) {
+        match msg.countdown {
+            Some(seconds) => self.print(format!(
+                "Vote for {:?} in game {} passed, changing in {}s",
+                msg.state, msg.game_id, seconds
+            )),
+            None => self.print(format!(
+                "{} voted for {:?} in game {} ({}/{})",
+                msg.client_id, msg.state, msg.game_id, msg.votes, msg.needed
+            )),
+        }
+    }
+
+    fn handle_pause_countdown(&mut self, msg: message::PauseCountdown) {
+        self.print(format!(
+            "Game {} is {:?} in {}s",
+            msg.game_id, msg.state, msg.seconds
+        ));
+    }
+
+    fn handle_game_state_changed(&mut self, msg: message::GameStateChanged) {
+        self.print(format!("Game {} is now {:?}", msg.game_id, msg.state));
     }
 
     fn handle_request_client_id(&mut self, msg: message::RequestClientID) {
-        println!("Received a Request for Client ID");
-        let mut socket_clone = self.socket.try_clone().expect("Failed to clone socket");
-        self.dispatch.execute(move || {
-            let id = read_input_line("Enter your ID:").expect("Error Reading Client ID from stdin");
-            let response = message::RequestClientIDResponse { id };
-            message::send_json(response, &mut socket_clone);
-        })
+        debug!("Received a request for client ID");
+        let id = self.id.lock().unwrap().clone();
+        match id {
+            Some(id) => {
+                if let Err(e) = self.login(id) {
+                    warn!(error = %e, "Failed to log in");
+                }
+            }
+            None => self.print("Connected, log in to continue"),
+        }
+    }
+
+    fn handle_pong(&mut self, msg: message::Pong) {
+        let rtt = Duration::from_micros(now_us().saturating_sub(msg.sent_at_us));
+        *self.ping.lock().unwrap() = Some(rtt);
+        if let Some(samples) = self.ping_samples.lock().unwrap().as_mut() {
+            samples.push(rtt);
+        }
+        if self.print_updates.load(Ordering::Relaxed) {
+            self.print(format!("Pong in {:.1}ms", rtt.as_secs_f64() * 1000.0));
+        }
     }
 }
 
-fn read_input_line(prompt: &str) -> Result<String, InputHandleError> {
-    let mut msg = String::new();
-    println!("{}", prompt);
-    match std::io::stdin().read_line(&mut msg) {
-        Ok(_buff_size) => return Ok(msg),
-        Err(_) => Err(InputHandleError),
+/// Microseconds since the unix epoch, which pings are timed with.
+fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros() as u64)
+}
+
+/// Formats a chat broadcast for display as "[channel] HH:MM:SS <sender> text".
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::comms::message::{ChatBroadcast, ChatChannel};
+/// use multiplayer::host_side::host_client::format_chat;
+///
+/// let msg = ChatBroadcast {
+///     channel: ChatChannel::Lobby,
+///     sender: Some(String::from("alice")),
+///     text: String::from("Hello!"),
+///     timestamp: 3661,
+/// };
+///
+/// assert_eq!(format_chat(&msg), "[lobby] 01:01:01 <alice> Hello!");
+/// ```
+pub fn format_chat(msg: &message::ChatBroadcast) -> String {
+    let channel = match &msg.channel {
+        message::ChatChannel::Lobby => String::from("lobby"),
+        message::ChatChannel::Game(id) => format!("game {}", id),
+        message::ChatChannel::Whisper(id) => format!("whisper to {}", id),
+        message::ChatChannel::System => String::from("system"),
+    };
+    let secs = msg.timestamp % 86400;
+    let time = format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    );
+    match &msg.sender {
+        Some(sender) => format!("[{}] {} <{}> {}", channel, time, sender, msg.text),
+        None => format!("[{}] {} {}", channel, time, msg.text),
     }
 }
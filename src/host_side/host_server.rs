@@ -1,6 +1,7 @@
 use std::io::prelude::*;
+use tracing::{info, warn};
 
-use crate::comms::handler::{Handler, TryClone};
+use crate::comms::handler::{Handler, JsonStream, TryClone};
 use crate::comms::message;
 use crate::host_side::host_client::HostClient;
 use crate::threading::threadpool;
@@ -17,24 +18,34 @@ impl HostServer {
         HostServer { client, pool }
     }
 
-    pub fn start(mut self) {
-        loop {
-            let mut buff = vec![0; message::MSG_SIZE];
-            let mut client_clone = self.client.try_clone().expect("Failed to clone HostClient");
-            match self.client.socket.read(&mut buff) {
-                Ok(0) => {
-                    println!("Source Disconected!");
-                    break;
-                }
-                Ok(_) => {
-                    self.pool.dispatcher.execute(move || {
-                        client_clone.receive_json(&buff);
-                    });
-                }
-                Err(_) => {
-                    println!("Error: halting listener");
-                    break;
-                }
+    pub fn start(self) {
+        let HostServer { client, pool } = self;
+        listen(client);
+        std::mem::drop(pool);
+    }
+}
+
+/// Reads messages from the server until it disconnects, handling each one on a job sent to the
+/// client's dispatcher.
+pub fn listen(mut client: HostClient) {
+    let mut stream = JsonStream::new();
+    loop {
+        let mut buff = vec![0; message::MSG_SIZE];
+        let mut client_clone = client.try_clone().expect("Failed to clone HostClient");
+        match client.socket.read(&mut buff) {
+            Ok(0) => {
+                info!("Server disconnected");
+                break;
+            }
+            Ok(n) => {
+                let messages = stream.push(&buff[..n]);
+                client.dispatch.execute(move || {
+                    client_clone.receive_messages(messages);
+                });
+            }
+            Err(e) => {
+                warn!(error = %e, "Halting listener");
+                break;
             }
         }
     }
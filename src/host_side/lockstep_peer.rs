@@ -0,0 +1,54 @@
+use crate::comms::message::{InputBundle, LockstepInput, PlayerInput, StateChecksum};
+use crate::game::controller::GameController;
+use crate::game::GameID;
+use crate::server_side::client::ClientID;
+
+/// Simulates a lockstep game locally. The server only collects inputs, so every peer advances
+/// its own copy of the world with each InputBundle it receives.
+/// * game_id - The GameID of the lockstep game.
+/// * controller - The GameController which owns the local copy of the world.
+/// * pending - Input gathered since the last tick, sent with the next LockstepInput.
+pub struct LockstepPeer {
+    pub game_id: GameID,
+    pub controller: GameController,
+    pub pending: PlayerInput,
+}
+
+impl LockstepPeer {
+    /// Returns a new LockstepPeer, spawning the players in the order the server sent them.
+    pub fn new(game_id: GameID, players: &[ClientID]) -> LockstepPeer {
+        let mut controller = GameController::new();
+        controller.start_lockstep(players);
+        LockstepPeer {
+            game_id,
+            controller,
+            pending: PlayerInput::default(),
+        }
+    }
+
+    /// Adds to the input which will be sent for the next tick.
+    pub fn queue_input(&mut self, input: PlayerInput) {
+        self.pending.x += input.x;
+        self.pending.y += input.y;
+    }
+
+    /// Returns the LockstepInput for a tick, clearing the pending input.
+    pub fn take_input(&mut self, tick: u64) -> LockstepInput {
+        LockstepInput {
+            tick,
+            input: std::mem::take(&mut self.pending),
+        }
+    }
+
+    /// Simulates a tick from the inputs of every player.
+    ///
+    /// # Returns
+    ///
+    /// * The checksum of the world, to report to the server.
+    pub fn step(&mut self, bundle: &InputBundle) -> StateChecksum {
+        StateChecksum {
+            tick: bundle.tick,
+            checksum: self.controller.step(bundle),
+        }
+    }
+}
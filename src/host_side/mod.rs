@@ -1,2 +1,11 @@
+pub mod bot;
+pub mod console;
+pub mod events;
 pub mod host_client;
 pub mod host_server;
+pub mod lockstep_peer;
+pub mod peer_host;
+pub mod repl;
+pub mod rollback_peer;
+pub mod session;
+pub mod tui;
@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+
+use crate::comms::message::{GameSnapshot, PlayerInput};
+use crate::game::controller::GameController;
+use crate::game::GameID;
+use crate::server_side::client::ClientID;
+
+/// Runs a peer-hosted game authoritatively in a player's process. Peers send their inputs to the
+/// host through the server, and the host publishes snapshots back the same way.
+/// * game_id - The GameID the server assigned to the hosted game.
+/// * controller - The GameController which owns the world.
+/// * peers - Every other client in the game.
+pub struct PeerHost {
+    pub game_id: GameID,
+    pub controller: GameController,
+    pub peers: HashSet<ClientID>,
+}
+
+impl PeerHost {
+    /// Returns a new PeerHost running a fresh game, with the host as its first player.
+    pub fn new(game_id: GameID, host_id: ClientID) -> PeerHost {
+        let mut controller = GameController::new();
+        controller.model.add_player(host_id);
+        PeerHost {
+            game_id,
+            controller,
+            peers: HashSet::new(),
+        }
+    }
+
+    /// Takes over a game from a previous host, restoring the world from its latest snapshot.
+    pub fn from_snapshot(game_id: GameID, host_id: &ClientID, snapshot: &GameSnapshot) -> PeerHost {
+        let controller = GameController::from_snapshot(snapshot);
+        let peers = snapshot
+            .entities
+            .iter()
+            .filter_map(|e| e.player.clone())
+            .filter(|p| p != host_id)
+            .collect();
+        PeerHost {
+            game_id,
+            controller,
+            peers,
+        }
+    }
+
+    /// Adds a peer to the game as a player.
+    pub fn add_peer(&mut self, peer_id: ClientID) {
+        if self.peers.insert(peer_id.clone()) {
+            self.controller.model.add_player(peer_id);
+        }
+    }
+
+    /// Removes a peer and their entity from the game.
+    pub fn remove_peer(&mut self, peer_id: &ClientID) {
+        self.peers.remove(peer_id);
+        self.controller.model.remove_player(peer_id);
+    }
+
+    /// Applies an input received from a peer.
+    pub fn apply_input(&mut self, peer_id: &ClientID, input: PlayerInput) {
+        self.controller.model.apply_input(peer_id, input);
+    }
+
+    /// Advances the game by one tick, and returns the snapshot to publish to the peers.
+    pub fn tick(&mut self) -> GameSnapshot {
+        self.controller.dispatch();
+        self.controller.snapshot(self.game_id)
+    }
+}
@@ -0,0 +1,348 @@
+use std::io::BufRead;
+use std::net::Shutdown;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use rustyline::error::ReadlineError;
+use rustyline::{DefaultEditor, ExternalPrinter};
+use tracing::warn;
+
+use crate::comms::handler::TryClone;
+use crate::comms::message::{self, ChatChannel, Message};
+use crate::errors::CommandError;
+use crate::game::GameID;
+use crate::host_side::console;
+use crate::host_side::host_client::HostClient;
+use crate::host_side::host_server;
+use crate::server_side::client::ClientID;
+use crate::threading::threadpool::ThreadPool;
+
+/// File in the home directory the commands typed into the REPL are kept in.
+pub const HISTORY_FILE: &str = ".multiplayer_history";
+
+pub const HELP: &str = "\
+Commands:
+  /connect <addr>          Connect to a server, e.g. /connect 127.0.0.1:7878
+  /login <id>              Log in, or set the id to log in with once connected
+  /games                   List the games on the server
+  /create                  Create a game and join it
+  /join <game id>          Join a game
+  /leave                   Leave the game you are in
+  /say <text>              Chat to your game, or the lobby when not in one
+  /whisper <id> <text>     Chat to one client
+  /ping                    Measure the round trip time to the server
+  /wait <secs>             Wait, so replies can arrive while a script runs
+  /help                    Show this help
+  /quit                    Disconnect and exit
+Lines not starting with / are said. Lines starting with # are ignored.";
+
+/// A command typed into the REPL or read from a script.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Connect(String),
+    Login(ClientID),
+    Games,
+    Create,
+    Join(GameID),
+    Leave,
+    Say(String),
+    Whisper(ClientID, String),
+    Ping,
+    Wait(Duration),
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// Parses a line of input.
+    ///
+    /// # Returns
+    ///
+    /// * None for a blank line or a # comment.
+    /// * CommandError if the command is unknown, or its arguments are missing or wrong.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate multiplayer;
+    /// use multiplayer::host_side::repl::Command;
+    ///
+    /// assert_eq!(Command::parse("/join 3").unwrap(), Some(Command::Join(3)));
+    /// assert_eq!(
+    ///     Command::parse("/whisper bob see you there").unwrap(),
+    ///     Some(Command::Whisper(String::from("bob"), String::from("see you there")))
+    /// );
+    /// assert_eq!(Command::parse("gg").unwrap(), Some(Command::Say(String::from("gg"))));
+    /// assert_eq!(Command::parse("# a comment").unwrap(), None);
+    /// assert!(Command::parse("/join").is_err());
+    /// ```
+    pub fn parse(line: &str) -> Result<Option<Command>, CommandError> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let line = match line.strip_prefix('/') {
+            Some(line) => line,
+            None => return Ok(Some(Command::Say(line.to_owned()))),
+        };
+
+        let (name, rest) = match line.split_once(char::is_whitespace) {
+            Some((name, rest)) => (name, rest.trim()),
+            None => (line, ""),
+        };
+        let command = match name {
+            "connect" => Command::Connect(required(name, rest)?.to_owned()),
+            "login" => Command::Login(required(name, rest)?.to_owned()),
+            "games" => Command::Games,
+            "create" => Command::Create,
+            "join" => Command::Join(parse_arg(name, rest)?),
+            "leave" => Command::Leave,
+            "say" => Command::Say(required(name, rest)?.to_owned()),
+            "whisper" => match required(name, rest)?.split_once(char::is_whitespace) {
+                Some((id, text)) => Command::Whisper(id.to_owned(), text.trim().to_owned()),
+                None => return Err(usage(name)),
+            },
+            "ping" => Command::Ping,
+            "wait" => {
+                let secs: f64 = parse_arg(name, rest)?;
+                if !secs.is_finite() || secs < 0.0 {
+                    return Err(usage(name));
+                }
+                Command::Wait(Duration::from_secs_f64(secs))
+            }
+            "help" => Command::Help,
+            "quit" | "exit" => Command::Quit,
+            other => {
+                return Err(CommandError {
+                    reason: format!("Unknown command /{}, try /help", other),
+                })
+            }
+        };
+        Ok(Some(command))
+    }
+}
+
+fn usage(name: &str) -> CommandError {
+    let line = HELP
+        .lines()
+        .find(|l| l.trim_start().starts_with(&format!("/{} ", name)))
+        .and_then(|l| l.trim().split("  ").next())
+        .unwrap_or(name);
+    CommandError {
+        reason: format!("Usage: {}", line),
+    }
+}
+
+fn required<'a>(name: &str, rest: &'a str) -> Result<&'a str, CommandError> {
+    if rest.is_empty() {
+        return Err(usage(name));
+    }
+    Ok(rest)
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, rest: &str) -> Result<T, CommandError> {
+    required(name, rest)?.parse().map_err(|_| usage(name))
+}
+
+/// Whether the REPL should keep reading commands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+/// An interactive client. Commands are read from a prompt or a script, while messages from the
+/// server are printed as they arrive.
+pub struct Repl {
+    pool: ThreadPool,
+    client: Option<HostClient>,
+    login: Option<ClientID>,
+}
+
+impl Repl {
+    pub fn new(size: usize) -> Repl {
+        Repl {
+            pool: ThreadPool::new(size),
+            client: None,
+            login: None,
+        }
+    }
+
+    /// Runs a command.
+    ///
+    /// # Returns
+    ///
+    /// * Whether to keep reading commands.
+    /// * CommandError if the command needs a connection and there isn't one, or failed to send.
+    pub fn execute(&mut self, command: Command) -> Result<Flow, CommandError> {
+        match command {
+            Command::Connect(addr) => self.connect(&addr)?,
+            Command::Login(id) => match &self.client {
+                Some(client) => client.login(id.clone()).map_err(send_error)?,
+                None => {
+                    console::print_line(format!("Will log in as {} once connected", id));
+                    self.login = Some(id);
+                }
+            },
+            Command::Games => self.send(&message::RequestGameList)?,
+            Command::Create => self.send(&message::CreateGame)?,
+            Command::Join(game_id) => self.send(&message::JoinGame { game_id })?,
+            Command::Leave => self.send(&message::LeaveGame)?,
+            Command::Say(text) => {
+                let channel = match self.client()?.game.lock().unwrap().as_ref() {
+                    Some(game_id) => ChatChannel::Game(*game_id),
+                    None => ChatChannel::Lobby,
+                };
+                self.send(&message::ChatMessage::new(channel, text))?
+            }
+            Command::Whisper(id, text) => {
+                self.send(&message::ChatMessage::new(ChatChannel::Whisper(id), text))?
+            }
+            Command::Ping => self.client()?.send_ping().map_err(send_error)?,
+            Command::Wait(duration) => thread::sleep(duration),
+            Command::Help => console::print_line(HELP),
+            Command::Quit => {
+                self.disconnect();
+                return Ok(Flow::Quit);
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Parses and runs a line of input.
+    pub fn execute_line(&mut self, line: &str) -> Result<Flow, CommandError> {
+        match Command::parse(line)? {
+            Some(command) => self.execute(command),
+            None => Ok(Flow::Continue),
+        }
+    }
+
+    /// Runs every command in a script, echoing each one so the output reads like a session.
+    ///
+    /// # Returns
+    ///
+    /// * CommandError naming the line of the first command which failed. Later commands aren't run.
+    pub fn run_script<R: BufRead>(&mut self, script: R) -> Result<(), CommandError> {
+        for (number, line) in script.lines().enumerate() {
+            let line = line.map_err(|e| CommandError {
+                reason: format!("Failed to read script: {}", e),
+            })?;
+            if Command::parse(&line).is_ok_and(|c| c.is_some()) {
+                console::print_line(format!("> {}", line.trim()));
+            }
+            let flow = self.execute_line(&line).map_err(|e| CommandError {
+                reason: format!("Line {}: {}", number + 1, e.reason),
+            })?;
+            if flow == Flow::Quit {
+                return Ok(());
+            }
+        }
+        self.disconnect();
+        Ok(())
+    }
+
+    /// Reads commands from a prompt with history until /quit or end of input. Messages from the
+    /// server are printed above the prompt.
+    pub fn run_interactive(&mut self) -> Result<(), CommandError> {
+        let mut editor = DefaultEditor::new().map_err(|e| CommandError {
+            reason: format!("Failed to open the terminal: {}", e),
+        })?;
+        let history = history_path();
+        if let Some(path) = &history {
+            // There is no history the first time the REPL runs.
+            let _ = editor.load_history(path);
+        }
+        match editor.create_external_printer() {
+            Ok(mut printer) => console::set_printer(move |line| {
+                if let Err(e) = printer.print(format!("{}\n", line)) {
+                    warn!(error = %e, "Failed to print");
+                }
+            }),
+            Err(e) => warn!(error = %e, "Printing over the prompt instead"),
+        }
+        console::print_line("Type /help for commands");
+
+        loop {
+            match editor.readline("> ") {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = editor.add_history_entry(line.as_str());
+                    }
+                    match self.execute_line(&line) {
+                        Ok(Flow::Quit) => break,
+                        Ok(Flow::Continue) => (),
+                        Err(e) => console::print_line(e.to_string()),
+                    }
+                }
+                Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
+                    self.disconnect();
+                    break;
+                }
+                Err(e) => {
+                    console::print_line(format!("Failed to read input: {}", e));
+                    break;
+                }
+            }
+        }
+
+        console::clear_printer();
+        if let Some(path) = &history {
+            if let Err(e) = editor.save_history(path) {
+                warn!(error = %e, "Failed to save history");
+            }
+        }
+        Ok(())
+    }
+
+    /// Connects to a server, dropping any connection already open, and starts listening to it.
+    fn connect(&mut self, addr: &str) -> Result<(), CommandError> {
+        self.disconnect();
+        let client =
+            HostClient::connect(addr, self.pool.dispatcher.clone()).map_err(|e| CommandError {
+                reason: format!("Failed to connect to {}: {}", addr, e),
+            })?;
+        *client.id.lock().unwrap() = self.login.clone();
+
+        let listener = client.try_clone().map_err(send_error)?;
+        self.pool.dispatcher.execute(move || {
+            host_server::listen(listener);
+            console::print_line("Disconnected from the server");
+        });
+        console::print_line(format!("Connecting to {}", addr));
+        self.client = Some(client);
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(client) = self.client.take() {
+            // Ends the listener. The socket may already be closed by the server.
+            let _ = client.socket.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Returns the connection to the server.
+    ///
+    /// # Returns
+    ///
+    /// * CommandError if the REPL isn't connected.
+    pub fn client(&self) -> Result<&HostClient, CommandError> {
+        self.client.as_ref().ok_or_else(|| CommandError {
+            reason: String::from("Not connected, use /connect <addr>"),
+        })
+    }
+
+    fn send<M: Message<'static>>(&self, msg: &M) -> Result<(), CommandError> {
+        self.client()?.send(msg).map_err(send_error)
+    }
+}
+
+fn send_error(e: std::io::Error) -> CommandError {
+    CommandError {
+        reason: format!("Failed to send: {}", e),
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
@@ -0,0 +1,78 @@
+use tracing::debug;
+
+use crate::comms::message::{InputBundle, LockstepInput, PlayerInput, RemoteInput};
+use crate::errors::RollbackError;
+use crate::game::controller::GameController;
+use crate::game::rollback::{RollbackConfig, RollbackSession};
+use crate::game::GameID;
+use crate::server_side::client::ClientID;
+
+/// Runs a rollback game locally, one frame at a time.
+/// * game_id - The GameID of the rollback game.
+/// * session - The RollbackSession which owns the local copy of the world.
+/// * pending - Input gathered since the last frame, scheduled on the next frame.
+pub struct RollbackPeer {
+    pub game_id: GameID,
+    pub session: RollbackSession,
+    pub pending: PlayerInput,
+}
+
+impl RollbackPeer {
+    /// Returns a new RollbackPeer, spawning the players in the order the server sent them.
+    pub fn new(
+        game_id: GameID,
+        local: ClientID,
+        players: &[ClientID],
+        config: RollbackConfig,
+    ) -> RollbackPeer {
+        let mut controller = GameController::new();
+        controller.start_lockstep(players);
+        RollbackPeer {
+            game_id,
+            session: RollbackSession::new(controller, local, players.to_vec(), config),
+            pending: PlayerInput::default(),
+        }
+    }
+
+    /// Adds to the input which will be scheduled on the next frame.
+    pub fn queue_input(&mut self, input: PlayerInput) {
+        self.pending.x += input.x;
+        self.pending.y += input.y;
+    }
+
+    /// Schedules the pending input and simulates the next frame. Nothing happens while the
+    /// session is stalled waiting for remote input.
+    ///
+    /// # Returns
+    ///
+    /// * The LockstepInputs to send to the server.
+    pub fn frame(&mut self) -> Vec<LockstepInput> {
+        if self.session.is_stalled() {
+            self.session.stats.stalls += 1;
+            return Vec::new();
+        }
+
+        let input = std::mem::take(&mut self.pending);
+        let scheduled = self.session.add_local_input(input);
+        if let Err(e) = self.session.advance() {
+            debug!(error = %e, "Frame not simulated");
+        }
+        scheduled
+            .into_iter()
+            .map(|(tick, input)| LockstepInput { tick, input })
+            .collect()
+    }
+
+    /// Records another player's input.
+    pub fn receive(&mut self, msg: &RemoteInput) -> Result<(), RollbackError> {
+        self.session
+            .add_remote_input(&msg.player, msg.tick, msg.input)
+    }
+
+    /// Stops waiting for players who left, from the tick the server removed them on.
+    pub fn apply_departures(&mut self, bundle: &InputBundle) {
+        for player in bundle.left.iter() {
+            self.session.remove_player(player, bundle.tick);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::net::Shutdown;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::comms::handler::TryClone;
+use crate::comms::message::{self, ChatChannel, GameSnapshot, Message, PlayerInput};
+use crate::errors::SessionError;
+use crate::game::GameID;
+use crate::host_side::host_client::HostClient;
+use crate::host_side::host_server;
+use crate::server_side::client::ClientID;
+use crate::threading::threadpool::ThreadPool;
+
+/// How often connect checks whether the server has let the client in.
+const POLL: Duration = Duration::from_millis(10);
+
+/// Describes a connection to a server
+/// * addr - The server to connect to, e.g. "127.0.0.1:7878".
+/// * id - The ClientID to log in with.
+/// * threads - Workers handling messages from the server and running callbacks.
+/// * timeout - How long to wait for the server to let the client in.
+#[derive(Clone, Debug)]
+pub struct SessionConfig {
+    pub addr: String,
+    pub id: ClientID,
+    pub threads: usize,
+    pub timeout: Duration,
+}
+
+impl SessionConfig {
+    pub fn new<A: Into<String>, I: Into<ClientID>>(addr: A, id: I) -> SessionConfig {
+        SessionConfig {
+            addr: addr.into(),
+            id: id.into(),
+            threads: 4,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// A logged in connection to a server, for game front-ends. Requests are typed methods and
+/// whatever the server sends is reported to callbacks, so neither sockets nor JSON are touched.
+/// Nothing is printed. The connection is closed when the session is dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// extern crate multiplayer;
+/// use multiplayer::comms::message::PlayerInput;
+/// use multiplayer::host_side::host_client::format_chat;
+/// use multiplayer::host_side::session::{ClientSession, SessionConfig};
+///
+/// let session = ClientSession::connect(SessionConfig::new("127.0.0.1:7878", "alice")).unwrap();
+/// session.on_snapshot(|snapshot| println!("Tick {}", snapshot.tick));
+/// session.on_chat(|msg| println!("{}", format_chat(msg)));
+/// session.on_disconnect(|| println!("Disconnected"));
+///
+/// session.join_game(0).unwrap();
+/// session.send_input(PlayerInput { x: 1.0, y: 0.0 }).unwrap();
+/// session.chat("gl hf").unwrap();
+/// ```
+pub struct ClientSession {
+    client: HostClient,
+    connected: Arc<AtomicBool>,
+    // Dropped last, once the socket is closed, which joins the workers.
+    _pool: ThreadPool,
+}
+
+impl ClientSession {
+    /// Connects to a server and logs in, returning once the server has let the client in.
+    ///
+    /// # Returns
+    ///
+    /// * SessionError if the server can't be reached, refuses the client, or doesn't let it in
+    ///   before the timeout.
+    pub fn connect(config: SessionConfig) -> Result<ClientSession, SessionError> {
+        let pool = ThreadPool::new(config.threads.max(1));
+        let client = HostClient::connect(&config.addr, pool.dispatcher.clone()).map_err(|e| {
+            SessionError {
+                reason: format!("Failed to connect to {}: {}", config.addr, e),
+            }
+        })?;
+        *client.id.lock().unwrap() = Some(config.id);
+        client.quiet.store(true, Ordering::Relaxed);
+        client.print_updates.store(false, Ordering::Relaxed);
+
+        // The server says why it refuses a client before closing the connection.
+        let notice = Arc::new(Mutex::new(None));
+        let sink = Arc::clone(&notice);
+        client
+            .events
+            .notice
+            .add(move |text: &String| *sink.lock().unwrap() = Some(text.clone()));
+
+        let connected = Arc::new(AtomicBool::new(true));
+        let listener = client.try_clone().map_err(send_error)?;
+        let flag = Arc::clone(&connected);
+        pool.dispatcher.execute(move || {
+            let events = Arc::clone(&listener.events);
+            host_server::listen(listener);
+            flag.store(false, Ordering::Relaxed);
+            events.disconnect.emit(&());
+        });
+
+        let session = ClientSession {
+            client,
+            connected,
+            _pool: pool,
+        };
+        session.wait_until_in(config.timeout, &notice)?;
+        Ok(session)
+    }
+
+    /// Waits for the server to answer a ping, which it only does once the client has been added.
+    fn wait_until_in(
+        &self,
+        timeout: Duration,
+        notice: &Mutex<Option<String>>,
+    ) -> Result<(), SessionError> {
+        let deadline = Instant::now() + timeout;
+        let mut pinged = false;
+        while self.client.ping.lock().unwrap().is_none() {
+            if !self.is_connected() {
+                let reason = match notice.lock().unwrap().take() {
+                    Some(text) => format!("Refused by the server: {}", text),
+                    None => String::from("Disconnected before logging in"),
+                };
+                return Err(SessionError { reason });
+            }
+            if Instant::now() >= deadline {
+                self.close();
+                return Err(SessionError {
+                    reason: format!("Not let in after {:?}", timeout),
+                });
+            }
+            // Nothing may be sent before the id.
+            if !pinged && self.client.logged_in.load(Ordering::Relaxed) {
+                self.client.send_ping().map_err(send_error)?;
+                pinged = true;
+            }
+            thread::sleep(POLL);
+        }
+        Ok(())
+    }
+
+    /// The ClientID the session logged in with.
+    pub fn id(&self) -> ClientID {
+        self.client.id.lock().unwrap().clone().unwrap_or_default()
+    }
+
+    /// Whether the connection to the server is still open.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// The game being played or spectated, if any.
+    pub fn game(&self) -> Option<GameID> {
+        *self.client.game.lock().unwrap()
+    }
+
+    /// The last snapshot of the game, if any.
+    pub fn snapshot(&self) -> Option<GameSnapshot> {
+        self.client.snapshot.lock().unwrap().clone()
+    }
+
+    /// The round trip time measured by the last ping.
+    pub fn ping(&self) -> Option<Duration> {
+        *self.client.ping.lock().unwrap()
+    }
+
+    /// Measures the round trip time again. The result is read with `ping`.
+    pub fn send_ping(&self) -> Result<(), SessionError> {
+        self.client.send_ping().map_err(send_error)
+    }
+
+    /// Asks for the games on the server, which are reported to `on_game_list`.
+    pub fn list_games(&self) -> Result<(), SessionError> {
+        self.send(&message::RequestGameList)
+    }
+
+    /// Creates a game and joins it.
+    pub fn create_game(&self) -> Result<(), SessionError> {
+        self.send(&message::CreateGame)
+    }
+
+    pub fn join_game(&self, game_id: GameID) -> Result<(), SessionError> {
+        self.send(&message::JoinGame { game_id })
+    }
+
+    pub fn spectate_game(&self, game_id: GameID) -> Result<(), SessionError> {
+        self.send(&message::SpectateGame { game_id })
+    }
+
+    pub fn leave_game(&self) -> Result<(), SessionError> {
+        self.send(&message::LeaveGame)
+    }
+
+    /// Queues for a game of a mode with players of a similar rating.
+    pub fn matchmake<S: Into<String>>(&self, mode: S, rating: u32) -> Result<(), SessionError> {
+        self.send(&message::EnqueueMatchmaking {
+            mode: mode.into(),
+            rating,
+        })
+    }
+
+    /// Moves the player's entity in the game being played.
+    pub fn send_input(&self, input: PlayerInput) -> Result<(), SessionError> {
+        self.client.send_input(input).map_err(send_error)
+    }
+
+    /// Chats to the game being played, or the lobby when not in one.
+    pub fn chat<S: Into<String>>(&self, text: S) -> Result<(), SessionError> {
+        let channel = match self.game() {
+            Some(game_id) => ChatChannel::Game(game_id),
+            None => ChatChannel::Lobby,
+        };
+        self.send(&message::ChatMessage::new(channel, text))
+    }
+
+    /// Chats to one client.
+    pub fn whisper<S: Into<String>>(&self, to: ClientID, text: S) -> Result<(), SessionError> {
+        self.send(&message::ChatMessage::new(ChatChannel::Whisper(to), text))
+    }
+
+    pub fn on_snapshot<F>(&self, callback: F)
+    where
+        F: FnMut(&GameSnapshot) + Send + 'static,
+    {
+        self.client.events.snapshot.add(callback);
+    }
+
+    pub fn on_chat<F>(&self, callback: F)
+    where
+        F: FnMut(&message::ChatBroadcast) + Send + 'static,
+    {
+        self.client.events.chat.add(callback);
+    }
+
+    pub fn on_game_joined<F>(&self, callback: F)
+    where
+        F: FnMut(&message::GameJoined) + Send + 'static,
+    {
+        self.client.events.game_joined.add(callback);
+    }
+
+    pub fn on_game_left<F>(&self, callback: F)
+    where
+        F: FnMut(&message::GameLeft) + Send + 'static,
+    {
+        self.client.events.game_left.add(callback);
+    }
+
+    pub fn on_game_list<F>(&self, callback: F)
+    where
+        F: FnMut(&message::GameList) + Send + 'static,
+    {
+        self.client.events.game_list.add(callback);
+    }
+
+    /// Text messages from the server, e.g. why a request was refused.
+    pub fn on_notice<F>(&self, callback: F)
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        let mut callback = callback;
+        self.client
+            .events
+            .notice
+            .add(move |text: &String| callback(text));
+    }
+
+    /// Runs once the connection is lost, or closed by dropping the session.
+    pub fn on_disconnect<F>(&self, callback: F)
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let mut callback = callback;
+        self.client.events.disconnect.add(move |_: &()| callback());
+    }
+
+    fn send<M: Message<'static>>(&self, msg: &M) -> Result<(), SessionError> {
+        self.client.send(msg).map_err(send_error)
+    }
+
+    fn close(&self) {
+        // Ends the listener. The socket may already be closed by the server.
+        let _ = self.client.socket.shutdown(Shutdown::Both);
+    }
+}
+
+impl Drop for ClientSession {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn send_error(e: std::io::Error) -> SessionError {
+    SessionError {
+        reason: format!("Failed to send: {}", e),
+    }
+}
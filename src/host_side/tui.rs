@@ -0,0 +1,331 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::comms::message::{GameSnapshot, PlayerInput};
+use crate::host_side::console;
+use crate::host_side::host_client::HostClient;
+use crate::host_side::repl::{Flow, Repl};
+use crate::server_side::client::ClientID;
+
+/// How many lines of chat and server messages are kept.
+pub const LOG_LINES: usize = 200;
+/// How often the round trip time is measured.
+pub const PING_INTERVAL: Duration = Duration::from_secs(1);
+/// How long to wait for a key before drawing again.
+const FRAME: Duration = Duration::from_millis(50);
+/// How fast the arrow keys move a player, in world units per tick.
+const SPEED: f32 = 1.0;
+
+const HELP: &str = "arrows/wasd move  space stop  enter chat or /command  q quit";
+
+/// What a cell of the grid shows, from the entities in a snapshot.
+fn cell(entity_player: Option<&ClientID>, me: Option<&ClientID>) -> char {
+    match entity_player {
+        Some(player) if Some(player) == me => '@',
+        Some(player) => player.chars().next().unwrap_or('?'),
+        None => '*',
+    }
+}
+
+/// Draws a snapshot as a top-down grid of `width` by `height` cells, one world unit to a cell,
+/// centred on the entity of `me` if it has one. North, increasing y, is up. Entities off the
+/// grid aren't drawn.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::comms::message::{EntitySnapshot, GameSnapshot};
+/// use multiplayer::host_side::tui::grid;
+///
+/// let alice = String::from("alice");
+/// let snapshot = GameSnapshot {
+///     game_id: 0,
+///     tick: 7,
+///     entities: vec![
+///         EntitySnapshot { id: 0, x: 0.0, y: 0.0, player: Some(alice.clone()) },
+///         EntitySnapshot { id: 1, x: 1.0, y: 1.0, player: Some(String::from("bob")) },
+///         EntitySnapshot { id: 2, x: -1.0, y: 0.0, player: None },
+///     ],
+/// };
+///
+/// assert_eq!(grid(&snapshot, Some(&alice), 3, 3), vec!["..b", "*@.", "..."]);
+/// ```
+pub fn grid(
+    snapshot: &GameSnapshot,
+    me: Option<&ClientID>,
+    width: usize,
+    height: usize,
+) -> Vec<String> {
+    let centre = snapshot
+        .entities
+        .iter()
+        .find(|e| e.player.is_some() && e.player.as_ref() == me)
+        .map_or((0.0, 0.0), |e| (e.x.round(), e.y.round()));
+    let left = centre.0 as i64 - (width as i64 - 1) / 2;
+    let top = centre.1 as i64 + (height as i64 - 1) / 2;
+
+    let mut rows = vec![vec!['.'; width]; height];
+    // Players are drawn last so they aren't hidden by other entities in the same cell.
+    let mut entities: Vec<_> = snapshot.entities.iter().collect();
+    entities.sort_by_key(|e| (e.player.is_some(), e.player.as_ref() == me));
+    for entity in entities {
+        let column = entity.x.round() as i64 - left;
+        let row = top - entity.y.round() as i64;
+        if (0..width as i64).contains(&column) && (0..height as i64).contains(&row) {
+            rows[row as usize][column as usize] = cell(entity.player.as_ref(), me);
+        }
+    }
+    rows.into_iter().map(|r| r.into_iter().collect()).collect()
+}
+
+/// Returns the input an arrow or wasd key asks for, if it is one.
+fn movement(key: &KeyEvent) -> Option<PlayerInput> {
+    let (x, y) = match key.code {
+        KeyCode::Up | KeyCode::Char('w') => (0.0, SPEED),
+        KeyCode::Down | KeyCode::Char('s') => (0.0, -SPEED),
+        KeyCode::Left | KeyCode::Char('a') => (-SPEED, 0.0),
+        KeyCode::Right | KeyCode::Char('d') => (SPEED, 0.0),
+        KeyCode::Char(' ') => (0.0, 0.0),
+        _ => return None,
+    };
+    Some(PlayerInput { x, y })
+}
+
+/// The state of the screen between frames.
+struct View {
+    log: Arc<Mutex<VecDeque<String>>>,
+    /// The chat line being typed, if any.
+    input: Option<String>,
+    last_ping: Option<Instant>,
+}
+
+/// Runs a terminal view of the game the REPL's client is in. Snapshots are drawn as a grid,
+/// arrow keys send PlayerInput, and typed lines are said or run as commands. Returns when the
+/// player quits.
+pub fn run(repl: &mut Repl) -> io::Result<()> {
+    let log = Arc::new(Mutex::new(VecDeque::new()));
+    let sink = Arc::clone(&log);
+    console::set_printer(move |line| {
+        let mut log = sink.lock().unwrap();
+        log.extend(line.lines().map(String::from));
+        while log.len() > LOG_LINES {
+            log.pop_front();
+        }
+    });
+    if let Ok(client) = repl.client() {
+        client.print_updates.store(false, Ordering::Relaxed);
+    }
+
+    let mut view = View {
+        log,
+        input: None,
+        last_ping: None,
+    };
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, repl, &mut view);
+    ratatui::restore();
+    console::clear_printer();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, repl: &mut Repl, view: &mut View) -> io::Result<()> {
+    loop {
+        if let Some(client) = repl
+            .client()
+            .ok()
+            .filter(|c| c.logged_in.load(Ordering::Relaxed))
+        {
+            if view
+                .last_ping
+                .is_none_or(|at| at.elapsed() >= PING_INTERVAL)
+            {
+                view.last_ping = Some(Instant::now());
+                if let Err(e) = client.send_ping() {
+                    console::print_line(format!("Failed to ping: {}", e));
+                }
+            }
+        }
+        terminal.draw(|frame| draw(frame, repl.client().ok(), view))?;
+
+        if !event::poll(FRAME)? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            repl.execute_line("/quit").ok();
+            return Ok(());
+        }
+
+        if let Some(input) = view.input.as_mut() {
+            match key.code {
+                KeyCode::Enter => {
+                    let line = view.input.take().unwrap_or_default();
+                    match repl.execute_line(&line) {
+                        Ok(Flow::Quit) => return Ok(()),
+                        Ok(Flow::Continue) => (),
+                        Err(e) => console::print_line(e.to_string()),
+                    }
+                    // A new connection prints its updates unless told not to.
+                    if let Ok(client) = repl.client() {
+                        client.print_updates.store(false, Ordering::Relaxed);
+                    }
+                }
+                KeyCode::Esc => view.input = None,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => (),
+            }
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                repl.execute_line("/quit").ok();
+                return Ok(());
+            }
+            KeyCode::Enter => view.input = Some(String::new()),
+            _ => {
+                if let (Some(input), Ok(client)) = (movement(&key), repl.client()) {
+                    if let Err(e) = client.send_input(input) {
+                        console::print_line(format!("Failed to move: {}", e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, client: Option<&HostClient>, view: &View) {
+    let [top, log_area, bottom] = Layout::vertical([
+        Constraint::Min(8),
+        Constraint::Length(10),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [game_area, players_area] =
+        Layout::horizontal([Constraint::Min(20), Constraint::Length(28)]).areas(top);
+
+    let me = client.and_then(|c| c.id.lock().unwrap().clone());
+    let snapshot = client.and_then(|c| c.snapshot.lock().unwrap().clone());
+    let ping = client.and_then(|c| *c.ping.lock().unwrap());
+
+    draw_game(
+        frame,
+        game_area,
+        snapshot.as_ref(),
+        me.as_ref(),
+        ping,
+        client,
+    );
+    draw_players(frame, players_area, snapshot.as_ref(), me.as_ref());
+
+    let log = view.log.lock().unwrap();
+    let height = log_area.height.saturating_sub(2) as usize;
+    let lines: Vec<ListItem> = log
+        .iter()
+        .skip(log.len().saturating_sub(height))
+        .map(|line| ListItem::new(line.as_str()))
+        .collect();
+    frame.render_widget(
+        List::new(lines).block(Block::default().borders(Borders::ALL).title("Chat")),
+        log_area,
+    );
+
+    let prompt = match &view.input {
+        Some(input) => Line::from(vec![Span::raw("> "), Span::raw(input.as_str())]),
+        None => Line::styled(HELP, Style::default().fg(Color::DarkGray)),
+    };
+    frame.render_widget(Paragraph::new(prompt), bottom);
+    if let Some(input) = &view.input {
+        frame.set_cursor_position((bottom.x + 2 + input.chars().count() as u16, bottom.y));
+    }
+}
+
+fn draw_game(
+    frame: &mut Frame,
+    area: Rect,
+    snapshot: Option<&GameSnapshot>,
+    me: Option<&ClientID>,
+    ping: Option<Duration>,
+    client: Option<&HostClient>,
+) {
+    let ping = match ping {
+        Some(ping) => format!("{:.1}ms", ping.as_secs_f64() * 1000.0),
+        None => String::from("-"),
+    };
+    let title = match (snapshot, client) {
+        (Some(s), _) => format!(" Game {} | tick {} | ping {} ", s.game_id, s.tick, ping),
+        (None, Some(_)) => format!(" Not in a game, try /create | ping {} ", ping),
+        (None, None) => String::from(" Not connected, try /connect <addr> "),
+    };
+    let block = Block::default().borders(Borders::ALL).title(title);
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    if let Some(snapshot) = snapshot {
+        let rows = grid(snapshot, me, inner.width as usize, inner.height as usize);
+        let lines: Vec<Line> = rows
+            .into_iter()
+            .map(|row| {
+                Line::from(
+                    row.chars()
+                        .map(|c| match c {
+                            '.' => Span::styled(".", Style::default().fg(Color::DarkGray)),
+                            '@' => Span::styled(
+                                "@",
+                                Style::default()
+                                    .fg(Color::Yellow)
+                                    .add_modifier(Modifier::BOLD),
+                            ),
+                            c => Span::styled(c.to_string(), Style::default().fg(Color::Cyan)),
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines), inner);
+    }
+}
+
+fn draw_players(
+    frame: &mut Frame,
+    area: Rect,
+    snapshot: Option<&GameSnapshot>,
+    me: Option<&ClientID>,
+) {
+    let items: Vec<ListItem> = snapshot
+        .map(|s| {
+            s.entities
+                .iter()
+                .filter_map(|e| {
+                    let player = e.player.as_ref()?;
+                    let marker = if Some(player) == me { "@" } else { " " };
+                    Some(ListItem::new(format!(
+                        "{}{} ({:.0}, {:.0})",
+                        marker, player, e.x, e.y
+                    )))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    frame.render_widget(
+        List::new(items).block(Block::default().borders(Borders::ALL).title("Players")),
+        area,
+    );
+}
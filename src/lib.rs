@@ -3,6 +3,8 @@ pub mod comms;
 pub mod errors;
 pub mod game;
 pub mod host_side;
+pub mod logging;
+pub mod metrics;
 pub mod server_side;
 pub mod state;
 pub mod threading;
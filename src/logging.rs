@@ -0,0 +1,15 @@
+use tracing_subscriber::EnvFilter;
+
+/// Installs a subscriber which writes log events to stderr, so they don't mix with a client's
+/// console output. Filters are read from RUST_LOG, e.g. `RUST_LOG=multiplayer::server_side=debug`,
+/// falling back to `default_filter` when it isn't set.
+///
+/// Does nothing if a subscriber is already installed.
+pub fn init(default_filter: &str) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter));
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .try_init();
+}
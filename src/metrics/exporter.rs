@@ -0,0 +1,54 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::metrics::registry::registry;
+
+/// How long a scrape may take to send its request before it is dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves the metrics of this process over HTTP in the Prometheus text format, from a thread of
+/// its own so scrapes never wait on the server's thread pool. Every path returns the metrics.
+///
+/// # Arguments
+///
+/// * 'addr' - The address to listen on, e.g. "127.0.0.1:9100". Metrics aren't authenticated, so
+///   this should be a local address.
+///
+/// # Returns
+///
+/// * An error if the address can't be bound.
+pub fn serve(addr: &str) -> io::Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(addr)?;
+    info!(%addr, "Serving metrics");
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = respond(stream) {
+                        warn!(error = %e, "Failed to serve metrics");
+                    }
+                }
+                Err(e) => warn!(error = %e, "Failed to accept metrics connection"),
+            }
+        }
+    }))
+}
+
+fn respond(mut stream: TcpStream) -> io::Result<()> {
+    // The request itself doesn't matter, but it must be read before the connection is closed.
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut buff = [0; 1024];
+    let _ = stream.read(&mut buff)?;
+
+    let body = registry().render();
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(header.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()
+}
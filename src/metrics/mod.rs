@@ -0,0 +1,3 @@
+pub mod exporter;
+pub mod registry;
+pub mod scrape;
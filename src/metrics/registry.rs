@@ -0,0 +1,347 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of the buckets tick durations are counted in.
+pub const TICK_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1,
+];
+
+/// A value which only goes up.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value which goes up and down.
+#[derive(Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The label counted in place of one which isn't known, or once a counter has MAX_LABELS.
+pub const UNKNOWN_LABEL: &str = "unknown";
+/// The most labels a LabeledCounter keeps apart.
+pub const MAX_LABELS: usize = 128;
+
+/// Counters split by the value of a single label, such as a message type.
+#[derive(Default)]
+pub struct LabeledCounter(Mutex<BTreeMap<String, u64>>);
+
+impl LabeledCounter {
+    pub fn inc(&self, label: &str) {
+        let mut counts = self.0.lock().unwrap();
+        if let Some(count) = counts.get_mut(label) {
+            *count += 1;
+            return;
+        }
+        let label = match counts.len() < MAX_LABELS {
+            true => label,
+            false => UNKNOWN_LABEL,
+        };
+        *counts.entry(label.to_owned()).or_insert(0) += 1;
+    }
+
+    pub fn get(&self, label: &str) -> u64 {
+        self.0.lock().unwrap().get(label).cloned().unwrap_or(0)
+    }
+}
+
+/// Counts durations into buckets, keeping their total so the mean can be worked out.
+pub struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(i) = self.bounds.iter().position(|bound| secs <= *bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// Upper bounds, in seconds, of the buckets job wait times are counted in.
+pub const WAIT_BUCKETS: [f64; 10] = [
+    0.00001, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0,
+];
+
+/// Every metric the library records. Counters are updated where things happen, so there is a
+/// single registry per process, reached through `registry()`.
+/// * clients_connected - Clients connected to the server.
+/// * games_running - Games run by the server.
+/// * messages_received - Messages handled, by msg_type.
+/// * messages_sent - Messages sent, by msg_type.
+/// * bytes_received - Bytes read from client sockets.
+/// * bytes_sent - Bytes written to sockets.
+/// * tick_duration - How long running a game's systems once took.
+/// * jobs_queued - Jobs sent to a thread pool which no worker has picked up yet.
+/// * job_wait - How long jobs waited for a worker.
+/// * jobs_stolen - Jobs a worker took from another worker's queue.
+/// * workers_busy - Workers running a job. Looping jobs keep their worker busy until they end.
+/// * jobs_executed - Jobs picked up by a worker.
+/// * jobs_panicked - Jobs which panicked. The worker carries on with the next job.
+/// * workers_live - Worker threads running.
+/// * workers_respawned - Workers started again after their thread died.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::metrics::registry::Registry;
+///
+/// let registry = Registry::new();
+/// registry.messages_received.inc("Chat");
+/// registry.clients_connected.set(3);
+///
+/// let text = registry.render();
+/// assert!(text.contains("multiplayer_messages_received_total{msg_type=\"Chat\"} 1"));
+/// assert!(text.contains("multiplayer_clients_connected 3"));
+/// ```
+pub struct Registry {
+    pub clients_connected: Gauge,
+    pub games_running: Gauge,
+    pub messages_received: LabeledCounter,
+    pub messages_sent: LabeledCounter,
+    pub bytes_received: Counter,
+    pub bytes_sent: Counter,
+    pub tick_duration: Histogram,
+    pub jobs_queued: Gauge,
+    pub job_wait: Histogram,
+    pub jobs_stolen: Counter,
+    pub workers_busy: Gauge,
+    pub jobs_executed: Counter,
+    pub jobs_panicked: Counter,
+    pub workers_live: Gauge,
+    pub workers_respawned: Counter,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry {
+            clients_connected: Gauge::default(),
+            games_running: Gauge::default(),
+            messages_received: LabeledCounter::default(),
+            messages_sent: LabeledCounter::default(),
+            bytes_received: Counter::default(),
+            bytes_sent: Counter::default(),
+            tick_duration: Histogram::new(&TICK_BUCKETS),
+            jobs_queued: Gauge::default(),
+            job_wait: Histogram::new(&WAIT_BUCKETS),
+            jobs_stolen: Counter::default(),
+            workers_busy: Gauge::default(),
+            jobs_executed: Counter::default(),
+            jobs_panicked: Counter::default(),
+            workers_live: Gauge::default(),
+            workers_respawned: Counter::default(),
+        }
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        render_gauge(
+            &mut out,
+            "clients_connected",
+            "Clients connected to the server.",
+            &self.clients_connected,
+        );
+        render_gauge(
+            &mut out,
+            "games_running",
+            "Games run by the server.",
+            &self.games_running,
+        );
+        render_labeled(
+            &mut out,
+            "messages_received_total",
+            "Messages handled, by type.",
+            &self.messages_received,
+        );
+        render_labeled(
+            &mut out,
+            "messages_sent_total",
+            "Messages sent, by type.",
+            &self.messages_sent,
+        );
+        render_counter(
+            &mut out,
+            "bytes_received_total",
+            "Bytes read from client sockets.",
+            &self.bytes_received,
+        );
+        render_counter(
+            &mut out,
+            "bytes_sent_total",
+            "Bytes written to sockets.",
+            &self.bytes_sent,
+        );
+        render_histogram(
+            &mut out,
+            "tick_duration_seconds",
+            "Time taken to run a game's systems once.",
+            &self.tick_duration,
+        );
+        render_gauge(
+            &mut out,
+            "jobs_queued",
+            "Jobs waiting for a worker.",
+            &self.jobs_queued,
+        );
+        render_histogram(
+            &mut out,
+            "job_wait_seconds",
+            "Time jobs waited for a worker.",
+            &self.job_wait,
+        );
+        render_counter(
+            &mut out,
+            "jobs_stolen_total",
+            "Jobs taken from another worker's queue.",
+            &self.jobs_stolen,
+        );
+        render_gauge(
+            &mut out,
+            "workers_busy",
+            "Workers running a job.",
+            &self.workers_busy,
+        );
+        render_counter(
+            &mut out,
+            "jobs_executed_total",
+            "Jobs picked up by a worker.",
+            &self.jobs_executed,
+        );
+        render_counter(
+            &mut out,
+            "jobs_panicked_total",
+            "Jobs which panicked.",
+            &self.jobs_panicked,
+        );
+        render_gauge(
+            &mut out,
+            "workers_live",
+            "Worker threads running.",
+            &self.workers_live,
+        );
+        render_counter(
+            &mut out,
+            "workers_respawned_total",
+            "Workers started again after their thread died.",
+            &self.workers_respawned,
+        );
+        out
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the registry of this process.
+pub fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::new)
+}
+
+const PREFIX: &str = "multiplayer_";
+
+fn render_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {}{} {}", PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}{} {}", PREFIX, name, kind);
+}
+
+fn render_counter(out: &mut String, name: &str, help: &str, counter: &Counter) {
+    render_header(out, name, help, "counter");
+    let _ = writeln!(out, "{}{} {}", PREFIX, name, counter.get());
+}
+
+fn render_gauge(out: &mut String, name: &str, help: &str, gauge: &Gauge) {
+    render_header(out, name, help, "gauge");
+    let _ = writeln!(out, "{}{} {}", PREFIX, name, gauge.get());
+}
+
+fn render_labeled(out: &mut String, name: &str, help: &str, counter: &LabeledCounter) {
+    render_header(out, name, help, "counter");
+    for (label, count) in counter.0.lock().unwrap().iter() {
+        let label = label
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        let _ = writeln!(
+            out,
+            "{}{}{{msg_type=\"{}\"}} {}",
+            PREFIX, name, label, count
+        );
+    }
+}
+
+fn render_histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    render_header(out, name, help, "histogram");
+    // Prometheus buckets are cumulative.
+    let mut cumulative = 0;
+    for (bound, bucket) in histogram.bounds.iter().zip(histogram.buckets.iter()) {
+        cumulative += bucket.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "{}{}_bucket{{le=\"{}\"}} {}",
+            PREFIX, name, bound, cumulative
+        );
+    }
+    let count = histogram.count();
+    let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+    let _ = writeln!(out, "{}{}_bucket{{le=\"+Inf\"}} {}", PREFIX, name, count);
+    let _ = writeln!(out, "{}{}_sum {}", PREFIX, name, sum);
+    let _ = writeln!(out, "{}{}_count {}", PREFIX, name, count);
+}
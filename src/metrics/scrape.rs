@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// How long to wait for a metrics endpoint to answer.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Fetches the metrics a server exposes, as served by `exporter::serve`.
+///
+/// # Arguments
+///
+/// * 'addr' - The address metrics are served on, e.g. "127.0.0.1:9100".
+///
+/// # Returns
+///
+/// * The body of the response, in the Prometheus text format.
+/// * An error if the endpoint can't be reached, or doesn't answer with 200 OK.
+pub fn fetch(addr: &str) -> io::Result<String> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    // One write, as the exporter answers after its first read.
+    let request = format!(
+        "GET /metrics HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        addr
+    );
+    stream.write_all(request.as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed metrics response"))?;
+    if !head.starts_with("HTTP/1.1 200") {
+        let status = head.lines().next().unwrap_or_default();
+        return Err(io::Error::other(format!(
+            "Metrics endpoint answered {}",
+            status
+        )));
+    }
+    Ok(body.to_owned())
+}
+
+/// Reads every sample in the Prometheus text format, adding up the samples of each metric across
+/// its labels. Comments and lines which aren't samples are skipped.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::metrics::scrape::totals;
+///
+/// let text = "\
+/// # TYPE multiplayer_messages_sent_total counter
+/// multiplayer_messages_sent_total{msg_type=\"Chat\"} 3
+/// multiplayer_messages_sent_total{msg_type=\"GameSnapshot\"} 40
+/// multiplayer_clients_connected 2
+/// ";
+/// let totals = totals(text);
+///
+/// assert_eq!(totals["multiplayer_messages_sent_total"], 43.0);
+/// assert_eq!(totals["multiplayer_clients_connected"], 2.0);
+/// ```
+pub fn totals(text: &str) -> BTreeMap<String, f64> {
+    let mut totals = BTreeMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (series, value) = match line.rsplit_once(' ') {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let value: f64 = match value.parse() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        let name = series.split('{').next().unwrap_or(series).trim();
+        *totals.entry(name.to_owned()).or_insert(0.0) += value;
+    }
+    totals
+}
@@ -0,0 +1,354 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, info_span, warn};
+
+use crate::comms::admin::{
+    AdminCommand, AdminLogin, AdminRequest, AdminResponse, ClientSummary, MessageReader,
+    ADMIN_LOGIN_IDENTIFIER, ADMIN_REQUEST_IDENTIFIER,
+};
+use crate::comms::message;
+use crate::errors::AdminError;
+use crate::game::model::GameState;
+use crate::game::GameID;
+use crate::server_side::chat;
+use crate::server_side::client::ClientID;
+use crate::server_side::game_actor;
+use crate::server_side::lobby;
+use crate::server_side::server::{ClientHashmap, ServerContext};
+use crate::state::State;
+
+/// The most admin connections served at once. Each is served by a thread of its own.
+pub const MAX_ADMIN_SESSIONS: usize = 2;
+/// How long a new admin connection has to log in.
+pub const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a logged in admin connection may be idle before it is closed.
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Where the admin port listens, and the token operators must log in with.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    pub addr: String,
+    pub token: String,
+}
+
+/// Clients and addresses which may not connect.
+#[derive(Default)]
+pub struct BanList {
+    clients: HashSet<ClientID>,
+    ips: HashSet<IpAddr>,
+}
+
+pub type BanHandle = Arc<Mutex<BanList>>;
+
+impl BanList {
+    pub fn new() -> BanList {
+        BanList::default()
+    }
+
+    pub fn is_client_banned(&self, client_id: &ClientID) -> bool {
+        self.clients.contains(client_id)
+    }
+
+    pub fn is_ip_banned(&self, ip: &IpAddr) -> bool {
+        self.ips.contains(ip)
+    }
+}
+
+/// Accepts admin connections until the listener fails. Each connection is served by a thread of
+/// its own, so admin sessions never hold the server's workers, and connections past
+/// MAX_ADMIN_SESSIONS are refused. Blocks, so it should be run on a thread of its own.
+pub fn listen(config: AdminConfig, context: ServerContext) {
+    let listener = match TcpListener::bind(&config.addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!(addr = %config.addr, error = %e, "Failed to bind admin port");
+            return;
+        }
+    };
+    info!(addr = %config.addr, "Admin port listening");
+
+    let sessions = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(mut stream) => {
+                let session = match Session::open(&sessions) {
+                    Some(session) => session,
+                    None => {
+                        warn!("Admin connection refused, too many sessions");
+                        let refused = AdminResponse::Error(String::from("Too many admin sessions"));
+                        let _ = message::try_send_json(&refused, &mut stream);
+                        continue;
+                    }
+                };
+                let token = config.token.clone();
+                let context = context.clone();
+                let spawned = thread::Builder::new()
+                    .name(String::from("admin_session"))
+                    .spawn(move || {
+                        serve(stream, &token, &context);
+                        drop(session);
+                    });
+                if let Err(e) = spawned {
+                    warn!(error = %e, "Failed to spawn admin session thread");
+                }
+            }
+            Err(e) => warn!(error = %e, "Failed to accept admin connection"),
+        }
+    }
+}
+
+/// Runs the commands of a single admin connection until it closes. The first message must be an
+/// AdminLogin with the right token.
+fn serve(mut socket: TcpStream, token: &str, context: &ServerContext) {
+    let peer = socket
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+    let span = info_span!("admin", %peer);
+    let _enter = span.enter();
+
+    let mut reader = match socket.try_clone() {
+        Ok(reading) => MessageReader::new(reading),
+        Err(e) => {
+            warn!(error = %e, "Failed to clone admin socket");
+            return;
+        }
+    };
+    if let Err(e) = socket.set_read_timeout(Some(LOGIN_TIMEOUT)) {
+        warn!(error = %e, "Failed to set admin login timeout");
+        return;
+    }
+    let authenticated = match reader.next_message() {
+        Ok(Some((msg_type, data))) if msg_type == ADMIN_LOGIN_IDENTIFIER => {
+            serde_json::from_value::<AdminLogin>(data)
+                .is_ok_and(|login| tokens_match(&login.token, token))
+        }
+        _ => false,
+    };
+    if !authenticated {
+        warn!("Admin login refused");
+        let refused = AdminResponse::Error(String::from("Login refused"));
+        let _ = message::try_send_json(&refused, &mut socket);
+        return;
+    }
+    info!("Admin logged in");
+    if let Err(e) = socket.set_read_timeout(Some(SESSION_TIMEOUT)) {
+        warn!(error = %e, "Failed to set admin session timeout");
+        return;
+    }
+    let _ = message::try_send_json(&AdminResponse::Done(String::from("Logged in")), &mut socket);
+
+    loop {
+        let response = match reader.next_message() {
+            Ok(Some((msg_type, data))) if msg_type == ADMIN_REQUEST_IDENTIFIER => {
+                match serde_json::from_value::<AdminRequest>(data) {
+                    Ok(request) => {
+                        info!(command = ?request.command, "Admin command");
+                        run(request.command, context)
+                            .unwrap_or_else(|e| AdminResponse::Error(e.to_string()))
+                    }
+                    Err(e) => AdminResponse::Error(format!("Invalid AdminRequest: {}", e)),
+                }
+            }
+            Ok(Some((msg_type, _))) => {
+                AdminResponse::Error(format!("Unexpected message {}", msg_type))
+            }
+            Ok(None) => break,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                info!("Admin session timed out");
+                break;
+            }
+            Err(e) => {
+                warn!(error = %e, "Admin connection failed");
+                break;
+            }
+        };
+        if message::try_send_json(&response, &mut socket).is_err() {
+            break;
+        }
+    }
+    info!("Admin disconnected");
+}
+
+/// Counts an admin session as open until dropped.
+struct Session {
+    open: Arc<AtomicUsize>,
+}
+
+impl Session {
+    /// Opens a session, or returns None if MAX_ADMIN_SESSIONS are already open.
+    fn open(open: &Arc<AtomicUsize>) -> Option<Session> {
+        open.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+            (n < MAX_ADMIN_SESSIONS).then_some(n + 1)
+        })
+        .ok()?;
+        Some(Session {
+            open: Arc::clone(open),
+        })
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.open.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Compares tokens without stopping at the first difference, so their contents can't be guessed
+/// from how long the comparison took.
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Runs an admin command against the server.
+///
+/// # Returns
+///
+/// * AdminError if the command's client or game doesn't exist, or the game can't change state.
+pub fn run(command: AdminCommand, context: &ServerContext) -> Result<AdminResponse, AdminError> {
+    match command {
+        AdminCommand::ListClients => Ok(AdminResponse::Clients(client_list(&context.clients))),
+        AdminCommand::ListGames => Ok(AdminResponse::Games(lobby::game_list(&context.games).games)),
+        AdminCommand::Kick(client_id) => {
+            if !disconnect(&client_id, "You were kicked", &context.clients) {
+                return Err(AdminError {
+                    reason: format!("Client {} is not connected", client_id),
+                });
+            }
+            Ok(AdminResponse::Done(format!("Kicked {}", client_id)))
+        }
+        AdminCommand::Ban(client_id) => {
+            context
+                .bans
+                .lock()
+                .unwrap()
+                .clients
+                .insert(client_id.clone());
+            disconnect(&client_id, "You were banned", &context.clients);
+            Ok(AdminResponse::Done(format!("Banned {}", client_id)))
+        }
+        AdminCommand::BanIp(ip) => {
+            context.bans.lock().unwrap().ips.insert(ip);
+            let connected: Vec<_> = {
+                let clients = context.clients.lock().unwrap();
+                clients
+                    .values()
+                    .filter(|c| c.addr().is_some_and(|a| a.ip() == ip))
+                    .map(|c| c.id.clone())
+                    .collect()
+            };
+            for client_id in connected.iter() {
+                disconnect(client_id, "You were banned", &context.clients);
+            }
+            Ok(AdminResponse::Done(format!(
+                "Banned {}, disconnecting {} client(s)",
+                ip,
+                connected.len()
+            )))
+        }
+        AdminCommand::Unban(client_id) => {
+            if !context.bans.lock().unwrap().clients.remove(&client_id) {
+                return Err(AdminError {
+                    reason: format!("Client {} isn't banned", client_id),
+                });
+            }
+            Ok(AdminResponse::Done(format!("Unbanned {}", client_id)))
+        }
+        AdminCommand::UnbanIp(ip) => {
+            if !context.bans.lock().unwrap().ips.remove(&ip) {
+                return Err(AdminError {
+                    reason: format!("{} isn't banned", ip),
+                });
+            }
+            Ok(AdminResponse::Done(format!("Unbanned {}", ip)))
+        }
+        AdminCommand::Pause(game_id) => {
+            set_game_state(game_id, GameState::Paused, context)?;
+            Ok(AdminResponse::Done(format!("Paused game {}", game_id)))
+        }
+        AdminCommand::Resume(game_id) => {
+            set_game_state(game_id, GameState::Active, context)?;
+            Ok(AdminResponse::Done(format!("Resumed game {}", game_id)))
+        }
+        AdminCommand::Broadcast(text) => {
+            let clients = context.clients.lock().unwrap();
+            chat::announce(text, &clients);
+            Ok(AdminResponse::Done(format!(
+                "Broadcast to {} client(s)",
+                clients.len()
+            )))
+        }
+    }
+}
+
+/// Returns a summary of every connected client, sorted by ClientID.
+pub fn client_list(clients: &ClientHashmap) -> Vec<ClientSummary> {
+    let clients = clients.lock().unwrap();
+    let mut summaries: Vec<_> = clients
+        .values()
+        .map(|c| ClientSummary {
+            client_id: c.id.clone(),
+            state: c.state(),
+            game_id: c.game_id,
+            addr: c.addr().map(|a| a.to_string()),
+        })
+        .collect();
+    summaries.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+    summaries
+}
+
+/// Tells a client why they are being disconnected, then closes their socket. The client's
+/// listener notices the socket closing and removes them from the server.
+///
+/// # Returns
+///
+/// * False if the client isn't connected.
+pub fn disconnect(client_id: &ClientID, reason: &str, clients: &ClientHashmap) -> bool {
+    let clients = clients.lock().unwrap();
+    let client = match clients.get(client_id) {
+        Some(client) => client,
+        None => return false,
+    };
+    chat::notify(client_id, reason, &clients);
+    if let Some(socket) = &client.socket {
+        let _ = socket.shutdown(Shutdown::Both);
+    }
+    true
+}
+
+/// Pauses or resumes a game.
+///
+/// # Returns
+///
+/// * AdminError if the game doesn't exist, or is waiting for players.
+fn set_game_state(
+    game_id: GameID,
+    state: GameState,
+    context: &ServerContext,
+) -> Result<(), AdminError> {
+    let game = game_actor::find(&context.games, game_id).ok_or_else(|| AdminError {
+        reason: format!("Game {} does not exist", game_id),
+    })?;
+    game.call(move |game_id, game| {
+        if let GameState::PendingPlayers(_) = game.model.state() {
+            return Err(AdminError {
+                reason: format!("Game {} is waiting for players", game_id),
+            });
+        }
+        game.change_state(state).map_err(|e| AdminError {
+            reason: e.to_string(),
+        })
+    })
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::comms::message::{ChatBroadcast, ChatChannel, ChatMessage};
+use crate::errors::ChatError;
+use crate::game::GameID;
+use crate::server_side::client::{Client, ClientID, ClientState};
+
+/// Returns the number of seconds since the UNIX epoch.
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Relays a chat message from a client to every recipient of its channel.
+///
+/// # Arguments
+///
+/// * 'sender' - The ClientID of the client who sent the message.
+/// * 'msg' - The ChatMessage to relay.
+/// * 'clients' - The connected clients.
+///
+/// # Returns
+///
+/// * ChatError if the sender isn't allowed to use the channel, or the recipient doesn't exist.
+pub fn relay(
+    sender: &ClientID,
+    msg: ChatMessage,
+    clients: &HashMap<ClientID, Client>,
+) -> Result<(), ChatError> {
+    let sender_client = clients.get(sender).ok_or_else(|| ChatError {
+        reason: format!("Client {} is not connected", sender),
+    })?;
+
+    let broadcast = ChatBroadcast {
+        channel: msg.channel.clone(),
+        sender: Some(sender.clone()),
+        text: msg.text,
+        timestamp: timestamp(),
+    };
+
+    match &msg.channel {
+        ChatChannel::Lobby => {
+            deliver(&broadcast, clients.values());
+            Ok(())
+        }
+        ChatChannel::Game(game_id) => {
+            if sender_client.state() == ClientState::Spectating {
+                return Err(ChatError {
+                    reason: format!("Spectators can't chat in game {}", game_id),
+                });
+            }
+            if sender_client.game_id != Some(*game_id) {
+                return Err(ChatError {
+                    reason: format!("Client {} is not in game {}", sender, game_id),
+                });
+            }
+            deliver(&broadcast, in_game(*game_id, clients));
+            Ok(())
+        }
+        ChatChannel::Whisper(target) => {
+            let target_client = clients.get(target).ok_or_else(|| ChatError {
+                reason: format!("Client {} is not connected", target),
+            })?;
+            deliver(&broadcast, vec![target_client, sender_client]);
+            Ok(())
+        }
+        ChatChannel::System => Err(ChatError {
+            reason: String::from("Clients can't send on the System channel"),
+        }),
+    }
+}
+
+/// Sends a server originated announcement to every connected client.
+pub fn announce<S: Into<String>>(text: S, clients: &HashMap<ClientID, Client>) {
+    deliver(&system_broadcast(text), clients.values());
+}
+
+/// Sends a server originated announcement to every client in a game.
+pub fn announce_game<S: Into<String>>(
+    game_id: GameID,
+    text: S,
+    clients: &HashMap<ClientID, Client>,
+) {
+    deliver(&system_broadcast(text), in_game(game_id, clients));
+}
+
+/// Sends a server originated message to a single client.
+pub fn notify<S: Into<String>>(client_id: &ClientID, text: S, clients: &HashMap<ClientID, Client>) {
+    if let Some(client) = clients.get(client_id) {
+        deliver(&system_broadcast(text), vec![client]);
+    }
+}
+
+fn system_broadcast<S: Into<String>>(text: S) -> ChatBroadcast {
+    ChatBroadcast {
+        channel: ChatChannel::System,
+        sender: None,
+        text: text.into(),
+        timestamp: timestamp(),
+    }
+}
+
+fn in_game(game_id: GameID, clients: &HashMap<ClientID, Client>) -> Vec<&Client> {
+    clients
+        .values()
+        .filter(|c| c.game_id == Some(game_id))
+        .collect()
+}
+
+/// Writes a broadcast to each recipient.
+fn deliver<'a, I>(broadcast: &ChatBroadcast, recipients: I)
+where
+    I: IntoIterator<Item = &'a Client>,
+{
+    for client in recipients {
+        client.send(broadcast);
+    }
+}
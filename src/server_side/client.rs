@@ -1,11 +1,17 @@
-use std::collections::HashSet;
-use std::net::TcpStream;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
 
 use crate::comms::handler::{Handler, TryClone};
-// use crate::comms::message;
+use crate::comms::message;
+use crate::errors::StateError;
 use crate::game::GameID;
-use crate::state::State;
+use crate::server_side::server::ClientHashmap;
+use crate::state::{State, StateMachine, Transitions};
 
 pub type ClientID = String;
 pub type ClientCollection = Arc<Mutex<HashSet<ClientID>>>;
@@ -14,52 +20,174 @@ pub type ClientCollection = Arc<Mutex<HashSet<ClientID>>>;
 /// * Waiting - In lobby, not playing any game
 /// * PendingGame - In lobby, waiting for other players
 /// * InGame - Actively playing the game
-#[derive(Clone, Copy)]
+/// * Spectating - Watching a game without playing in it
+///
+/// Clients in a game, or watching one, must return to Waiting before doing anything else.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
 pub enum ClientState {
     Waiting,
     PendingGame,
     InGame,
+    Spectating,
+}
+
+impl Transitions for ClientState {
+    fn can_transition_to(&self, next: &ClientState) -> bool {
+        matches!(
+            (self, next),
+            (ClientState::Waiting, _)
+                | (ClientState::PendingGame, _)
+                | (ClientState::InGame, ClientState::Waiting)
+                | (ClientState::Spectating, ClientState::Waiting)
+        )
+    }
 }
 
 /// Describes a server-side client
 /// * id - Unique identifier
 /// * message_handler - A ClientHandler to distribue and parse incoming and out going messages.
-/// * game_id - The GameID of the game the client is currently playing. None if state is Waiting.
+/// * game_id - The GameID of the game the client is currently playing or spectating. None if state is Waiting.
 /// * state - The state of the client
+/// * dropped - Set once a write to the client times out. Shared by every clone of the client.
 pub struct Client {
     pub id: ClientID,
     pub socket: Option<TcpStream>,
     pub game_id: Option<GameID>,
-    pub state: ClientState,
+    state: StateMachine<ClientState>,
+    dropped: Arc<AtomicBool>,
+}
+
+impl Client {
+    /// Returns a client in the Waiting state.
+    pub fn new(id: ClientID, socket: Option<TcpStream>) -> Client {
+        Client {
+            id,
+            socket,
+            game_id: None,
+            state: StateMachine::new(ClientState::Waiting),
+            dropped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn state(&self) -> ClientState {
+        self.state.state()
+    }
+
+    /// Returns true if the client may move to `next`.
+    pub fn can_change_state(&self, next: &ClientState) -> bool {
+        self.state.can_transition_to(next)
+    }
+
+    /// Returns the StateMachine driving the client, to register callbacks or subscribe to its
+    /// transitions.
+    pub fn state_machine(&mut self) -> &mut StateMachine<ClientState> {
+        &mut self.state
+    }
+
+    /// Returns the address the client connected from.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.socket.as_ref().and_then(|s| s.peer_addr().ok())
+    }
+
+    /// Whether the client was dropped for not reading what it is sent. Its listener stops, even
+    /// if the client still has messages waiting to be read.
+    pub fn is_dropped(&self) -> bool {
+        self.dropped.load(Ordering::Acquire)
+    }
+
+    /// Sends a message to the client. Failing to write is logged rather than panicking, since the
+    /// client's listener will notice the disconnect and remove it.
+    pub fn send<M: message::Message<'static>>(&self, msg: &M) {
+        if let Some(mut sender) = self.sender() {
+            sender.send(msg);
+        }
+    }
+
+    /// Returns a sender which writes to the client's socket, None if the client has no socket or
+    /// it couldn't be cloned.
+    pub fn sender(&self) -> Option<ClientSender> {
+        let socket = self.socket.as_ref()?.try_clone().ok()?;
+        Some(ClientSender {
+            id: self.id.clone(),
+            socket,
+            dropped: Arc::clone(&self.dropped),
+        })
+    }
+}
+
+/// The socket of a client, cloned out of the ClientHashmap so messages can be written to it
+/// without holding the lock.
+pub struct ClientSender {
+    pub id: ClientID,
+    socket: TcpStream,
+    dropped: Arc<AtomicBool>,
+}
+
+impl ClientSender {
+    /// Sends a message to the client. A client whose write times out isn't reading what it is
+    /// sent, so it is marked dropped and its socket is shut down, which ends its listener and
+    /// removes it. Nothing more is written to a dropped client. Other failures are only logged,
+    /// since the listener notices the disconnect too.
+    pub fn send<M: message::Message<'static>>(&mut self, msg: &M) {
+        if self.dropped.load(Ordering::Acquire) {
+            return;
+        }
+        if let Err(e) = message::try_send_json(msg, &mut self.socket) {
+            match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                    self.dropped.store(true, Ordering::Release);
+                    info!(client_id = %self.id, msg_type = M::MSG_TYPE, "Write timed out, dropping client");
+                    let _ = self.socket.shutdown(Shutdown::Both);
+                }
+                _ => {
+                    warn!(client_id = %self.id, msg_type = M::MSG_TYPE, error = %e, "Failed to send message")
+                }
+            }
+        }
+    }
+}
+
+/// Returns a sender to each of the clients which is still connected. The ClientHashmap is only
+/// held while their sockets are cloned, so messages are written after it is released and a slow
+/// client never holds up everyone else.
+pub fn senders<'a, I>(clients: &ClientHashmap, ids: I) -> HashMap<ClientID, ClientSender>
+where
+    I: IntoIterator<Item = &'a ClientID>,
+{
+    let clients = clients.lock().unwrap();
+    ids.into_iter()
+        .filter_map(|id| clients.get(id).and_then(Client::sender))
+        .map(|sender| (sender.id.clone(), sender))
+        .collect()
 }
 
 impl TryClone for Client {
-    // Function to attempt to clone a Client.
+    // Function to attempt to clone a Client. The clone starts in the same state, without the
+    // callbacks and subscribers of the original.
     fn try_clone(&self) -> std::io::Result<Client> {
         let id = self.id.clone();
-        let state = self.state.clone();
-        let game_id = self.game_id.clone();
+        let state = StateMachine::new(self.state());
+        let game_id = self.game_id;
         let mut socket = None;
-        match &self.socket {
-            Some(sock) => {
-                socket = Some(sock.try_clone()?);
-            }
-            None => (),
-        };
+        if let Some(sock) = &self.socket {
+            socket = Some(sock.try_clone()?);
+        }
 
         Ok(Client {
             id,
             state,
             socket,
             game_id,
+            dropped: Arc::clone(&self.dropped),
         })
     }
 }
 
 impl State for Client {
     type StateEnum = ClientState;
-    fn change_state(&mut self, new_state: ClientState) {
-        self.state = new_state;
+    fn change_state(&mut self, new_state: ClientState) -> Result<(), StateError> {
+        self.state.transition(new_state)?;
+        Ok(())
     }
 }
 
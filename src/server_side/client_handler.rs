@@ -0,0 +1,655 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::comms::handler::{Handler, TryClone};
+use crate::comms::message;
+use crate::errors::PartyError;
+use crate::game::controller::GameController;
+use crate::game::model::GameState;
+use crate::game::pause::PauseRules;
+use crate::game::GameID;
+use crate::server_side::chat;
+use crate::server_side::client::{ClientID, ClientState};
+use crate::server_side::config::{Features, Limits};
+use crate::server_side::game_actor;
+use crate::server_side::lobby;
+use crate::server_side::matchmaking::{MatchTicket, MatchmakerHandle};
+use crate::server_side::party::PartyHandle;
+use crate::server_side::pause;
+use crate::server_side::relay::{self, HostedGameHandle};
+use crate::server_side::replays::{self, ReplayViewers};
+use crate::server_side::server::{ClientHashmap, GameHashmap, ServerContext};
+use crate::state::State;
+
+/// Handles messages received from a connected client on the server.
+/// * client_id - The ClientID of the client the messages are received from.
+/// * clients - The ClientHashmap of the server.
+/// * games - The GameHashmap of the server.
+/// * matchmaker - The matchmaking queue of the server.
+/// * parties - The parties of the server.
+/// * hosted - The peer-hosted games the server relays for.
+/// * replays - The replay each client is watching.
+/// * replay_dir - Where the server records replays. None if it doesn't.
+/// * pause_rules - How players vote to pause and resume their games.
+/// * tick_interval - How often games tick, which replays play back at.
+/// * features - Parts of the server the client may use.
+/// * limits - How many games the server runs.
+pub struct ClientHandler {
+    pub client_id: ClientID,
+    pub clients: ClientHashmap,
+    pub games: GameHashmap,
+    pub matchmaker: MatchmakerHandle,
+    pub parties: PartyHandle,
+    pub hosted: HostedGameHandle,
+    pub replays: ReplayViewers,
+    pub replay_dir: Option<PathBuf>,
+    pub pause_rules: PauseRules,
+    pub tick_interval: Duration,
+    pub features: Features,
+    pub limits: Limits,
+}
+
+impl ClientHandler {
+    pub fn new(client_id: ClientID, context: &ServerContext) -> ClientHandler {
+        ClientHandler {
+            client_id,
+            clients: Arc::clone(&context.clients),
+            games: Arc::clone(&context.games),
+            matchmaker: Arc::clone(&context.matchmaker),
+            parties: Arc::clone(&context.parties),
+            hosted: Arc::clone(&context.hosted),
+            replays: Arc::clone(&context.replays),
+            replay_dir: context.replay_dir.clone(),
+            pause_rules: context.pause_rules,
+            tick_interval: context.tick_interval,
+            features: context.features,
+            limits: context.limits,
+        }
+    }
+
+    /// Returns the clients who act with this client: the whole party when the client leads one,
+    /// or just the client when they aren't in a party.
+    ///
+    /// # Returns
+    ///
+    /// * PartyError if the client is in a party but doesn't lead it.
+    fn group(&self) -> Result<Vec<ClientID>, PartyError> {
+        let parties = self.parties.lock().unwrap();
+        match parties.party_of(&self.client_id) {
+            Some(party) if party.leader != self.client_id => Err(PartyError {
+                reason: format!("Only the party leader {} can do that", party.leader),
+            }),
+            _ => Ok(parties.members_of(&self.client_id)),
+        }
+    }
+
+    /// Takes a group out of the matchmaking queue before it joins a game by hand, so the
+    /// matchmaker can't place it in a second game.
+    fn leave_queue(&self, members: &[ClientID]) {
+        for member in members {
+            lobby::cancel_matchmaking(member, &self.matchmaker, &self.clients);
+        }
+    }
+
+    /// Returns the leader of the client's party.
+    fn leader(&self) -> Option<ClientID> {
+        let parties = self.parties.lock().unwrap();
+        parties.party_of(&self.client_id).map(|p| p.leader.clone())
+    }
+
+    /// Returns the game the client is playing or spectating.
+    fn game_id(&self) -> Option<GameID> {
+        let clients = self.clients.lock().unwrap();
+        clients.get(&self.client_id).and_then(|c| c.game_id)
+    }
+
+    /// Votes for the client's game to be paused or resumed.
+    fn vote_state(&self, state: GameState) {
+        if !self.allowed(self.features.pause_votes, "Pause voting") {
+            return;
+        }
+        if let Err(e) = pause::vote(
+            &self.client_id,
+            state,
+            &self.pause_rules,
+            &self.clients,
+            &self.games,
+        ) {
+            self.notify(e.to_string());
+        }
+    }
+
+    /// Returns true if a feature is on, or tells the client it is off.
+    fn allowed(&self, enabled: bool, feature: &str) -> bool {
+        if !enabled {
+            self.notify(format!("{} is turned off on this server", feature));
+        }
+        enabled
+    }
+
+    /// Sends a system message to the client.
+    fn notify<S: Into<String>>(&self, text: S) {
+        let clients = self.clients.lock().unwrap();
+        chat::notify(&self.client_id, text, &clients);
+    }
+}
+
+impl TryClone for ClientHandler {
+    fn try_clone(&self) -> std::io::Result<ClientHandler> {
+        Ok(ClientHandler {
+            client_id: self.client_id.clone(),
+            clients: Arc::clone(&self.clients),
+            games: Arc::clone(&self.games),
+            matchmaker: Arc::clone(&self.matchmaker),
+            parties: Arc::clone(&self.parties),
+            hosted: Arc::clone(&self.hosted),
+            replays: Arc::clone(&self.replays),
+            replay_dir: self.replay_dir.clone(),
+            pause_rules: self.pause_rules,
+            tick_interval: self.tick_interval,
+            features: self.features,
+            limits: self.limits,
+        })
+    }
+}
+
+impl Handler for ClientHandler {
+    fn handle_chat_msg(&mut self, msg: message::ChatMessage) {
+        if !self.allowed(self.features.chat, "Chat") {
+            return;
+        }
+        let clients = self.clients.lock().unwrap();
+        if let Err(e) = chat::relay(&self.client_id, msg, &clients) {
+            chat::notify(&self.client_id, e.to_string(), &clients);
+        }
+    }
+
+    fn handle_create_game(&mut self, msg: message::CreateGame) {
+        let members = match self.group() {
+            Ok(members) => members,
+            Err(e) => return self.notify(e.to_string()),
+        };
+        self.leave_queue(&members);
+        let max_games = self.limits.max_games;
+        if let Err(e) = lobby::start_game(&members, max_games, &self.clients, &self.games) {
+            self.notify(e.to_string());
+        }
+    }
+
+    fn handle_join_game(&mut self, msg: message::JoinGame) {
+        let members = match self.group() {
+            Ok(members) => members,
+            Err(e) => return self.notify(e.to_string()),
+        };
+        self.leave_queue(&members);
+        if let Err(e) = lobby::join_group(&members, msg.game_id, &self.clients, &self.games) {
+            self.notify(e.to_string());
+        }
+    }
+
+    fn handle_spectate_game(&mut self, msg: message::SpectateGame) {
+        if !self.allowed(self.features.spectating, "Spectating") {
+            return;
+        }
+        lobby::cancel_matchmaking(&self.client_id, &self.matchmaker, &self.clients);
+        if let Err(e) =
+            lobby::spectate_game(&self.client_id, msg.game_id, &self.clients, &self.games)
+        {
+            self.notify(e.to_string());
+        }
+    }
+
+    fn handle_leave_game(&mut self, msg: message::LeaveGame) {
+        if replays::stop_watching(&self.client_id, &self.replays, &self.clients) {
+            return;
+        }
+        if let Err(e) = lobby::leave_game(&self.client_id, &self.clients, &self.games) {
+            self.notify(e.to_string());
+        }
+    }
+
+    fn handle_request_game_list(&mut self, msg: message::RequestGameList) {
+        let list = lobby::game_list(&self.games);
+        let clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&self.client_id) {
+            client.send(&list);
+        }
+    }
+
+    fn handle_player_input(&mut self, msg: message::PlayerInput) {
+        let (state, game_id) = {
+            let clients = self.clients.lock().unwrap();
+            match clients.get(&self.client_id) {
+                Some(client) => (client.state(), client.game_id),
+                None => return,
+            }
+        };
+
+        match (state, game_id) {
+            (ClientState::InGame, Some(game_id)) => match game_actor::find(&self.games, game_id) {
+                Some(game) if game.is_lockstep() => {
+                    self.notify("Lockstep games only accept LockstepInput")
+                }
+                Some(game) => game.input(self.client_id.clone(), msg),
+                None => (),
+            },
+            (ClientState::Spectating, _) => self.notify("Spectators can't send PlayerInput"),
+            _ => self.notify("PlayerInput ignored: not in a game"),
+        }
+    }
+
+    fn handle_enqueue_matchmaking(&mut self, msg: message::EnqueueMatchmaking) {
+        if !self.allowed(self.features.matchmaking, "Matchmaking") {
+            return;
+        }
+        let members = match self.group() {
+            Ok(members) => members,
+            Err(e) => return self.notify(e.to_string()),
+        };
+
+        {
+            let mut clients = self.clients.lock().unwrap();
+            if let Some(member) = members.iter().find(|m| {
+                clients.get(*m).is_none_or(|c| {
+                    c.game_id.is_some() || !c.can_change_state(&ClientState::PendingGame)
+                })
+            }) {
+                let text = format!(
+                    "{} must be connected and out of a game to matchmake",
+                    member
+                );
+                chat::notify(&self.client_id, text, &clients);
+                return;
+            }
+            for member in members.iter() {
+                if let Some(client) = clients.get_mut(member) {
+                    if let Err(e) = client.change_state(ClientState::PendingGame) {
+                        warn!(client_id = %member, error = %e, "Failed to enter matchmaking");
+                    }
+                }
+            }
+        }
+
+        let ticket = MatchTicket::new(members, msg.mode, msg.rating);
+        self.matchmaker.lock().unwrap().enqueue(ticket);
+    }
+
+    fn handle_leave_matchmaking(&mut self, msg: message::LeaveMatchmaking) {
+        if !lobby::cancel_matchmaking(&self.client_id, &self.matchmaker, &self.clients) {
+            self.notify("Not in the matchmaking queue");
+        }
+    }
+
+    fn handle_create_party(&mut self, msg: message::CreateParty) {
+        if !self.allowed(self.features.parties, "Parties") {
+            return;
+        }
+        let result = self.parties.lock().unwrap().create(&self.client_id);
+        match result {
+            Ok(party_id) => {
+                let parties = self.parties.lock().unwrap();
+                let clients = self.clients.lock().unwrap();
+                if let Some(party) = parties.party_of(&self.client_id) {
+                    party.send_update(&clients);
+                }
+            }
+            Err(e) => self.notify(e.to_string()),
+        }
+    }
+
+    fn handle_invite_to_party(&mut self, msg: message::InviteToParty) {
+        if !self.allowed(self.features.parties, "Parties") {
+            return;
+        }
+        if !self.clients.lock().unwrap().contains_key(&msg.client_id) {
+            return self.notify(format!("Client {} is not connected", msg.client_id));
+        }
+
+        let result = self
+            .parties
+            .lock()
+            .unwrap()
+            .invite(&self.client_id, &msg.client_id);
+        match result {
+            Ok(party_id) => {
+                let clients = self.clients.lock().unwrap();
+                if let Some(client) = clients.get(&msg.client_id) {
+                    client.send(&message::PartyInvite {
+                        party_id,
+                        from: self.client_id.clone(),
+                    });
+                }
+                let text = format!("Invited {} to the party", msg.client_id);
+                chat::notify(&self.client_id, text, &clients);
+            }
+            Err(e) => self.notify(e.to_string()),
+        }
+    }
+
+    fn handle_accept_party_invite(&mut self, msg: message::AcceptPartyInvite) {
+        let result = self
+            .parties
+            .lock()
+            .unwrap()
+            .accept(&self.client_id, msg.party_id);
+        if let Err(e) = result {
+            return self.notify(e.to_string());
+        }
+
+        // The party changed shape, so any ticket queued for it no longer describes it.
+        let leader = self.leader();
+        if let Some(leader) = leader {
+            lobby::cancel_matchmaking(&leader, &self.matchmaker, &self.clients);
+        }
+        lobby::cancel_matchmaking(&self.client_id, &self.matchmaker, &self.clients);
+
+        let parties = self.parties.lock().unwrap();
+        let clients = self.clients.lock().unwrap();
+        if let Some(party) = parties.party_of(&self.client_id) {
+            party.send_update(&clients);
+        }
+    }
+
+    fn handle_decline_party_invite(&mut self, msg: message::DeclinePartyInvite) {
+        let result = self
+            .parties
+            .lock()
+            .unwrap()
+            .decline(&self.client_id, msg.party_id);
+        if let Err(e) = result {
+            self.notify(e.to_string());
+        }
+    }
+
+    fn handle_leave_party(&mut self, msg: message::LeaveParty) {
+        if self
+            .parties
+            .lock()
+            .unwrap()
+            .party_of(&self.client_id)
+            .is_none()
+        {
+            return self.notify("Not in a party");
+        }
+        lobby::leave_party(
+            &self.client_id,
+            &self.parties,
+            &self.matchmaker,
+            &self.clients,
+        );
+        self.notify("Left the party");
+    }
+
+    fn handle_host_game(&mut self, msg: message::HostGame) {
+        if !self.allowed(self.features.hosting, "Hosting") {
+            return;
+        }
+        let result = self.hosted.lock().unwrap().host(&self.client_id);
+        match result {
+            Ok(game_id) => {
+                let clients = self.clients.lock().unwrap();
+                if let Some(client) = clients.get(&self.client_id) {
+                    client.send(&message::GameHosted { game_id });
+                }
+            }
+            Err(e) => self.notify(e.to_string()),
+        }
+    }
+
+    fn handle_request_hosted_game_list(&mut self, msg: message::RequestHostedGameList) {
+        let games = self.hosted.lock().unwrap().summaries();
+        let clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&self.client_id) {
+            client.send(&message::HostedGameList { games });
+        }
+    }
+
+    fn handle_join_hosted_game(&mut self, msg: message::JoinHostedGame) {
+        if !self.allowed(self.features.hosting, "Hosting") {
+            return;
+        }
+        let result = self
+            .hosted
+            .lock()
+            .unwrap()
+            .join(msg.game_id, &self.client_id);
+        match result {
+            Ok(game) => {
+                let clients = self.clients.lock().unwrap();
+                if let Some(host) = clients.get(&game.host) {
+                    host.send(&message::PeerJoined {
+                        game_id: game.game_id,
+                        client_id: self.client_id.clone(),
+                    });
+                }
+                if let Some(client) = clients.get(&self.client_id) {
+                    client.send(&message::HostAssigned {
+                        game_id: game.game_id,
+                        host: game.host.clone(),
+                        snapshot: game.snapshot.clone(),
+                    });
+                }
+            }
+            Err(e) => self.notify(e.to_string()),
+        }
+    }
+
+    fn handle_leave_hosted_game(&mut self, msg: message::LeaveHostedGame) {
+        if !relay::leave_hosted_game(&self.client_id, &self.hosted, &self.clients) {
+            self.notify("Not in a hosted game");
+        }
+    }
+
+    fn handle_relay(&mut self, msg: message::Relay) {
+        if let Err(e) = relay::forward(&self.client_id, msg, &self.hosted, &self.clients) {
+            self.notify(e.to_string());
+        }
+    }
+
+    fn handle_create_lockstep_game(&mut self, msg: message::CreateLockstepGame) {
+        if !self.allowed(self.features.lockstep, "Lockstep") {
+            return;
+        }
+        let members = match self.group() {
+            Ok(members) => members,
+            Err(e) => return self.notify(e.to_string()),
+        };
+        self.leave_queue(&members);
+        let result = lobby::start_lockstep_game(
+            &members,
+            msg.players,
+            msg.rollback,
+            self.limits.max_games,
+            &self.clients,
+            &self.games,
+        );
+        if let Err(e) = result {
+            self.notify(e.to_string());
+        }
+    }
+
+    fn handle_lockstep_input(&mut self, msg: message::LockstepInput) {
+        let game_id = match self.game_id() {
+            Some(game_id) => game_id,
+            None => return self.notify("LockstepInput ignored: not in a game"),
+        };
+
+        let result = match game_actor::find(&self.games, game_id) {
+            Some(game) => {
+                let client_id = self.client_id.clone();
+                let (tick, input) = (msg.tick, msg.input);
+                game.call(move |game_id, game| {
+                    lockstep_input(game, game_id, &client_id, tick, input)
+                })
+            }
+            None => Err(format!("Game {} does not exist", game_id)),
+        };
+
+        match result {
+            // Rollback players simulate ahead, so their inputs are forwarded straight away.
+            Ok(Some((players, departures))) => {
+                let remote = message::RemoteInput {
+                    game_id,
+                    player: self.client_id.clone(),
+                    tick: msg.tick,
+                    input: msg.input,
+                };
+                let clients = self.clients.lock().unwrap();
+                for player in players.iter() {
+                    if let Some(client) = clients.get(player) {
+                        if player != &self.client_id {
+                            client.send(&remote);
+                        }
+                        for bundle in departures.iter() {
+                            client.send(bundle);
+                        }
+                    }
+                }
+            }
+            Ok(None) => (),
+            Err(e) => self.notify(e),
+        }
+    }
+
+    fn handle_state_checksum(&mut self, msg: message::StateChecksum) {
+        let game_id = match self.game_id() {
+            Some(game_id) => game_id,
+            None => return,
+        };
+
+        let (tick, checksum) = (msg.tick, msg.checksum);
+        let desync = game_actor::find(&self.games, game_id).and_then(|game| {
+            game.call(move |_, game| {
+                let lockstep = game.lockstep.as_ref()?;
+                let expected = lockstep.verify(tick, checksum)?;
+                Some((expected, lockstep.players.clone()))
+            })
+        });
+
+        if let Some((expected, players)) = desync {
+            warn!(
+                client_id = %self.client_id,
+                game_id,
+                tick = msg.tick,
+                "Peer desynced"
+            );
+            let desync = message::DesyncDetected {
+                game_id,
+                tick: msg.tick,
+                client_id: self.client_id.clone(),
+                expected,
+                checksum: msg.checksum,
+            };
+            let clients = self.clients.lock().unwrap();
+            for player in players {
+                if let Some(client) = clients.get(&player) {
+                    client.send(&desync);
+                }
+            }
+        }
+    }
+
+    fn handle_request_replay_list(&mut self, msg: message::RequestReplayList) {
+        let dir = match &self.replay_dir {
+            Some(dir) => dir,
+            None => return self.notify("Replays aren't recorded on this server"),
+        };
+        match replays::replay_list(dir) {
+            Ok(list) => {
+                let clients = self.clients.lock().unwrap();
+                if let Some(client) = clients.get(&self.client_id) {
+                    client.send(&list);
+                }
+            }
+            Err(e) => self.notify(format!("Failed to list replays: {}", e)),
+        }
+    }
+
+    fn handle_watch_replay(&mut self, msg: message::WatchReplay) {
+        if !self.allowed(self.features.spectating, "Spectating") {
+            return;
+        }
+        let dir = match &self.replay_dir {
+            Some(dir) => dir,
+            None => return self.notify("Replays aren't recorded on this server"),
+        };
+        if let Err(e) = replays::watch_replay(
+            &self.client_id,
+            &msg.name,
+            dir,
+            &self.replays,
+            &self.clients,
+        ) {
+            self.notify(e.to_string());
+        }
+    }
+
+    fn handle_replay_control(&mut self, msg: message::ReplayControl) {
+        if let Err(e) = replays::control(
+            &self.client_id,
+            msg.action,
+            self.tick_interval,
+            &self.replays,
+            &self.clients,
+        ) {
+            self.notify(e.to_string());
+        }
+    }
+
+    fn handle_vote_pause(&mut self, msg: message::VotePause) {
+        self.vote_state(GameState::Paused);
+    }
+
+    fn handle_vote_resume(&mut self, msg: message::VoteResume) {
+        self.vote_state(GameState::Active);
+    }
+
+    fn handle_ping(&mut self, msg: message::Ping) {
+        let clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&self.client_id) {
+            client.send(&message::Pong {
+                sent_at_us: msg.sent_at_us,
+            });
+        }
+    }
+}
+
+/// The players to forward a rollback input to, and any bundles announcing departures.
+type RollbackForward = (Vec<ClientID>, Vec<message::InputBundle>);
+
+/// Records a player's input for a tick of a lockstep game. Rollback games are advanced straight
+/// away, so the server keeps pace with players who simulate ahead.
+///
+/// # Returns
+///
+/// * The players to forward the input to, and any bundles announcing departures, if the game
+///   runs with rollback.
+/// * An error message if the game isn't a lockstep game, or the input was refused.
+fn lockstep_input(
+    game: &mut GameController,
+    game_id: GameID,
+    client_id: &ClientID,
+    tick: u64,
+    input: message::PlayerInput,
+) -> Result<Option<RollbackForward>, String> {
+    let lockstep = game
+        .lockstep
+        .as_mut()
+        .ok_or_else(|| format!("Game {} isn't a lockstep game", game_id))?;
+    lockstep
+        .submit_input(client_id, tick, input)
+        .map_err(|e| e.to_string())?;
+    if !lockstep.rollback {
+        return Ok(None);
+    }
+
+    let players = lockstep.players.clone();
+    let departures = game
+        .advance_lockstep(game_id)
+        .into_iter()
+        .filter(|bundle| !bundle.left.is_empty())
+        .collect();
+    Ok(Some((players, departures)))
+}
@@ -0,0 +1,423 @@
+use serde::Deserialize;
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::errors::ConfigError;
+use crate::game::pause::PauseRules;
+use crate::server_side::admin::AdminConfig;
+
+/// Prefix of the environment variables which override a config file.
+pub const ENV_PREFIX: &str = "MULTIPLAYER_";
+/// Workers kept back from clients for the server's scheduled jobs and for handling messages.
+/// Every connected client holds a worker while it is listened to.
+pub const RESERVED_WORKERS: usize = 8;
+/// The most ticks or publishes a second the server can be set to.
+pub const MAX_RATE: u32 = 1000;
+
+/// Parts of the server which can be turned off. Everything is on by default.
+/// * chat - Clients can send chat messages to each other.
+/// * matchmaking - Clients can queue to be placed into games.
+/// * parties - Clients can group up to join games together.
+/// * spectating - Clients can watch games they aren't playing, and replays.
+/// * hosting - Clients can host games which the server relays for.
+/// * lockstep - Clients can create lockstep and rollback games.
+/// * pause_votes - Players can vote to pause and resume their games.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    pub chat: bool,
+    pub matchmaking: bool,
+    pub parties: bool,
+    pub spectating: bool,
+    pub hosting: bool,
+    pub lockstep: bool,
+    pub pause_votes: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features {
+            chat: true,
+            matchmaking: true,
+            parties: true,
+            spectating: true,
+            hosting: true,
+            lockstep: true,
+            pause_votes: true,
+        }
+    }
+}
+
+impl Features {
+    /// Turns a feature on or off by the name it has in a config file.
+    ///
+    /// # Returns
+    ///
+    /// * ConfigError if there is no feature called `name`.
+    pub fn set(&mut self, name: &str, enabled: bool) -> Result<(), ConfigError> {
+        let feature = match name.trim() {
+            "chat" => &mut self.chat,
+            "matchmaking" => &mut self.matchmaking,
+            "parties" => &mut self.parties,
+            "spectating" => &mut self.spectating,
+            "hosting" => &mut self.hosting,
+            "lockstep" => &mut self.lockstep,
+            "pause_votes" => &mut self.pause_votes,
+            other => {
+                return Err(ConfigError {
+                    reason: format!("Unknown feature {}", other),
+                })
+            }
+        };
+        *feature = enabled;
+        Ok(())
+    }
+}
+
+/// Limits on what clients may use of the server. Timeouts of 0 never expire.
+/// * max_clients - How many clients may be connected at once.
+/// * max_games - How many games the server runs at once. Hosted games aren't counted.
+/// * handshake_timeout_secs - How long a new connection has to identify itself.
+/// * idle_timeout_secs - How long a client may send nothing before being disconnected.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_clients: usize,
+    pub max_games: usize,
+    pub handshake_timeout_secs: u64,
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_clients: 64,
+            max_games: 32,
+            handshake_timeout_secs: 10,
+            idle_timeout_secs: 0,
+        }
+    }
+}
+
+impl Limits {
+    pub fn handshake_timeout(&self) -> Option<Duration> {
+        timeout(self.handshake_timeout_secs)
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        timeout(self.idle_timeout_secs)
+    }
+}
+
+fn timeout(secs: u64) -> Option<Duration> {
+    match secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// The `[pause]` table of a config file, read into PauseRules.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PauseConfig {
+    pub vote_fraction: f32,
+    pub pause_countdown_secs: u64,
+    pub resume_countdown_secs: u64,
+    pub pauses_per_player: Option<u32>,
+}
+
+impl Default for PauseConfig {
+    fn default() -> Self {
+        let rules = PauseRules::default();
+        PauseConfig {
+            vote_fraction: rules.vote_fraction,
+            pause_countdown_secs: rules.pause_countdown.as_secs(),
+            resume_countdown_secs: rules.resume_countdown.as_secs(),
+            pauses_per_player: rules.pauses_per_player,
+        }
+    }
+}
+
+impl PauseConfig {
+    pub fn rules(&self) -> PauseRules {
+        PauseRules {
+            vote_fraction: self.vote_fraction,
+            pause_countdown: Duration::from_secs(self.pause_countdown_secs),
+            resume_countdown: Duration::from_secs(self.resume_countdown_secs),
+            pauses_per_player: self.pauses_per_player,
+        }
+    }
+}
+
+/// Everything a server is started with. A config is built from the defaults, then a TOML file,
+/// then `MULTIPLAYER_*` environment variables, and finally the command line, each overriding the
+/// last. `validate` should be called once it is built.
+/// * addr - The address clients connect to.
+/// * threads - The most workers the server's ThreadPool grows to.
+/// * min_threads - Workers the ThreadPool keeps even when the server is idle.
+/// * tick_rate - How many times a second the systems of every game run.
+/// * publish_rate - How many times a second snapshots are sent to clients.
+/// * spectator_delay_secs - How long snapshots are held back from spectators.
+/// * save_dir - Where games are saved. None if games aren't saved.
+/// * replay_dir - Where games are recorded. None if games aren't recorded.
+/// * metrics_addr - Where metrics are served. None if they aren't.
+/// * admin - The admin port. None if the server has no admin port.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::server_side::config::ServerConfig;
+///
+/// let mut config = ServerConfig::from_toml(
+///     r#"
+///     tick_rate = 20
+///
+///     [limits]
+///     max_games = 4
+///
+///     [features]
+///     chat = false
+///     "#,
+/// )
+/// .unwrap();
+/// config
+///     .apply_vars(vec![(String::from("MULTIPLAYER_MAX_GAMES"), String::from("8"))])
+///     .unwrap();
+///
+/// assert_eq!(config.tick_rate, 20);
+/// assert_eq!(config.limits.max_games, 8);
+/// assert!(!config.features.chat);
+/// assert!(config.validate().is_ok());
+///
+/// config.tick_rate = 0;
+/// assert!(config.validate().is_err());
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub addr: String,
+    pub threads: usize,
+    pub min_threads: usize,
+    pub tick_rate: u32,
+    pub publish_rate: u32,
+    pub spectator_delay_secs: u64,
+    pub save_dir: Option<PathBuf>,
+    pub replay_dir: Option<PathBuf>,
+    pub metrics_addr: Option<String>,
+    pub admin: Option<AdminConfig>,
+    pub limits: Limits,
+    pub features: Features,
+    pub pause: PauseConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            addr: String::from("127.0.0.1:7878"),
+            threads: 100,
+            min_threads: 16,
+            tick_rate: 1,
+            publish_rate: 1,
+            spectator_delay_secs: 0,
+            save_dir: None,
+            replay_dir: None,
+            metrics_addr: None,
+            admin: None,
+            limits: Limits::default(),
+            features: Features::default(),
+            pause: PauseConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads a config from TOML. Anything left out keeps its default.
+    pub fn from_toml(text: &str) -> Result<ServerConfig, ConfigError> {
+        toml::from_str(text).map_err(|e| ConfigError {
+            reason: e.to_string(),
+        })
+    }
+
+    /// Reads a config from a TOML file.
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<ServerConfig, ConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| ConfigError {
+            reason: format!("Failed to read {}: {}", path.display(), e),
+        })?;
+        ServerConfig::from_toml(&text).map_err(|e| ConfigError {
+            reason: format!("{}: {}", path.display(), e.reason),
+        })
+    }
+
+    /// Overrides the config with the `MULTIPLAYER_*` environment variables which are set.
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        self.apply_vars(std::env::vars())
+    }
+
+    /// Overrides the config with `MULTIPLAYER_*` variables. Other variables are ignored.
+    ///
+    /// * MULTIPLAYER_ADDR, MULTIPLAYER_THREADS, MULTIPLAYER_TICK_RATE, MULTIPLAYER_PUBLISH_RATE,
+    ///   MULTIPLAYER_SPECTATOR_DELAY, MULTIPLAYER_SAVE_DIR, MULTIPLAYER_REPLAY_DIR and
+    ///   MULTIPLAYER_METRICS_ADDR set the field of the same name.
+    /// * MULTIPLAYER_MAX_CLIENTS, MULTIPLAYER_MAX_GAMES, MULTIPLAYER_HANDSHAKE_TIMEOUT and
+    ///   MULTIPLAYER_IDLE_TIMEOUT set the limits.
+    /// * MULTIPLAYER_ADMIN_ADDR opens the admin port, and MULTIPLAYER_ADMIN_TOKEN sets its token.
+    /// * MULTIPLAYER_DISABLE turns off a comma separated list of features.
+    ///
+    /// # Returns
+    ///
+    /// * ConfigError if a variable can't be read into its field.
+    pub fn apply_vars<I>(&mut self, vars: I) -> Result<(), ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut admin_addr = None;
+        let mut admin_token = None;
+        for (key, value) in vars {
+            let name = match key.strip_prefix(ENV_PREFIX) {
+                Some(name) => name,
+                None => continue,
+            };
+            match name {
+                "ADDR" => self.addr = value,
+                "THREADS" => self.threads = parse(&key, &value)?,
+                "MIN_THREADS" => self.min_threads = parse(&key, &value)?,
+                "TICK_RATE" => self.tick_rate = parse(&key, &value)?,
+                "PUBLISH_RATE" => self.publish_rate = parse(&key, &value)?,
+                "SPECTATOR_DELAY" => self.spectator_delay_secs = parse(&key, &value)?,
+                "SAVE_DIR" => self.save_dir = Some(PathBuf::from(value)),
+                "REPLAY_DIR" => self.replay_dir = Some(PathBuf::from(value)),
+                "METRICS_ADDR" => self.metrics_addr = Some(value),
+                "MAX_CLIENTS" => self.limits.max_clients = parse(&key, &value)?,
+                "MAX_GAMES" => self.limits.max_games = parse(&key, &value)?,
+                "HANDSHAKE_TIMEOUT" => self.limits.handshake_timeout_secs = parse(&key, &value)?,
+                "IDLE_TIMEOUT" => self.limits.idle_timeout_secs = parse(&key, &value)?,
+                "ADMIN_ADDR" => admin_addr = Some(value),
+                "ADMIN_TOKEN" => admin_token = Some(value),
+                "DISABLE" => {
+                    for feature in value.split(',').filter(|f| !f.trim().is_empty()) {
+                        self.features.set(feature, false)?;
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.set_admin(admin_addr, admin_token);
+        Ok(())
+    }
+
+    /// Opens the admin port at `addr`, or changes the token of the port already set. A token
+    /// without an address or an open port is ignored.
+    pub fn set_admin(&mut self, addr: Option<String>, token: Option<String>) {
+        match addr {
+            Some(addr) => {
+                let token = token
+                    .or_else(|| self.admin.as_ref().map(|a| a.token.clone()))
+                    .unwrap_or_default();
+                self.admin = Some(AdminConfig { addr, token });
+            }
+            None => {
+                if let (Some(admin), Some(token)) = (&mut self.admin, token) {
+                    admin.token = token;
+                }
+            }
+        }
+    }
+
+    /// Checks the config can start a server.
+    ///
+    /// # Returns
+    ///
+    /// * ConfigError listing every problem found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        check_addr("addr", &self.addr, &mut problems);
+        if let Some(addr) = &self.metrics_addr {
+            check_addr("metrics_addr", addr, &mut problems);
+        }
+        if let Some(admin) = &self.admin {
+            check_addr("admin.addr", &admin.addr, &mut problems);
+            if admin.token.is_empty() {
+                problems.push(String::from(
+                    "admin.token must be set to open an admin port",
+                ));
+            }
+        }
+        if self.tick_rate == 0 || self.tick_rate > MAX_RATE {
+            problems.push(format!("tick_rate must be from 1 to {}", MAX_RATE));
+        }
+        if self.publish_rate == 0 || self.publish_rate > MAX_RATE {
+            problems.push(format!("publish_rate must be from 1 to {}", MAX_RATE));
+        }
+        if self.limits.max_clients == 0 {
+            problems.push(String::from("limits.max_clients must be at least 1"));
+        }
+        if self.limits.max_games == 0 {
+            problems.push(String::from("limits.max_games must be at least 1"));
+        }
+        if self.threads < self.limits.max_clients + RESERVED_WORKERS {
+            problems.push(format!(
+                "threads must be at least limits.max_clients + {} ({})",
+                RESERVED_WORKERS,
+                self.limits.max_clients + RESERVED_WORKERS
+            ));
+        }
+        if self.min_threads > self.threads {
+            problems.push(String::from("min_threads must be at most threads"));
+        }
+        for (name, dir) in [
+            ("save_dir", &self.save_dir),
+            ("replay_dir", &self.replay_dir),
+        ] {
+            if let Some(dir) = dir {
+                if dir.exists() && !dir.is_dir() {
+                    problems.push(format!("{} {} isn't a directory", name, dir.display()));
+                }
+            }
+        }
+        let fraction = self.pause.vote_fraction;
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            problems.push(String::from(
+                "pause.vote_fraction must be above 0 and at most 1",
+            ));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError {
+                reason: problems.join("; "),
+            })
+        }
+    }
+
+    pub fn tick_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.tick_rate.max(1)
+    }
+
+    pub fn publish_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.publish_rate.max(1)
+    }
+
+    pub fn spectator_delay(&self) -> Duration {
+        Duration::from_secs(self.spectator_delay_secs)
+    }
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    value.trim().parse().map_err(|_| ConfigError {
+        reason: format!("{} can't be {:?}", key, value),
+    })
+}
+
+fn check_addr(name: &str, addr: &str, problems: &mut Vec<String>) {
+    if addr
+        .to_socket_addrs()
+        .map_or(true, |mut a| a.next().is_none())
+    {
+        problems.push(format!("{} {} isn't an address", name, addr));
+    }
+}
@@ -0,0 +1,293 @@
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, error};
+
+use crate::comms::message::{GameSnapshot, LockstepStart, PlayerInput};
+use crate::errors::LockstepError;
+use crate::game::controller::GameController;
+use crate::game::GameID;
+use crate::server_side::client::ClientID;
+use crate::server_side::server::GameHashmap;
+use crate::threading::job_handle::panic_message;
+use crate::threading::worker;
+
+/// Where a game actor sends the answer to a command.
+pub type Reply<T> = mpsc::Sender<T>;
+
+/// Snapshots of a game which are due to be published, each with the clients it goes to.
+pub type Snapshots = Vec<(Vec<ClientID>, GameSnapshot)>;
+
+/// A closure run on a game by its actor.
+pub type GameFn = Box<dyn FnOnce(GameID, &mut GameController) + Send>;
+
+/// A command sent to a game actor. Commands are handled one at a time, in the order sent.
+pub enum GameCommand {
+    /// Adds a player, replying with the LockstepStart to send if they filled a lockstep game.
+    AddPlayer(
+        ClientID,
+        Reply<Result<Option<LockstepStart>, LockstepError>>,
+    ),
+    AddSpectator(ClientID),
+    /// Removes a client as both a player and a spectator.
+    Remove(ClientID),
+    Input(ClientID, PlayerInput),
+    /// Takes a snapshot of the game, replying with every snapshot due. Spectators are sent
+    /// snapshots held back for the delay.
+    Snapshot(Duration, Reply<Snapshots>),
+    /// Runs a closure on the game, for anything the other commands don't cover.
+    Run(GameFn),
+}
+
+/// Periodic work on a game. Only one of each runs for a game at a time, so a slow game skips
+/// beats rather than piling up jobs, and never holds up other games.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Beat {
+    Tick,
+    Publish,
+}
+
+thread_local! {
+    /// The game whose commands this thread handles, if it is a game thread.
+    static GAME_THREAD: Cell<Option<GameID>> = const { Cell::new(None) };
+}
+
+struct Actor {
+    game_id: GameID,
+    lockstep: bool,
+    sender: mpsc::Sender<GameCommand>,
+    ticking: AtomicBool,
+    publishing: AtomicBool,
+}
+
+impl Actor {
+    fn beat(&self, beat: Beat) -> &AtomicBool {
+        match beat {
+            Beat::Tick => &self.ticking,
+            Beat::Publish => &self.publishing,
+        }
+    }
+}
+
+/// Handles the commands of a game one at a time until every handle to it is dropped. A command
+/// which panics is logged and the game carries on with the next.
+fn run(game_id: GameID, mut game: GameController, commands: mpsc::Receiver<GameCommand>) {
+    GAME_THREAD.with(|current| current.set(Some(game_id)));
+    for command in commands {
+        let handled = panic::catch_unwind(AssertUnwindSafe(|| handle(game_id, &mut game, command)));
+        if let Err(payload) = handled {
+            error!(
+                game_id,
+                panic = %panic_message(&payload),
+                "Game command panicked"
+            );
+            worker::drop_payload(payload);
+        }
+    }
+    debug!(game_id, "Game thread stopped");
+}
+
+fn handle(game_id: GameID, game: &mut GameController, command: GameCommand) {
+    match command {
+        GameCommand::AddPlayer(player, reply) => {
+            let _ = reply.send(game.add_player(game_id, player));
+        }
+        GameCommand::AddSpectator(spectator) => game.model.add_spectator(spectator),
+        GameCommand::Remove(client_id) => {
+            if game.remove_player(&client_id) {
+                debug!(%client_id, game_id, "Removed from players");
+            }
+            if game.model.remove_spectator(&client_id) {
+                debug!(%client_id, game_id, "Removed from spectators");
+            }
+        }
+        GameCommand::Input(player, input) => game.apply_input(&player, input),
+        GameCommand::Snapshot(spectator_delay, reply) => {
+            let snapshot = game.snapshot(game_id);
+            let players: Vec<_> = game.model.players.iter().cloned().collect();
+            let spectators: Vec<_> = game.model.spectators.iter().cloned().collect();
+
+            let mut snapshots = Vec::new();
+            for delayed in game.delay_for_spectators(snapshot.clone(), spectator_delay) {
+                snapshots.push((spectators.clone(), delayed));
+            }
+            // Lockstep players simulate the game themselves, only spectators need snapshots.
+            if game.lockstep.is_none() {
+                snapshots.push((players, snapshot));
+            }
+            let _ = reply.send(snapshots);
+        }
+        GameCommand::Run(f) => f(game_id, game),
+    }
+}
+
+/// A handle to a game run as an actor. Each game is owned by its own thread, and the only way
+/// to reach it is by sending commands, so a game is never locked for longer than one command,
+/// and a busy game never holds up another. The thread stops once every handle is dropped.
+///
+/// Cloning a GameHandle is cheap, and every clone reaches the same game.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::game::controller::GameController;
+/// use multiplayer::server_side::game_actor::GameHandle;
+///
+/// let game = GameHandle::new(3, GameController::new());
+/// game.add_player(String::from("alice")).unwrap();
+///
+/// let players = game.call(|_, game| game.player_count());
+/// assert_eq!(players, 1);
+/// ```
+#[derive(Clone)]
+pub struct GameHandle {
+    actor: Arc<Actor>,
+}
+
+impl GameHandle {
+    pub fn new(game_id: GameID, game: GameController) -> GameHandle {
+        let (sender, commands) = mpsc::channel();
+        let lockstep = game.lockstep.is_some();
+        thread::Builder::new()
+            .name(format!("game_{}", game_id))
+            .spawn(move || run(game_id, game, commands))
+            .expect("Failed to spawn game thread");
+        GameHandle {
+            actor: Arc::new(Actor {
+                game_id,
+                lockstep,
+                sender,
+                ticking: AtomicBool::new(false),
+                publishing: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    pub fn game_id(&self) -> GameID {
+        self.actor.game_id
+    }
+
+    /// Whether the game is run in lockstep by its players.
+    pub fn is_lockstep(&self) -> bool {
+        self.actor.lockstep
+    }
+
+    /// Sends a command without waiting for it to be handled.
+    pub fn send(&self, command: GameCommand) {
+        // The game thread holds the receiver until every handle is dropped.
+        let _ = self.actor.sender.send(command);
+    }
+
+    /// Sends a command and waits for its reply.
+    ///
+    /// # Panics
+    ///
+    /// If the command panicked, or is sent from a command of any game. Waiting on a game from a
+    /// game thread could wait for itself, or deadlock with a game waiting the other way.
+    pub fn ask<T, C>(&self, command: C) -> T
+    where
+        C: FnOnce(Reply<T>) -> GameCommand,
+    {
+        if let Some(asking) = GAME_THREAD.with(Cell::get) {
+            panic!(
+                "Game {} was asked from a command of game {}, which could deadlock",
+                self.game_id(),
+                asking
+            );
+        }
+        let (reply, answer) = mpsc::channel();
+        self.send(command(reply));
+        answer
+            .recv()
+            .unwrap_or_else(|_| panic!("Game {} dropped a command", self.game_id()))
+    }
+
+    /// Runs a closure on the game and waits for its result.
+    ///
+    /// # Panics
+    ///
+    /// Like `ask`.
+    pub fn call<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(GameID, &mut GameController) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.ask(|reply| {
+            GameCommand::Run(Box::new(move |game_id, game| {
+                let _ = reply.send(f(game_id, game));
+            }))
+        })
+    }
+
+    /// Adds a player to the game.
+    ///
+    /// # Returns
+    ///
+    /// * The LockstepStart to send to every player if this player filled a lockstep game.
+    /// * LockstepError if the lockstep game already started.
+    pub fn add_player(&self, player: ClientID) -> Result<Option<LockstepStart>, LockstepError> {
+        self.ask(|reply| GameCommand::AddPlayer(player, reply))
+    }
+
+    pub fn add_spectator(&self, spectator: ClientID) {
+        self.send(GameCommand::AddSpectator(spectator));
+    }
+
+    /// Removes a client from the game as both a player and a spectator.
+    pub fn remove(&self, client_id: ClientID) {
+        self.send(GameCommand::Remove(client_id));
+    }
+
+    pub fn input(&self, player: ClientID, input: PlayerInput) {
+        self.send(GameCommand::Input(player, input));
+    }
+
+    /// Returns every snapshot of the game due to be published, and the clients each goes to.
+    pub fn snapshots(&self, spectator_delay: Duration) -> Snapshots {
+        self.ask(|reply| GameCommand::Snapshot(spectator_delay, reply))
+    }
+
+    /// Starts a beat of periodic work on the game, unless the last one is still going.
+    ///
+    /// # Returns
+    ///
+    /// * A guard which ends the beat when dropped. None if the last beat hasn't ended.
+    pub fn begin(&self, beat: Beat) -> Option<BeatGuard> {
+        match self.actor.beat(beat).swap(true, Ordering::AcqRel) {
+            true => None,
+            false => Some(BeatGuard {
+                actor: Arc::clone(&self.actor),
+                beat,
+            }),
+        }
+    }
+}
+
+/// Ends a beat of periodic work on a game when dropped.
+pub struct BeatGuard {
+    actor: Arc<Actor>,
+    beat: Beat,
+}
+
+impl Drop for BeatGuard {
+    fn drop(&mut self) {
+        self.actor.beat(self.beat).store(false, Ordering::Release);
+    }
+}
+
+/// Returns the handle to a game, if it exists. The registry is only held while the handle is
+/// cloned.
+pub fn find(games: &GameHashmap, game_id: GameID) -> Option<GameHandle> {
+    games.lock().unwrap().get(&game_id).cloned()
+}
+
+/// Returns the handle to every game, sorted by GameID.
+pub fn all(games: &GameHashmap) -> Vec<GameHandle> {
+    let mut handles: Vec<_> = games.lock().unwrap().values().cloned().collect();
+    handles.sort_by_key(GameHandle::game_id);
+    handles
+}
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+use crate::comms::message::{GameJoined, GameLeft, GameList};
+use crate::errors::LobbyError;
+use crate::game::controller::GameController;
+use crate::game::GameID;
+use crate::server_side::chat;
+use crate::server_side::client::{Client, ClientID, ClientState};
+use crate::server_side::game_actor::{self, GameHandle};
+use crate::server_side::matchmaking::MatchmakerHandle;
+use crate::server_side::party::PartyHandle;
+use crate::server_side::server::{ClientHashmap, GameHashmap};
+use crate::state::State;
+
+/// Creates a new game and returns its GameID.
+pub fn create_game(games: &GameHashmap) -> GameID {
+    insert_game(GameController::new(), games)
+}
+
+/// Starts a game as an actor, adds its handle to the GameHashmap and returns its GameID.
+pub fn insert_game(game: GameController, games: &GameHashmap) -> GameID {
+    let mut games = games.lock().unwrap();
+    let game_id = games.keys().max().map_or(0, |id| id + 1);
+    games.insert(game_id, GameHandle::new(game_id, game));
+    info!(game_id, "Game created");
+    game_id
+}
+
+/// Creates a new game and adds every client in a group to it as a player.
+///
+/// # Returns
+///
+/// * The GameID of the new game.
+/// * LobbyError if the server already runs `max_games` games, or any member of the group can't
+///   join a game. No game is created.
+pub fn start_game(
+    members: &[ClientID],
+    max_games: usize,
+    clients: &ClientHashmap,
+    games: &GameHashmap,
+) -> Result<GameID, LobbyError> {
+    check_capacity(max_games, games)?;
+    check_free(members, clients)?;
+    let game_id = create_game(games);
+    if let Err(e) = join_group(members, game_id, clients, games) {
+        games.lock().unwrap().remove(&game_id);
+        return Err(e);
+    }
+    Ok(game_id)
+}
+
+/// Creates a new lockstep game for `players` players, and adds every client in a group to it.
+/// The game starts once the remaining players join. Rollback games forward each input to the
+/// other players as soon as it arrives.
+///
+/// # Returns
+///
+/// * The GameID of the new game.
+/// * LobbyError if the group is larger than the game, the server already runs `max_games` games,
+///   or any member can't join a game.
+pub fn start_lockstep_game(
+    members: &[ClientID],
+    players: u32,
+    rollback: bool,
+    max_games: usize,
+    clients: &ClientHashmap,
+    games: &GameHashmap,
+) -> Result<GameID, LobbyError> {
+    if players == 0 || members.len() > players as usize {
+        return Err(LobbyError {
+            reason: format!(
+                "{} players can't join a {} player game",
+                members.len(),
+                players
+            ),
+        });
+    }
+    check_capacity(max_games, games)?;
+    check_free(members, clients)?;
+    let game = if rollback {
+        GameController::new_rollback(players as usize)
+    } else {
+        GameController::new_lockstep(players as usize)
+    };
+    let game_id = insert_game(game, games);
+    join_group(members, game_id, clients, games)?;
+    Ok(game_id)
+}
+
+/// Adds every client in a group to a game as players, so parties always land in the same game.
+///
+/// # Returns
+///
+/// * LobbyError if any member is already in a game, or the game doesn't exist. Nobody is added.
+pub fn join_group(
+    members: &[ClientID],
+    game_id: GameID,
+    clients: &ClientHashmap,
+    games: &GameHashmap,
+) -> Result<(), LobbyError> {
+    check_free(members, clients)?;
+    if !games.lock().unwrap().contains_key(&game_id) {
+        return Err(LobbyError {
+            reason: format!("Game {} does not exist", game_id),
+        });
+    }
+    for (joined, member) in members.iter().enumerate() {
+        if let Err(e) = attach(member, game_id, ClientState::InGame, clients, games) {
+            for added in members[..joined].iter() {
+                if let Err(e) = leave_game(added, clients, games) {
+                    warn!(client_id = %added, error = %e, "Failed to undo joining a game");
+                }
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Returns every member of a group the matchmaker couldn't place to the Waiting state, and tells
+/// them why. Members who already joined a game some other way are left alone.
+pub fn release_group(members: &[ClientID], reason: &str, clients: &ClientHashmap) {
+    let mut clients = clients.lock().unwrap();
+    for member in members {
+        if let Some(client) = clients.get_mut(member) {
+            if client.game_id.is_some() || client.state() != ClientState::PendingGame {
+                continue;
+            }
+            if let Err(e) = client.change_state(ClientState::Waiting) {
+                warn!(client_id = %member, error = %e, "Failed to leave matchmaking");
+            }
+        }
+        chat::notify(member, format!("Matchmaking failed: {}", reason), &clients);
+    }
+}
+
+/// Adds a client to a game as a player.
+///
+/// # Arguments
+///
+/// * 'client_id' - The ClientID of the client joining the game.
+/// * 'game_id' - The GameID of the game to join.
+/// * 'clients' - A reference to the ClientHashmap.
+/// * 'games' - A reference to the GameHashmap.
+///
+/// # Returns
+///
+/// * LobbyError if the client is already in a game, or the game doesn't exist.
+pub fn join_game(
+    client_id: &ClientID,
+    game_id: GameID,
+    clients: &ClientHashmap,
+    games: &GameHashmap,
+) -> Result<(), LobbyError> {
+    attach(client_id, game_id, ClientState::InGame, clients, games)
+}
+
+/// Attaches a client to a running game as a spectator.
+///
+/// # Returns
+///
+/// * LobbyError if the client is already in a game, or the game doesn't exist.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::server_side::client::Client;
+/// use multiplayer::server_side::lobby;
+/// use std::collections::HashMap;
+/// use std::sync::{Arc, Mutex};
+///
+/// let clients = Arc::new(Mutex::new(HashMap::new()));
+/// let games = Arc::new(Mutex::new(HashMap::new()));
+/// let alice = String::from("alice");
+/// clients.lock().unwrap().insert(alice.clone(), Client::new(alice.clone(), None));
+///
+/// let game_id = lobby::create_game(&games);
+/// lobby::spectate_game(&alice, game_id, &clients, &games).unwrap();
+/// assert!(lobby::join_game(&alice, game_id, &clients, &games).is_err());
+///
+/// let list = lobby::game_list(&games);
+/// assert_eq!(list.games[0].players, 0);
+/// assert_eq!(list.games[0].spectators, 1);
+/// ```
+pub fn spectate_game(
+    client_id: &ClientID,
+    game_id: GameID,
+    clients: &ClientHashmap,
+    games: &GameHashmap,
+) -> Result<(), LobbyError> {
+    attach(client_id, game_id, ClientState::Spectating, clients, games)
+}
+
+/// Removes a client from the game they are playing or spectating.
+///
+/// # Returns
+///
+/// * The GameID of the game which was left.
+/// * LobbyError if the client isn't in a game.
+pub fn leave_game(
+    client_id: &ClientID,
+    clients: &ClientHashmap,
+    games: &GameHashmap,
+) -> Result<GameID, LobbyError> {
+    let game_id = {
+        let clients = clients.lock().unwrap();
+        let client = get_client(client_id, &clients)?;
+        client.game_id.ok_or_else(|| LobbyError {
+            reason: format!("Client {} is not in a game", client_id),
+        })?
+    };
+
+    detach(client_id, game_id, games);
+
+    let mut clients = clients.lock().unwrap();
+    if let Some(client) = clients.get_mut(client_id) {
+        client.game_id = None;
+        client
+            .change_state(ClientState::Waiting)
+            .map_err(|e| LobbyError {
+                reason: e.to_string(),
+            })?;
+        client.send(&GameLeft { game_id });
+    }
+    chat::announce_game(game_id, format!("{} left the game", client_id), &clients);
+
+    Ok(game_id)
+}
+
+/// Removes a client from a game as both a player and a spectator. The game handles the removal
+/// in its own time, after any command already sent to it.
+pub fn detach(client_id: &ClientID, game_id: GameID, games: &GameHashmap) {
+    if let Some(game) = game_actor::find(games, game_id) {
+        game.remove(client_id.clone());
+    }
+}
+
+/// Removes the matchmaking ticket holding a client, returning every member of the ticket to the
+/// Waiting state.
+///
+/// # Returns
+///
+/// * True if the client was queued.
+pub fn cancel_matchmaking(
+    client_id: &ClientID,
+    matchmaker: &MatchmakerHandle,
+    clients: &ClientHashmap,
+) -> bool {
+    let ticket = matchmaker.lock().unwrap().dequeue(client_id);
+    match ticket {
+        Some(ticket) => {
+            let mut clients = clients.lock().unwrap();
+            for member in ticket.members.iter() {
+                if let Some(client) = clients.get_mut(member) {
+                    if let Err(e) = client.change_state(ClientState::Waiting) {
+                        warn!(client_id = %member, error = %e, "Failed to leave matchmaking");
+                    }
+                }
+                chat::notify(member, "Left the matchmaking queue", &clients);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Removes a client from their party, cancelling any matchmaking ticket the party had queued and
+/// telling the remaining members.
+pub fn leave_party(
+    client_id: &ClientID,
+    parties: &PartyHandle,
+    matchmaker: &MatchmakerHandle,
+    clients: &ClientHashmap,
+) {
+    cancel_matchmaking(client_id, matchmaker, clients);
+    let remaining = parties.lock().unwrap().leave(client_id);
+    if let Some(party) = remaining {
+        let clients = clients.lock().unwrap();
+        party.send_update(&clients);
+    }
+}
+
+/// Returns a listing of every game on the server.
+pub fn game_list(games: &GameHashmap) -> GameList {
+    let summaries = game_actor::all(games)
+        .iter()
+        .map(|game| game.call(|game_id, game| game.summary(game_id)))
+        .collect();
+    GameList { games: summaries }
+}
+
+fn attach(
+    client_id: &ClientID,
+    game_id: GameID,
+    state: ClientState,
+    clients: &ClientHashmap,
+    games: &GameHashmap,
+) -> Result<(), LobbyError> {
+    {
+        let mut clients = clients.lock().unwrap();
+        let client = clients.get_mut(client_id).ok_or_else(|| LobbyError {
+            reason: format!("Client {} is not connected", client_id),
+        })?;
+        if let Some(current) = client.game_id {
+            return Err(LobbyError {
+                reason: format!("Client {} is already in game {}", client_id, current),
+            });
+        }
+        if !client.can_change_state(&state) {
+            return Err(LobbyError {
+                reason: format!(
+                    "Client {} can't join a game while {:?}",
+                    client_id,
+                    client.state()
+                ),
+            });
+        }
+        // Reserves the client for this game, so a join sent at the same time is refused.
+        client.game_id = Some(game_id);
+    }
+
+    let added = game_actor::find(games, game_id)
+        .ok_or_else(|| LobbyError {
+            reason: format!("Game {} does not exist", game_id),
+        })
+        .and_then(|game| match state {
+            ClientState::Spectating => {
+                game.add_spectator(client_id.clone());
+                Ok(None)
+            }
+            _ => game.add_player(client_id.clone()).map_err(|e| LobbyError {
+                reason: e.to_string(),
+            }),
+        });
+    let lockstep_start = match added {
+        Ok(lockstep_start) => lockstep_start,
+        Err(e) => {
+            if let Some(client) = clients.lock().unwrap().get_mut(client_id) {
+                client.game_id = None;
+            }
+            return Err(e);
+        }
+    };
+
+    let mut clients = clients.lock().unwrap();
+    if let Some(client) = clients.get_mut(client_id) {
+        // The client may have left while it was being added, and its reservation with it.
+        let joined = match client.game_id {
+            Some(reserved) if reserved == game_id => client.change_state(state).map_err(|e| {
+                client.game_id = None;
+                LobbyError {
+                    reason: e.to_string(),
+                }
+            }),
+            _ => Err(LobbyError {
+                reason: format!(
+                    "Client {} left game {} while joining it",
+                    client_id, game_id
+                ),
+            }),
+        };
+        if let Err(e) = joined {
+            drop(clients);
+            detach(client_id, game_id, games);
+            return Err(e);
+        }
+        let spectating = state == ClientState::Spectating;
+        client.send(&GameJoined {
+            game_id,
+            spectating,
+        });
+    }
+    let verb = match state {
+        ClientState::Spectating => "is spectating",
+        _ => "joined",
+    };
+    chat::announce_game(
+        game_id,
+        format!("{} {} the game", client_id, verb),
+        &clients,
+    );
+
+    if let Some(start) = lockstep_start {
+        for player in start.players.iter() {
+            if let Some(client) = clients.get(player) {
+                client.send(&start);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that every client in a group is connected, not in a game, and free to join one.
+fn check_free(members: &[ClientID], clients: &ClientHashmap) -> Result<(), LobbyError> {
+    let clients = clients.lock().unwrap();
+    for member in members {
+        let client = get_client(member, &clients)?;
+        if let Some(current) = client.game_id {
+            return Err(LobbyError {
+                reason: format!("Client {} is already in game {}", member, current),
+            });
+        }
+        if !client.can_change_state(&ClientState::InGame) {
+            return Err(LobbyError {
+                reason: format!(
+                    "Client {} can't join a game while {:?}",
+                    member,
+                    client.state()
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn get_client<'a>(
+    client_id: &ClientID,
+    clients: &'a HashMap<ClientID, Client>,
+) -> Result<&'a Client, LobbyError> {
+    clients.get(client_id).ok_or_else(|| LobbyError {
+        reason: format!("Client {} is not connected", client_id),
+    })
+}
+
+/// Checks the server can run another game.
+fn check_capacity(max_games: usize, games: &GameHashmap) -> Result<(), LobbyError> {
+    if games.lock().unwrap().len() >= max_games {
+        return Err(LobbyError {
+            reason: format!("The server is already running {} games", max_games),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::comms::message::MatchmakingStatus;
+use crate::server_side::client::ClientID;
+
+pub type MatchmakerHandle = Arc<Mutex<Matchmaker>>;
+
+/// Describes how the Matchmaker groups players
+/// * players_per_game - How many players are placed in each game.
+/// * rating_range - The largest rating difference allowed when a ticket is first queued.
+/// * widen_per_sec - How much the allowed rating difference grows for every second spent waiting.
+/// * max_rating_range - The largest rating difference allowed, no matter how long a ticket waits.
+#[derive(Clone, Copy, Debug)]
+pub struct MatchmakingConfig {
+    pub players_per_game: usize,
+    pub rating_range: u32,
+    pub widen_per_sec: u32,
+    pub max_rating_range: u32,
+}
+
+impl Default for MatchmakingConfig {
+    fn default() -> Self {
+        MatchmakingConfig {
+            players_per_game: 2,
+            rating_range: 100,
+            widen_per_sec: 25,
+            max_rating_range: 1000,
+        }
+    }
+}
+
+/// An entry in the matchmaking queue
+/// * members - Every client queued by this ticket. They are always placed in the same game.
+/// * mode - The game mode the ticket is queued for.
+/// * rating - The skill rating used to find compatible tickets.
+/// * enqueued_at - When the ticket entered the queue.
+#[derive(Clone, Debug)]
+pub struct MatchTicket {
+    pub members: Vec<ClientID>,
+    pub mode: String,
+    pub rating: u32,
+    pub enqueued_at: Instant,
+}
+
+impl MatchTicket {
+    pub fn new<S: Into<String>>(members: Vec<ClientID>, mode: S, rating: u32) -> MatchTicket {
+        MatchTicket {
+            members,
+            mode: mode.into(),
+            rating,
+            enqueued_at: Instant::now(),
+        }
+    }
+
+    /// The rating difference this ticket will accept after waiting until `now`.
+    fn rating_range(&self, now: Instant, config: &MatchmakingConfig) -> u32 {
+        let waited = now.duration_since(self.enqueued_at).as_secs() as u32;
+        let range = config.rating_range + config.widen_per_sec.saturating_mul(waited);
+        range.min(config.max_rating_range)
+    }
+
+    fn compatible(&self, other: &MatchTicket, now: Instant, config: &MatchmakingConfig) -> bool {
+        let diff = self.rating.abs_diff(other.rating);
+        let range = self
+            .rating_range(now, config)
+            .max(other.rating_range(now, config));
+        self.mode == other.mode && diff <= range
+    }
+}
+
+/// Groups queued tickets into games.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::server_side::matchmaking::{MatchTicket, Matchmaker, MatchmakingConfig};
+/// use std::time::Instant;
+///
+/// let mut matchmaker = Matchmaker::new(MatchmakingConfig::default());
+/// matchmaker.enqueue(MatchTicket::new(vec![String::from("alice")], "duel", 1000));
+/// matchmaker.enqueue(MatchTicket::new(vec![String::from("bob")], "duel", 1050));
+/// matchmaker.enqueue(MatchTicket::new(vec![String::from("carol")], "race", 1000));
+///
+/// let matches = matchmaker.form_matches(Instant::now());
+/// assert_eq!(matches.len(), 1);
+/// assert_eq!(matches[0].len(), 2);
+/// assert_eq!(matchmaker.queued(), 1);
+/// ```
+pub struct Matchmaker {
+    pub config: MatchmakingConfig,
+    queue: Vec<MatchTicket>,
+    /// Running average of how long matched tickets waited, used to estimate wait times.
+    average_wait: Option<Duration>,
+}
+
+impl Matchmaker {
+    pub fn new(config: MatchmakingConfig) -> Matchmaker {
+        Matchmaker {
+            config,
+            queue: Vec::new(),
+            average_wait: None,
+        }
+    }
+
+    /// Adds a ticket to the back of the queue. Any ticket already holding one of its members is
+    /// replaced.
+    pub fn enqueue(&mut self, ticket: MatchTicket) {
+        for member in ticket.members.iter() {
+            self.dequeue(member);
+        }
+        self.queue.push(ticket);
+    }
+
+    /// Removes the ticket holding a client from the queue.
+    ///
+    /// # Returns
+    ///
+    /// * The removed ticket, or None if the client wasn't queued.
+    pub fn dequeue(&mut self, client_id: &ClientID) -> Option<MatchTicket> {
+        let index = self
+            .queue
+            .iter()
+            .position(|t| t.members.contains(client_id))?;
+        Some(self.queue.remove(index))
+    }
+
+    /// Returns the number of tickets in the queue.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns true if the client is in the queue.
+    pub fn is_queued(&self, client_id: &ClientID) -> bool {
+        self.queue.iter().any(|t| t.members.contains(client_id))
+    }
+
+    /// Groups compatible tickets into games, oldest tickets first. Each returned group holds
+    /// exactly `players_per_game` clients; matched tickets are removed from the queue.
+    pub fn form_matches(&mut self, now: Instant) -> Vec<Vec<MatchTicket>> {
+        let players_per_game = self.config.players_per_game;
+        let mut matches = Vec::new();
+        let mut anchor = 0;
+
+        while anchor < self.queue.len() {
+            let mut group = vec![anchor];
+            let mut size = self.queue[anchor].members.len();
+
+            for candidate in (anchor + 1)..self.queue.len() {
+                if size == players_per_game {
+                    break;
+                }
+                let ticket = &self.queue[candidate];
+                let fits = size + ticket.members.len() <= players_per_game;
+                let compatible = group
+                    .iter()
+                    .all(|i| self.queue[*i].compatible(ticket, now, &self.config));
+                if fits && compatible {
+                    group.push(candidate);
+                    size += ticket.members.len();
+                }
+            }
+
+            if size == players_per_game {
+                // Remove from the back so the earlier indices stay valid.
+                let mut tickets = Vec::with_capacity(group.len());
+                for index in group.into_iter().rev() {
+                    let ticket = self.queue.remove(index);
+                    self.record_wait(now.duration_since(ticket.enqueued_at));
+                    tickets.push(ticket);
+                }
+                tickets.reverse();
+                matches.push(tickets);
+            } else {
+                anchor += 1;
+            }
+        }
+
+        matches
+    }
+
+    /// Returns the queue status of every queued client.
+    pub fn statuses(&self, now: Instant) -> Vec<(ClientID, MatchmakingStatus)> {
+        let mut statuses = Vec::new();
+        for (position, ticket) in self.queue.iter().enumerate() {
+            let waited = now.duration_since(ticket.enqueued_at);
+            let estimated_wait = self
+                .average_wait
+                .map(|avg| avg.checked_sub(waited).unwrap_or_default().as_secs());
+            for member in ticket.members.iter() {
+                statuses.push((
+                    member.clone(),
+                    MatchmakingStatus {
+                        position: position + 1,
+                        queued: self.queue.len(),
+                        waited_secs: waited.as_secs(),
+                        estimated_wait_secs: estimated_wait,
+                        rating_range: ticket.rating_range(now, &self.config),
+                    },
+                ));
+            }
+        }
+        statuses
+    }
+
+    fn record_wait(&mut self, wait: Duration) {
+        self.average_wait = Some(match self.average_wait {
+            Some(avg) => (avg * 4 + wait) / 5,
+            None => wait,
+        });
+    }
+}
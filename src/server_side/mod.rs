@@ -1,2 +1,14 @@
+pub mod admin;
+pub mod chat;
 pub mod client;
+pub mod client_handler;
+pub mod config;
+pub mod game_actor;
+pub mod lobby;
+pub mod matchmaking;
+pub mod party;
+pub mod pause;
+pub mod persistence;
+pub mod relay;
+pub mod replays;
 pub mod server;
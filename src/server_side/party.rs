@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::comms::message::PartyUpdate;
+use crate::errors::PartyError;
+use crate::server_side::client::{Client, ClientID};
+
+pub type PartyID = u32;
+pub type PartyHandle = Arc<Mutex<Parties>>;
+
+/// A group of clients who are placed into games together
+/// * leader - The client who can invite others, and queue or join games for the whole party.
+/// * members - Every client in the party, including the leader.
+/// * invites - Clients who have been invited but haven't answered yet.
+#[derive(Clone, Debug)]
+pub struct Party {
+    pub id: PartyID,
+    pub leader: ClientID,
+    pub members: Vec<ClientID>,
+    pub invites: HashSet<ClientID>,
+}
+
+impl Party {
+    /// Returns a PartyUpdate describing the party.
+    pub fn update(&self) -> PartyUpdate {
+        PartyUpdate {
+            party_id: self.id,
+            leader: self.leader.clone(),
+            members: self.members.clone(),
+        }
+    }
+
+    /// Sends a PartyUpdate to every member of the party.
+    pub fn send_update(&self, clients: &HashMap<ClientID, Client>) {
+        let update = self.update();
+        for member in self.members.iter() {
+            if let Some(client) = clients.get(member) {
+                client.send(&update);
+            }
+        }
+    }
+}
+
+/// Tracks every party on the server.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::server_side::party::Parties;
+///
+/// let alice = String::from("alice");
+/// let bob = String::from("bob");
+///
+/// let mut parties = Parties::new();
+/// let party_id = parties.create(&alice).unwrap();
+/// parties.invite(&alice, &bob).unwrap();
+/// parties.accept(&bob, party_id).unwrap();
+///
+/// assert_eq!(parties.members_of(&bob), vec![alice.clone(), bob.clone()]);
+/// assert!(parties.is_leader(&alice));
+/// ```
+#[derive(Default)]
+pub struct Parties {
+    parties: HashMap<PartyID, Party>,
+    next_id: PartyID,
+}
+
+impl Parties {
+    pub fn new() -> Parties {
+        Parties {
+            parties: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Creates a new party led by a client.
+    ///
+    /// # Returns
+    ///
+    /// * The PartyID of the new party.
+    /// * PartyError if the client is already in a party.
+    pub fn create(&mut self, leader: &ClientID) -> Result<PartyID, PartyError> {
+        if let Some(party) = self.party_of(leader) {
+            return Err(PartyError {
+                reason: format!("{} is already in party {}", leader, party.id),
+            });
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.parties.insert(
+            id,
+            Party {
+                id,
+                leader: leader.clone(),
+                members: vec![leader.clone()],
+                invites: HashSet::new(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Invites a client to the party led by `from`.
+    ///
+    /// # Returns
+    ///
+    /// * The PartyID the client was invited to.
+    /// * PartyError if `from` doesn't lead a party, or `to` is already in one.
+    pub fn invite(&mut self, from: &ClientID, to: &ClientID) -> Result<PartyID, PartyError> {
+        if let Some(party) = self.party_of(to) {
+            return Err(PartyError {
+                reason: format!("{} is already in party {}", to, party.id),
+            });
+        }
+        let party = self.led_by_mut(from)?;
+        party.invites.insert(to.clone());
+        Ok(party.id)
+    }
+
+    /// Accepts an invite, adding the client to the party.
+    ///
+    /// # Returns
+    ///
+    /// * PartyError if the client wasn't invited, or is already in a party.
+    pub fn accept(&mut self, client_id: &ClientID, party_id: PartyID) -> Result<(), PartyError> {
+        if let Some(party) = self.party_of(client_id) {
+            return Err(PartyError {
+                reason: format!("{} is already in party {}", client_id, party.id),
+            });
+        }
+        let party = self.invited_to_mut(client_id, party_id)?;
+        party.invites.remove(client_id);
+        party.members.push(client_id.clone());
+        Ok(())
+    }
+
+    /// Declines an invite.
+    ///
+    /// # Returns
+    ///
+    /// * PartyError if the client wasn't invited.
+    pub fn decline(&mut self, client_id: &ClientID, party_id: PartyID) -> Result<(), PartyError> {
+        let party = self.invited_to_mut(client_id, party_id)?;
+        party.invites.remove(client_id);
+        Ok(())
+    }
+
+    /// Removes a client from their party. If the leader leaves, the next member becomes the
+    /// leader. Empty parties are disbanded. Pending invites to the client are dropped.
+    ///
+    /// # Returns
+    ///
+    /// * The party after the client left, or None if it was disbanded or the client wasn't in one.
+    pub fn leave(&mut self, client_id: &ClientID) -> Option<Party> {
+        for party in self.parties.values_mut() {
+            party.invites.remove(client_id);
+        }
+
+        let party_id = self.party_of(client_id)?.id;
+        let party = self.parties.get_mut(&party_id)?;
+        party.members.retain(|m| m != client_id);
+
+        if party.members.is_empty() {
+            self.parties.remove(&party_id);
+            return None;
+        }
+        if &party.leader == client_id {
+            party.leader = party.members[0].clone();
+        }
+        Some(party.clone())
+    }
+
+    /// Returns the party a client is a member of.
+    pub fn party_of(&self, client_id: &ClientID) -> Option<&Party> {
+        self.parties
+            .values()
+            .find(|p| p.members.contains(client_id))
+    }
+
+    /// Returns true if the client leads a party.
+    pub fn is_leader(&self, client_id: &ClientID) -> bool {
+        self.party_of(client_id)
+            .is_some_and(|p| &p.leader == client_id)
+    }
+
+    /// Returns every member of the client's party, or just the client if they aren't in one.
+    pub fn members_of(&self, client_id: &ClientID) -> Vec<ClientID> {
+        match self.party_of(client_id) {
+            Some(party) => party.members.clone(),
+            None => vec![client_id.clone()],
+        }
+    }
+
+    fn led_by_mut(&mut self, leader: &ClientID) -> Result<&mut Party, PartyError> {
+        self.parties
+            .values_mut()
+            .find(|p| &p.leader == leader)
+            .ok_or_else(|| PartyError {
+                reason: format!("{} doesn't lead a party", leader),
+            })
+    }
+
+    fn invited_to_mut(
+        &mut self,
+        client_id: &ClientID,
+        party_id: PartyID,
+    ) -> Result<&mut Party, PartyError> {
+        match self.parties.get_mut(&party_id) {
+            Some(party) if party.invites.contains(client_id) => Ok(party),
+            _ => Err(PartyError {
+                reason: format!("{} has no invite to party {}", client_id, party_id),
+            }),
+        }
+    }
+}
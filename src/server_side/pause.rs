@@ -0,0 +1,110 @@
+use std::time::Instant;
+
+use crate::comms::message::{GameStateChanged, PauseCountdown, PauseVoteStatus};
+use crate::errors::PauseError;
+use crate::game::controller::GameController;
+use crate::game::model::GameState;
+use crate::game::pause::{self, PauseRules};
+use crate::game::GameID;
+use crate::server_side::client::{self, ClientID, ClientSender};
+use crate::server_side::game_actor;
+use crate::server_side::server::{ClientHashmap, GameHashmap};
+
+/// A message about the state of a game, waiting to be sent to everyone in it.
+pub enum StateNotice {
+    Vote(PauseVoteStatus),
+    Countdown(PauseCountdown),
+    Changed(GameStateChanged),
+}
+
+impl StateNotice {
+    pub fn send(&self, client: &mut ClientSender) {
+        match self {
+            StateNotice::Vote(msg) => client.send(msg),
+            StateNotice::Countdown(msg) => client.send(msg),
+            StateNotice::Changed(msg) => client.send(msg),
+        }
+    }
+}
+
+/// Records a client's vote to pause or resume the game they are playing, and tells everyone in
+/// the game how the vote stands.
+///
+/// # Returns
+///
+/// * PauseError if the client isn't playing a game, or the vote was refused.
+pub fn vote(
+    client_id: &ClientID,
+    state: GameState,
+    rules: &PauseRules,
+    clients: &ClientHashmap,
+    games: &GameHashmap,
+) -> Result<(), PauseError> {
+    let game_id = {
+        let clients = clients.lock().unwrap();
+        clients.get(client_id).and_then(|c| c.game_id)
+    }
+    .ok_or_else(|| PauseError {
+        reason: String::from("Not in a game"),
+    })?;
+
+    let game = game_actor::find(games, game_id).ok_or_else(|| PauseError {
+        reason: format!("Game {} does not exist", game_id),
+    })?;
+    let client_id = client_id.clone();
+    let rules = *rules;
+    let (audience, notices) = game.call(move |game_id, game| {
+        let progress = game.vote_state(&client_id, state, &rules)?;
+        let mut notices = vec![StateNotice::Vote(PauseVoteStatus {
+            game_id,
+            client_id,
+            state,
+            votes: progress.votes,
+            needed: progress.needed,
+            countdown: progress.countdown.map(pause::whole_seconds),
+        })];
+        notices.extend(transitions(game_id, game));
+        Ok::<_, PauseError>((game.audience(), notices))
+    })?;
+
+    send(&audience, &notices, clients);
+    Ok(())
+}
+
+/// Changes the state of a game whose pause or resume countdown is over.
+///
+/// # Returns
+///
+/// * The notices to send to everyone in the game: how long is left of a countdown which is still
+///   running, whenever another second has passed, and every state the game changed to since the
+///   last call.
+pub fn poll(game_id: GameID, game: &mut GameController, now: Instant) -> Vec<StateNotice> {
+    game.update_pause(now);
+    let mut notices = Vec::new();
+    if let Some((state, seconds)) = game.pause.countdown_changed(now) {
+        notices.push(StateNotice::Countdown(PauseCountdown {
+            game_id,
+            state,
+            seconds,
+        }));
+    }
+    notices.extend(transitions(game_id, game));
+    notices
+}
+
+/// Sends notices to every client in an audience who is still connected. The ClientHashmap is
+/// released before anything is written.
+pub fn send(audience: &[ClientID], notices: &[StateNotice], clients: &ClientHashmap) {
+    for mut client in client::senders(clients, audience).into_values() {
+        for notice in notices {
+            notice.send(&mut client);
+        }
+    }
+}
+
+fn transitions(game_id: GameID, game: &mut GameController) -> Vec<StateNotice> {
+    game.take_transitions()
+        .into_iter()
+        .map(|state| StateNotice::Changed(GameStateChanged { game_id, state }))
+        .collect()
+}
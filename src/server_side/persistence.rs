@@ -0,0 +1,104 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::game::controller::GameController;
+use crate::game::GameID;
+use crate::server_side::game_actor::{self, GameHandle};
+use crate::server_side::server::GameHashmap;
+
+const SAVE_PREFIX: &str = "game_";
+const SAVE_EXTENSION: &str = "json";
+const TMP_EXTENSION: &str = "json.tmp";
+
+/// Returns the path a game is saved to inside a save directory.
+pub fn save_path(dir: &Path, game_id: GameID) -> PathBuf {
+    dir.join(format!("{}{}.{}", SAVE_PREFIX, game_id, SAVE_EXTENSION))
+}
+
+/// Returns the GameID of a save file, or None if the file isn't a save.
+fn game_id_of(path: &Path) -> Option<GameID> {
+    if path.extension()? != SAVE_EXTENSION {
+        return None;
+    }
+    path.file_stem()?
+        .to_str()?
+        .strip_prefix(SAVE_PREFIX)?
+        .parse()
+        .ok()
+}
+
+/// Writes every game to a save directory, and removes the saves of games which no longer exist.
+/// Lockstep games are skipped, since their peers hold their own copy of the world.
+///
+/// # Returns
+///
+/// * The number of games saved.
+pub fn save_games(games: &GameHashmap, dir: &Path) -> io::Result<usize> {
+    fs::create_dir_all(dir)?;
+
+    // Each game is serialized by its actor, but the files are written here.
+    let saves: Vec<_> = game_actor::all(games)
+        .iter()
+        .filter(|game| !game.is_lockstep())
+        .map(|game| (game.game_id(), game.call(|_, game| game.save())))
+        .collect();
+
+    for (game_id, saved) in saves.iter() {
+        let json = serde_json::to_string_pretty(saved)?;
+        write_atomic(&save_path(dir, *game_id), json.as_bytes())?;
+    }
+
+    // Only once every game is saved, so a failed save never loses a game.
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if let Some(game_id) = game_id_of(&path) {
+            if !saves.iter().any(|(id, _)| *id == game_id) {
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+    Ok(saves.len())
+}
+
+/// Writes a file through a temporary file renamed into place, so a crash mid-write leaves the
+/// last save whole rather than a truncated one.
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension(TMP_EXTENSION);
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+/// Loads every game in a save directory into the GameHashmap as actors, keeping their GameIDs. Saves which
+/// can't be read are reported and skipped.
+///
+/// # Returns
+///
+/// * The number of games loaded. A missing directory loads nothing.
+pub fn load_games(games: &GameHashmap, dir: &Path) -> io::Result<usize> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+
+    let mut loaded = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let game_id = match game_id_of(&path) {
+            Some(game_id) => game_id,
+            None => continue,
+        };
+        match GameController::load_from_file(&path) {
+            Ok(game) => loaded.push((game_id, game)),
+            Err(e) => warn!(path = %path.display(), error = %e, "Failed to load game"),
+        }
+    }
+
+    let count = loaded.len();
+    let mut games = games.lock().unwrap();
+    for (game_id, game) in loaded {
+        info!(game_id, "Game loaded");
+        games.insert(game_id, GameHandle::new(game_id, game));
+    }
+    Ok(count)
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+use crate::comms::message::{self, GameSnapshot, HostAssigned, HostedGameSummary, Relay, Relayed};
+use crate::errors::RelayError;
+use crate::game::GameID;
+use crate::server_side::client::{Client, ClientID};
+use crate::server_side::server::ClientHashmap;
+
+pub type HostedGameHandle = Arc<Mutex<HostedGames>>;
+
+/// A game run authoritatively by one of its players. The server only tracks who is in the game
+/// and relays messages between them.
+/// * host - The client running the GameController.
+/// * peers - Every other client in the game, in the order they joined.
+/// * snapshot - The latest snapshot published by the host. Used to hand authority to a new host.
+#[derive(Clone, Debug)]
+pub struct HostedGame {
+    pub game_id: GameID,
+    pub host: ClientID,
+    pub peers: Vec<ClientID>,
+    pub snapshot: Option<GameSnapshot>,
+}
+
+impl HostedGame {
+    /// Returns the host followed by every peer.
+    pub fn members(&self) -> Vec<ClientID> {
+        let mut members = vec![self.host.clone()];
+        members.extend(self.peers.iter().cloned());
+        members
+    }
+
+    pub fn contains(&self, client_id: &ClientID) -> bool {
+        &self.host == client_id || self.peers.contains(client_id)
+    }
+}
+
+/// What happened to a hosted game when a client left it
+/// * PeerLeft - A peer left, the host is unchanged.
+/// * Migrated - The host left, and authority moved to the oldest peer.
+/// * Closed - The last client left, and the game was removed.
+pub enum Departure {
+    PeerLeft(HostedGame),
+    Migrated(HostedGame),
+    Closed(GameID),
+}
+
+/// Tracks every peer-hosted game on the server.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::server_side::relay::{Departure, HostedGames};
+///
+/// let alice = String::from("alice");
+/// let bob = String::from("bob");
+///
+/// let mut hosted = HostedGames::new();
+/// let game_id = hosted.host(&alice).unwrap();
+/// hosted.join(game_id, &bob).unwrap();
+///
+/// match hosted.leave(&alice) {
+///     Some(Departure::Migrated(game)) => assert_eq!(game.host, bob),
+///     _ => panic!("Host should have migrated"),
+/// }
+/// ```
+#[derive(Default)]
+pub struct HostedGames {
+    games: HashMap<GameID, HostedGame>,
+    next_id: GameID,
+}
+
+impl HostedGames {
+    pub fn new() -> HostedGames {
+        HostedGames {
+            games: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Registers a new game hosted by a client.
+    ///
+    /// # Returns
+    ///
+    /// * The GameID of the hosted game.
+    /// * RelayError if the client is already in a hosted game.
+    pub fn host(&mut self, host: &ClientID) -> Result<GameID, RelayError> {
+        self.check_free(host)?;
+        let game_id = self.next_id;
+        self.next_id += 1;
+        self.games.insert(
+            game_id,
+            HostedGame {
+                game_id,
+                host: host.clone(),
+                peers: Vec::new(),
+                snapshot: None,
+            },
+        );
+        Ok(game_id)
+    }
+
+    /// Adds a peer to a hosted game.
+    ///
+    /// # Returns
+    ///
+    /// * The game after the peer joined.
+    /// * RelayError if the client is already in a hosted game, or the game doesn't exist.
+    pub fn join(&mut self, game_id: GameID, peer: &ClientID) -> Result<HostedGame, RelayError> {
+        self.check_free(peer)?;
+        let game = self.games.get_mut(&game_id).ok_or_else(|| RelayError {
+            reason: format!("Hosted game {} does not exist", game_id),
+        })?;
+        game.peers.push(peer.clone());
+        Ok(game.clone())
+    }
+
+    /// Removes a client from their hosted game. If the client was the host, authority moves to
+    /// the peer who joined first.
+    ///
+    /// # Returns
+    ///
+    /// * What happened to the game, or None if the client wasn't in a hosted game.
+    pub fn leave(&mut self, client_id: &ClientID) -> Option<Departure> {
+        let game_id = self.game_of(client_id)?.game_id;
+        let game = self.games.get_mut(&game_id)?;
+
+        if &game.host != client_id {
+            game.peers.retain(|p| p != client_id);
+            return Some(Departure::PeerLeft(game.clone()));
+        }
+
+        if game.peers.is_empty() {
+            self.games.remove(&game_id);
+            return Some(Departure::Closed(game_id));
+        }
+
+        game.host = game.peers.remove(0);
+        Some(Departure::Migrated(game.clone()))
+    }
+
+    /// Records the latest snapshot published by the host of a game.
+    pub fn record_snapshot(&mut self, game_id: GameID, snapshot: GameSnapshot) {
+        if let Some(game) = self.games.get_mut(&game_id) {
+            game.snapshot = Some(snapshot);
+        }
+    }
+
+    /// Returns the hosted game a client is in.
+    pub fn game_of(&self, client_id: &ClientID) -> Option<&HostedGame> {
+        self.games.values().find(|g| g.contains(client_id))
+    }
+
+    /// Returns a summary of every hosted game, for discovery.
+    pub fn summaries(&self) -> Vec<HostedGameSummary> {
+        let mut summaries: Vec<_> = self
+            .games
+            .values()
+            .map(|g| HostedGameSummary {
+                game_id: g.game_id,
+                host: g.host.clone(),
+                peers: g.peers.len(),
+            })
+            .collect();
+        summaries.sort_by_key(|s| s.game_id);
+        summaries
+    }
+
+    fn check_free(&self, client_id: &ClientID) -> Result<(), RelayError> {
+        match self.game_of(client_id) {
+            Some(game) => Err(RelayError {
+                reason: format!("{} is already in hosted game {}", client_id, game.game_id),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Forwards a message from a client to the other clients of its peer-hosted game. Snapshots sent
+/// by the host are recorded, so authority can move to a peer if the host drops.
+///
+/// # Arguments
+///
+/// * 'sender' - The ClientID of the client who sent the Relay.
+/// * 'msg' - The Relay to forward.
+/// * 'hosted' - A reference to the HostedGames.
+/// * 'clients' - A reference to the ClientHashmap.
+///
+/// # Returns
+///
+/// * RelayError if the sender isn't in a hosted game, or the recipient isn't in the same game.
+pub fn forward(
+    sender: &ClientID,
+    msg: Relay,
+    hosted: &HostedGameHandle,
+    clients: &ClientHashmap,
+) -> Result<(), RelayError> {
+    let (game_id, recipients) = {
+        let mut hosted = hosted.lock().unwrap();
+        let game = hosted.game_of(sender).cloned().ok_or_else(|| RelayError {
+            reason: format!("{} is not in a hosted game", sender),
+        })?;
+        let is_host = &game.host == sender;
+
+        let recipients = match &msg.to {
+            Some(to) if game.contains(to) => vec![to.clone()],
+            Some(to) => {
+                return Err(RelayError {
+                    reason: format!("{} is not in hosted game {}", to, game.game_id),
+                })
+            }
+            None if is_host => game.peers.clone(),
+            None => vec![game.host.clone()],
+        };
+
+        if is_host && msg.payload["msg_type"] == message::GAME_SNAPSHOT_IDENTIFIER {
+            if let Ok(snapshot) = serde_json::from_value(msg.payload["data"].clone()) {
+                hosted.record_snapshot(game.game_id, snapshot);
+            }
+        }
+        (game.game_id, recipients)
+    };
+
+    let relayed = Relayed {
+        game_id,
+        from: sender.clone(),
+        payload: msg.payload,
+    };
+    let clients = clients.lock().unwrap();
+    for recipient in recipients {
+        if let Some(client) = clients.get(&recipient) {
+            client.send(&relayed);
+        }
+    }
+    Ok(())
+}
+
+/// Removes a client from their peer-hosted game. The host is told when a peer leaves, and every
+/// peer is told when authority moves to a new host.
+///
+/// # Returns
+///
+/// * True if the client was in a hosted game.
+pub fn leave_hosted_game(
+    client_id: &ClientID,
+    hosted: &HostedGameHandle,
+    clients: &ClientHashmap,
+) -> bool {
+    let departure = hosted.lock().unwrap().leave(client_id);
+    let clients = clients.lock().unwrap();
+    match departure {
+        Some(Departure::PeerLeft(game)) => {
+            if let Some(host) = clients.get(&game.host) {
+                host.send(&message::PeerLeft {
+                    game_id: game.game_id,
+                    client_id: client_id.clone(),
+                });
+            }
+            true
+        }
+        Some(Departure::Migrated(game)) => {
+            info!(game_id = game.game_id, host = %game.host, "Host left, migrating");
+            assign_host(&game, &clients);
+            true
+        }
+        Some(Departure::Closed(game_id)) => {
+            info!(game_id, "Hosted game closed");
+            true
+        }
+        None => false,
+    }
+}
+
+/// Tells every client of a hosted game who the host is, along with the latest snapshot.
+pub fn assign_host(game: &HostedGame, clients: &HashMap<ClientID, Client>) {
+    let msg = HostAssigned {
+        game_id: game.game_id,
+        host: game.host.clone(),
+        snapshot: game.snapshot.clone(),
+    };
+    for member in game.members() {
+        if let Some(client) = clients.get(&member) {
+            client.send(&msg);
+        }
+    }
+}
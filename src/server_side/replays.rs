@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::comms::message::{GameJoined, GameLeft, ReplayAction, ReplayList, ReplayStatus};
+use crate::errors::ReplayError;
+use crate::game::replay::{self, Replay, ReplayPlayer};
+use crate::server_side::client::{ClientID, ClientState};
+use crate::server_side::game_actor;
+use crate::server_side::server::{ClientHashmap, GameHashmap};
+use crate::state::State;
+
+const REPLAY_EXTENSION: &str = "jsonl";
+
+/// A replay being streamed to a client.
+/// * player - Plays the replay back.
+/// * next_frame - When the replay should next be stepped.
+pub struct ReplayViewer {
+    pub player: ReplayPlayer,
+    pub next_frame: Instant,
+}
+
+impl ReplayViewer {
+    pub fn status(&self) -> ReplayStatus {
+        ReplayStatus {
+            tick: self.player.tick(),
+            end_tick: self.player.replay.end_tick(),
+            paused: self.player.paused,
+            speed: self.player.speed,
+        }
+    }
+}
+
+/// The replay each client is watching.
+pub type ReplayViewers = Arc<Mutex<HashMap<ClientID, ReplayViewer>>>;
+
+/// Returns the path a replay is recorded to inside a replay directory.
+pub fn replay_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.{}", name, REPLAY_EXTENSION))
+}
+
+/// Appends everything recorded since the last call to the replay file of each game being
+/// recorded. Events recorded after the last call are lost when a game ends.
+///
+/// # Returns
+///
+/// * The number of replay files written to.
+pub fn record_replays(games: &GameHashmap, dir: &Path) -> io::Result<usize> {
+    fs::create_dir_all(dir)?;
+
+    // Each recorder is drained by its game's actor, but the files are written here.
+    let chunks: Vec<_> = game_actor::all(games)
+        .iter()
+        .filter_map(|game| {
+            game.call(|_, game| {
+                let tick = game.tick;
+                let recorder = game.recorder.as_mut()?;
+                Some((recorder.name().to_owned(), recorder.drain(tick)))
+            })
+        })
+        .collect();
+
+    for (name, chunk) in chunks.iter() {
+        chunk.write_to_file(replay_path(dir, name))?;
+    }
+    Ok(chunks.len())
+}
+
+/// Returns the name of every replay in a replay directory, sorted by name.
+pub fn replay_list(dir: &Path) -> io::Result<ReplayList> {
+    let mut replays = Vec::new();
+    if dir.exists() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == REPLAY_EXTENSION) {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    replays.push(name.to_owned());
+                }
+            }
+        }
+    }
+    replays.sort();
+    Ok(ReplayList { replays })
+}
+
+/// Starts streaming a replay to a client, as if they were spectating its game.
+///
+/// # Returns
+///
+/// * ReplayError if the client is busy, the name isn't a replay, or the replay can't be read.
+pub fn watch_replay(
+    client_id: &ClientID,
+    name: &str,
+    dir: &Path,
+    viewers: &ReplayViewers,
+    clients: &ClientHashmap,
+) -> Result<(), ReplayError> {
+    // Names come from clients, so they must not be able to reach outside the replay directory.
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(ReplayError {
+            reason: format!("{} isn't a replay", name),
+        });
+    }
+    let replay = Replay::load_from_file(replay_path(dir, name)).map_err(|e| ReplayError {
+        reason: format!("Failed to load {}: {}", name, e),
+    })?;
+    let game_id = replay.header.game_id;
+    let viewer = ReplayViewer {
+        player: ReplayPlayer::new(replay),
+        next_frame: Instant::now(),
+    };
+
+    {
+        let mut clients = clients.lock().unwrap();
+        let client = clients.get_mut(client_id).ok_or_else(|| ReplayError {
+            reason: format!("Client {} is not connected", client_id),
+        })?;
+        if client.game_id.is_some() || client.state() != ClientState::Waiting {
+            return Err(ReplayError {
+                reason: format!(
+                    "Client {} must be out of a game to watch a replay",
+                    client_id
+                ),
+            });
+        }
+        client
+            .change_state(ClientState::Spectating)
+            .map_err(|e| ReplayError {
+                reason: e.to_string(),
+            })?;
+        client.send(&GameJoined {
+            game_id,
+            spectating: true,
+        });
+        client.send(&viewer.player.snapshot());
+    }
+
+    viewers.lock().unwrap().insert(client_id.clone(), viewer);
+    Ok(())
+}
+
+/// Stops streaming a replay to a client.
+///
+/// # Returns
+///
+/// * False if the client wasn't watching a replay.
+pub fn stop_watching(
+    client_id: &ClientID,
+    viewers: &ReplayViewers,
+    clients: &ClientHashmap,
+) -> bool {
+    let viewer = match viewers.lock().unwrap().remove(client_id) {
+        Some(viewer) => viewer,
+        None => return false,
+    };
+
+    let mut clients = clients.lock().unwrap();
+    if let Some(client) = clients.get_mut(client_id) {
+        if let Err(e) = client.change_state(ClientState::Waiting) {
+            warn!(%client_id, error = %e, "Failed to stop watching replay");
+        }
+        client.send(&GameLeft {
+            game_id: viewer.player.replay.header.game_id,
+        });
+    }
+    true
+}
+
+/// Applies a control to the replay a client is watching, and sends them its new status. Replays
+/// play back one tick every `tick_interval` at normal speed.
+///
+/// # Returns
+///
+/// * ReplayError if the client isn't watching a replay.
+pub fn control(
+    client_id: &ClientID,
+    action: ReplayAction,
+    tick_interval: Duration,
+    viewers: &ReplayViewers,
+    clients: &ClientHashmap,
+) -> Result<(), ReplayError> {
+    let (status, snapshot) = {
+        let mut viewers = viewers.lock().unwrap();
+        let viewer = viewers.get_mut(client_id).ok_or_else(|| ReplayError {
+            reason: format!("Client {} isn't watching a replay", client_id),
+        })?;
+        let player = &mut viewer.player;
+        match action {
+            ReplayAction::Pause => player.pause(),
+            ReplayAction::Resume => player.resume(),
+            ReplayAction::Seek(tick) => player.seek(tick),
+            ReplayAction::Speed(speed) => player.set_speed(speed),
+        }
+        (viewer.status(), viewer.player.snapshot())
+    };
+
+    // Worked out after the viewers are released, which are only held to store it.
+    let interval = replay::interval_at(status.speed, tick_interval);
+    let next_frame = Instant::now().checked_add(interval);
+    if let (Some(viewer), Some(next_frame)) =
+        (viewers.lock().unwrap().get_mut(client_id), next_frame)
+    {
+        viewer.next_frame = next_frame;
+    }
+
+    let clients = clients.lock().unwrap();
+    if let Some(client) = clients.get(client_id) {
+        client.send(&status);
+        client.send(&snapshot);
+    }
+    Ok(())
+}
+
+/// Steps every replay which is due, and sends the snapshots to their viewers. A replay which
+/// finishes is paused on its last tick, so the viewer can seek back or leave. Replays play back
+/// one tick every `tick_interval` at normal speed.
+pub fn stream_replays(viewers: &ReplayViewers, clients: &ClientHashmap, tick_interval: Duration) {
+    let now = Instant::now();
+    let mut snapshots = Vec::new();
+    let mut finished = Vec::new();
+    {
+        let mut viewers = viewers.lock().unwrap();
+        for (client_id, viewer) in viewers.iter_mut() {
+            if viewer.player.paused || viewer.next_frame > now {
+                continue;
+            }
+            if !viewer.player.step() {
+                viewer.player.pause();
+                finished.push((client_id.clone(), viewer.status()));
+                continue;
+            }
+            viewer.next_frame = now + viewer.player.tick_interval(tick_interval);
+            snapshots.push((client_id.clone(), viewer.player.snapshot()));
+        }
+    }
+
+    let clients = clients.lock().unwrap();
+    for (client_id, snapshot) in snapshots {
+        if let Some(client) = clients.get(&client_id) {
+            client.send(&snapshot);
+        }
+    }
+    for (client_id, status) in finished {
+        if let Some(client) = clients.get(&client_id) {
+            client.send(&status);
+        }
+    }
+}
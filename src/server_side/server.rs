@@ -1,33 +1,142 @@
 use std::collections::HashMap;
 use std::io::prelude::*;
 use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tracing::{debug, debug_span, info, info_span, trace, warn};
 
-use crate::comms::handler::{DefaultHandler, Handler, TryClone};
+use crate::comms::handler::{Handler, JsonStream, TryClone};
 use crate::comms::message;
 use crate::errors;
-use crate::game::controller;
+use crate::game::pause::PauseRules;
+use crate::metrics::registry::registry;
+use crate::server_side::admin::{self, AdminConfig, BanHandle, BanList};
+use crate::server_side::chat;
 use crate::server_side::client;
+use crate::server_side::client_handler::ClientHandler;
+use crate::server_side::config::{Features, Limits, ServerConfig};
+use crate::server_side::game_actor::{self, Beat, GameHandle};
+use crate::server_side::lobby;
+use crate::server_side::matchmaking::{Matchmaker, MatchmakerHandle, MatchmakingConfig};
+use crate::server_side::party::{Parties, PartyHandle};
+use crate::server_side::pause;
+use crate::server_side::persistence;
+use crate::server_side::relay::{self, HostedGameHandle, HostedGames};
+use crate::server_side::replays::{self, ReplayViewers};
+use crate::threading::loop_handle::LoopConfig;
+use crate::threading::queue::Priority;
+use crate::threading::scheduler::Every;
+use crate::threading::threadpool::PoolConfig;
 use crate::threading::{dispatcher, threadpool};
 
+/// How often games are written to the save directory.
+pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// How often recorded events are appended to replay files.
+pub const REPLAY_WRITE_INTERVAL: Duration = Duration::from_secs(5);
+/// How often replays being watched are checked for a tick which is due.
+pub const REPLAY_FRAME_INTERVAL: Duration = Duration::from_millis(50);
+/// How often queued clients are grouped into games.
+pub const MATCHMAKING_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a write to a client may block before the client is dropped.
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// All client connections are held in a hashmap. The key to this Hashmap is the socket address, and the value is the TcpStream.Arc
 /// Since multiple threads are going to be trying to add, remove, and maniuplate the values in hashmap, it must be protected behind
 /// a mutex.
 type GameID = u32;
-type ClientHashmap = Arc<Mutex<HashMap<client::ClientID, client::Client>>>;
-type GameHashmap = Arc<Mutex<HashMap<GameID, controller::GameController>>>;
+pub type ClientHashmap = Arc<Mutex<HashMap<client::ClientID, client::Client>>>;
+/// Every game runs as an actor, which owns its world. The GameHashmap only holds handles to them,
+/// so it is locked just long enough to find a game, never while one runs.
+pub type GameHashmap = Arc<Mutex<HashMap<GameID, GameHandle>>>;
+
+/// Handles to the state a server shares between its jobs. Every field is reference counted, so
+/// cloning a ServerContext is cheap.
+/// * clients - Tracks client connections.
+/// * games - Games run by the server.
+/// * matchmaker - Groups queued clients into games.
+/// * parties - Tracks parties so friends are placed into games together.
+/// * hosted - Games run by one of their players, which the server only relays for.
+/// * bans - Clients and addresses which may not connect.
+/// * replays - The replay each client is watching.
+/// * replay_dir - Where games are recorded to and replays are read from. None if games aren't
+///   recorded.
+/// * pause_rules - How players vote to pause and resume games.
+/// * tick_interval - How often the systems of each game run.
+/// * features - Parts of the server clients may use.
+/// * limits - How many clients and games the server takes, and when connections time out.
+#[derive(Clone)]
+pub struct ServerContext {
+    pub clients: ClientHashmap,
+    pub games: GameHashmap,
+    pub matchmaker: MatchmakerHandle,
+    pub parties: PartyHandle,
+    pub hosted: HostedGameHandle,
+    pub bans: BanHandle,
+    pub replays: ReplayViewers,
+    pub replay_dir: Option<PathBuf>,
+    pub pause_rules: PauseRules,
+    pub tick_interval: Duration,
+    pub features: Features,
+    pub limits: Limits,
+}
+
+impl ServerContext {
+    pub fn new() -> ServerContext {
+        let clients = HashMap::new();
+        let clients = Arc::new(Mutex::new(clients));
+
+        let games: HashMap<u32, GameHandle> = HashMap::new();
+        let games = Arc::new(Mutex::new(games));
+
+        let matchmaker = Matchmaker::new(MatchmakingConfig::default());
+        let matchmaker = Arc::new(Mutex::new(matchmaker));
+
+        let parties = Arc::new(Mutex::new(Parties::new()));
+        let hosted = Arc::new(Mutex::new(HostedGames::new()));
+        let bans = Arc::new(Mutex::new(BanList::new()));
+        let replays = Arc::new(Mutex::new(HashMap::new()));
+
+        ServerContext {
+            clients,
+            games,
+            matchmaker,
+            parties,
+            hosted,
+            bans,
+            replays,
+            replay_dir: None,
+            pause_rules: PauseRules::default(),
+            tick_interval: Duration::from_secs(1),
+            features: Features::default(),
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl Default for ServerContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 /// Encapsulation of a server
 pub struct Server {
-    /// Servers have a ClientHashmap to asyncronously track client connections.
-    clients: ClientHashmap,
-    games: GameHashmap,
+    /// Servers have a ServerContext holding the state shared between jobs.
+    context: ServerContext,
     /// Servers have a TcpListener to listen for new client connections.
     listener: TcpListener,
     /// Servers have a ThreadPool which dispatches jobs.
     pool: threadpool::ThreadPool,
+    /// How long snapshots are held back from spectators, so they can't feed information to players.
+    spectator_delay: Duration,
+    /// Where games are saved, so they survive a restart. None if games aren't saved.
+    save_dir: Option<PathBuf>,
+    /// Where the admin port listens. None if the server has no admin port.
+    admin: Option<AdminConfig>,
+    /// How often snapshots are published to clients.
+    publish_interval: Duration,
 }
 
 impl Server {
@@ -49,65 +158,215 @@ impl Server {
     /// ```
     ///
     pub fn new(ip: &str, size: usize) -> Server {
-        let listener = TcpListener::bind(ip).unwrap();
-        let pool = threadpool::ThreadPool::new(size);
-
-        let clients = HashMap::new();
-        let clients = Arc::new(Mutex::new(clients));
+        Server::with_pool(ip, PoolConfig::new(size, size))
+    }
 
-        let games: HashMap<u32, controller::GameController> = HashMap::new();
-        let games = Arc::new(Mutex::new(games));
+    /// Returns a new server whose ThreadPool grows and shrinks between a minimum and maximum
+    /// number of workers.
+    pub fn with_pool(ip: &str, pool: PoolConfig) -> Server {
+        let listener = TcpListener::bind(ip).unwrap();
+        let pool = threadpool::ThreadPool::with_config(pool);
 
         Server {
-            clients,
-            games,
+            context: ServerContext::new(),
             listener,
             pool,
+            spectator_delay: Duration::from_secs(0),
+            save_dir: None,
+            admin: None,
+            publish_interval: Duration::from_secs(1),
         }
     }
 
+    /// Returns a new server set up from a config, which should already be validated.
+    ///
+    /// # Example:
+    /// ```
+    /// extern crate multiplayer;
+    /// use multiplayer::server_side::config::ServerConfig;
+    /// use multiplayer::server_side::server;
+    ///
+    /// let mut config = ServerConfig::default();
+    /// config.addr = String::from("127.0.0.1:7879");
+    /// config.validate().unwrap();
+    /// let server = server::Server::from_config(&config);
+    /// // server.start();
+    /// ```
+    pub fn from_config(config: &ServerConfig) -> Server {
+        let pool = PoolConfig::new(config.min_threads, config.threads);
+        let mut server = Server::with_pool(&config.addr, pool);
+        server.set_tick_rate(config.tick_rate);
+        server.set_publish_rate(config.publish_rate);
+        server.set_spectator_delay(config.spectator_delay());
+        server.set_limits(config.limits);
+        server.set_features(config.features);
+        server.set_pause_rules(config.pause.rules());
+        if let Some(dir) = &config.save_dir {
+            server.set_save_dir(dir);
+        }
+        if let Some(dir) = &config.replay_dir {
+            server.set_replay_dir(dir);
+        }
+        if let Some(admin) = &config.admin {
+            server.set_admin(admin.addr.as_str(), admin.token.as_str());
+        }
+        server
+    }
+
+    /// Sets how clients in the matchmaking queue are grouped into games.
+    pub fn set_matchmaking_config(&mut self, config: MatchmakingConfig) {
+        self.context.matchmaker.lock().unwrap().config = config;
+    }
+
+    /// Sets how long game snapshots are delayed before being published to spectators.
+    pub fn set_spectator_delay(&mut self, delay: Duration) {
+        self.spectator_delay = delay;
+    }
+
+    /// Sets the directory games are saved to. Saved games are loaded when the server starts, and
+    /// every game is saved periodically while it runs.
+    pub fn set_save_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.save_dir = Some(dir.into());
+    }
+
+    /// Opens an admin port, which operators log into with `token` to manage the server.
+    pub fn set_admin<S: Into<String>>(&mut self, addr: S, token: S) {
+        self.admin = Some(AdminConfig {
+            addr: addr.into(),
+            token: token.into(),
+        });
+    }
+
+    /// Sets the directory games are recorded to. Every game is recorded while it runs, and
+    /// clients can watch the recordings back.
+    pub fn set_replay_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.context.replay_dir = Some(dir.into());
+    }
+
+    /// Sets how players vote to pause and resume their games.
+    pub fn set_pause_rules(&mut self, rules: PauseRules) {
+        self.context.pause_rules = rules;
+    }
+
+    /// Sets how many times a second the systems of every game run.
+    pub fn set_tick_rate(&mut self, rate: u32) {
+        self.context.tick_interval = Duration::from_secs(1) / rate.max(1);
+    }
+
+    /// Sets how many times a second snapshots are published to clients.
+    pub fn set_publish_rate(&mut self, rate: u32) {
+        self.publish_interval = Duration::from_secs(1) / rate.max(1);
+    }
+
+    /// Sets which parts of the server clients may use.
+    pub fn set_features(&mut self, features: Features) {
+        self.context.features = features;
+    }
+
+    /// Sets how many clients and games the server takes, and when connections time out.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.context.limits = limits;
+    }
+
     /// Starts the server and various jobs.
     ///
+    /// Periodic jobs are scheduled rather than looped, so they only hold a worker while running.
+    /// Game ticks, publishing and replay streaming run at Realtime priority, ahead of handling
+    /// clients, while saving and recording replays run at Background priority.
+    ///
     /// # Jobs
     ///
     /// * 'Publish Data' - Periodically sends data to all connected clients.
-    ///     * Loops until UnexpectedError.
-    ///     * Stars more jobs:
-    ///         * 'Send Message' - Sends a message to a connected client.
-    /// * 'Add Client' - Adds a newly connected client to the ClientHashMap.
+    ///     * Scheduled at a fixed rate until UnexpectedError.
+    ///     * Starts more jobs:
+    ///         * 'Publish Game' - Sends the snapshots of one game to its players and spectators.
+    /// * 'Dispatch Sys' - Periodically runs the systems of every game.
+    ///     * Scheduled at a fixed rate until UnexpectedError.
+    ///     * Starts more jobs:
+    ///         * 'Tick Game' - Runs the systems of one game, and sends what came of it.
     /// * 'Client Listen' - Listens to incoming messages from a connected client.
     ///     * Loops until ClientDisconnectError.
     ///     * Starts more jobs:
     ///         * 'Remove Client' Removes a client from the ClientHashMap.
     ///         * 'Send Message' - Sends a message to a connected client.
+    /// * 'Autosave' - Periodically writes every game to the save directory, if one is set.
+    /// * 'Record Replays' - Periodically appends the events of every game to its replay file, if a
+    ///   replay directory is set.
+    /// * 'Stream Replays' - Sends the replays clients are watching to them.
+    /// * 'Admin' - Accepts connections to the admin port, if one is set.
+    ///     * Starts more jobs:
+    ///         * 'Admin Session' - Runs the commands of an operator.
     pub fn start(self) {
+        let realtime = self.pool.dispatcher.with_priority(Priority::Realtime);
+        let background = self.pool.dispatcher.with_priority(Priority::Background);
+        if let Some(dir) = self.save_dir.clone() {
+            match persistence::load_games(&self.context.games, &dir) {
+                Ok(count) => info!(count, dir = %dir.display(), "Loaded games"),
+                Err(e) => warn!(dir = %dir.display(), error = %e, "Failed to load games"),
+            }
+
+            let games_clone = Arc::clone(&self.context.games);
+            let every = Every::fixed_delay(AUTOSAVE_INTERVAL).named("autosave");
+            background.schedule_every(every, move || autosave(&games_clone, &dir));
+        }
+
+        if let Some(dir) = self.context.replay_dir.clone() {
+            let games_clone = Arc::clone(&self.context.games);
+            let every = Every::fixed_delay(REPLAY_WRITE_INTERVAL).named("record_replays");
+            background.schedule_every(every, move || record_replays(&games_clone, &dir));
+        }
+
+        if let Some(config) = self.admin.clone() {
+            let context = self.context.clone();
+            // Accepting connections blocks, so it gets a thread rather than holding a worker.
+            thread::Builder::new()
+                .name(String::from("admin"))
+                .spawn(move || admin::listen(config, context))
+                .expect("Failed to spawn admin thread");
+        }
+
+        // Stream replays to the clients watching them.
+        let context = self.context.clone();
+        let every = Every::fixed_rate(REPLAY_FRAME_INTERVAL).named("stream_replays");
+        realtime.schedule_every(every, move || stream_replays(&context));
+
         // Publish data continually to each client.
-        let games = Arc::clone(&self.games);
-        let clients = Arc::clone(&self.clients);
-        let dispatch = self.pool.dispatcher.clone();
-        self.pool
-            .dispatcher
-            .execute_loop(move || publish_data(&games, &clients, &dispatch));
+        let context = self.context.clone();
+        let spectator_delay = self.spectator_delay;
+        let every = Every::fixed_rate(self.publish_interval).named("publish_data");
+        let dispatch = realtime.clone();
+        realtime.schedule_every(every, move || {
+            publish_data(&context, &dispatch, spectator_delay)
+        });
 
         // Run game systems
-        let games_clone = Arc::clone(&self.games);
+        let context = self.context.clone();
+        let every = Every::fixed_rate(self.context.tick_interval).named("dispatch_sys");
+        let dispatch = realtime.clone();
+        realtime.schedule_every(every, move || dispatch_sys(&context, &dispatch));
+
+        // Group queued clients into games
+        let context = self.context.clone();
+        let every = Every::fixed_delay(MATCHMAKING_INTERVAL).named("run_matchmaking");
         self.pool
             .dispatcher
-            .execute_loop(move || dispatch_sys(&games_clone));
+            .schedule_every(every, move || run_matchmaking(&context));
 
         loop {
             // Wait for connections
-            if let Ok((stream, _addr)) = self.listener.accept() {
+            if let Ok((stream, addr)) = self.listener.accept() {
+                if self.context.bans.lock().unwrap().is_ip_banned(&addr.ip()) {
+                    info!(%addr, "Refused banned address");
+                    continue;
+                }
                 let dispatch = self.pool.dispatcher.clone();
-                let clients = Arc::clone(&self.clients);
-                let games = Arc::clone(&self.games);
+                let context = self.context.clone();
                 // Get client info
                 self.pool.dispatcher.execute(move || {
                     connect_client(
                         stream.try_clone().expect("Failed to clone stream"),
                         &dispatch,
-                        &clients,
-                        &games,
+                        &context,
                     )
                 })
             }
@@ -126,19 +385,26 @@ impl Server {
 ///
 /// * 'socket' - The TcpStream of the new client.
 /// * 'dispatch' - A reference to a Dispatcher.
-/// * 'clients' - A reference to the ClientHashmap,
-/// * 'games' - A reference to the GameHashmap.
+/// * 'context' - A reference to the ServerContext.
 fn connect_client(
     mut socket: TcpStream,
     dispatch: &dispatcher::Dispatcher,
-    clients: &ClientHashmap,
-    games: &GameHashmap,
+    context: &ServerContext,
 ) {
-    let handler = DefaultHandler {};
+    let limits = context.limits;
+    if let Err(e) = socket.set_read_timeout(limits.handshake_timeout()) {
+        warn!(error = %e, "Failed to set handshake timeout");
+    }
+    // Shared by every clone of the socket, so no write to the client blocks for long.
+    if let Err(e) = socket.set_write_timeout(Some(WRITE_TIMEOUT)) {
+        warn!(error = %e, "Failed to set write timeout");
+    }
 
     // Send request for Client ID.
-    let msg = message::RequestClientID;
-    message::send_json(msg, &mut socket);
+    if let Err(e) = message::try_send_json(&message::RequestClientID, &mut socket) {
+        debug!(error = %e, "Handshake failed");
+        return;
+    }
 
     // Wait for Reply
     let mut buff = vec![0; message::MSG_SIZE];
@@ -146,52 +412,88 @@ fn connect_client(
         // Socket disconnected
         Ok(0) => (),
         // Received Message
-        Ok(_) => {
-            // Check if message is a valid RequestClientIDResponse
-            if handler.is_type(
-                &buff.clone(),
-                message::REQUEST_CLIENT_ID_RESPONSE_IDENTIFIER,
-            ) {
-                // Parse the response
-                let v = handler.parse_json(&buff);
-                let data = v.get("data").unwrap();
-                let data_string = serde_json::to_string(data).expect("Failed to convert data");
-
-                let resp: message::RequestClientIDResponse =
-                    serde_json::from_str(data_string.as_str()).expect("Improper Resonse Format");
-
-                // Create the client object
-                let new_client = client::Client {
-                    id: resp.id,
-                    socket: Some(socket.try_clone().expect("Failed to clone socket")),
-                    game_id: None,
-                    state: client::ClientState::Waiting,
-                };
-
-                let client_clone = new_client.try_clone().expect("Failed to clone Client");
-                let clients_clone = Arc::clone(clients);
-                // Add clients to the ClientsHashmap as playing no game.
-                dispatch.execute(move || {
-                    add_client(client_clone, clients_clone);
+        Ok(n) => {
+            registry().bytes_received.add(n as u64);
+            // A client may send more messages straight after identifying itself, which arrive
+            // in the same read. They are handled once the client has been added, and the stream
+            // keeps any message cut off at the end of the read.
+            let mut stream = JsonStream::new();
+            let mut messages = stream.push(&buff[..n]).into_iter();
+            let resp = messages
+                .next()
+                .and_then(Result::ok)
+                .filter(|v| v["msg_type"] == message::REQUEST_CLIENT_ID_RESPONSE_IDENTIFIER)
+                .and_then(|v| {
+                    serde_json::from_value::<message::RequestClientIDResponse>(v["data"].clone())
+                        .ok()
                 });
+            let resp = match resp {
+                Some(resp) => resp,
+                None => {
+                    warn!("Failed handshake with client, dropping");
+                    return;
+                }
+            };
+            if context.bans.lock().unwrap().is_client_banned(&resp.id) {
+                info!(client_id = %resp.id, "Refused banned client");
+                return;
+            }
+            // From now on a client is only dropped for being quiet if an idle timeout is set.
+            if let Err(e) = socket.set_read_timeout(limits.idle_timeout()) {
+                warn!(error = %e, "Failed to set idle timeout");
+            }
 
-                let clients_clone = Arc::clone(clients);
-                let games_clone = Arc::clone(games);
-                let dispatch_clone = dispatch.clone();
-                // Listen to the client.
-                dispatch.execute_loop(move || {
-                    client_listen(
-                        new_client.try_clone().expect("Failed to clone new Client"),
-                        &clients_clone,
-                        &games_clone,
-                        &dispatch_clone,
-                    )
-                });
-            } else {
-                println!("Failed Handshake with client. Dropping");
+            // Create the client object
+            let new_client = client::Client::new(
+                resp.id,
+                Some(socket.try_clone().expect("Failed to clone socket")),
+            );
+
+            // Add clients to the ClientsHashmap as playing no game. The limit is checked under
+            // the same lock, so clients connecting at once can't take the server past it.
+            {
+                let mut clients = context.clients.lock().unwrap();
+                if clients.len() >= limits.max_clients {
+                    drop(clients);
+                    info!(client_id = %new_client.id, "Refused client, server is full");
+                    let full = message::TextMessage::new("Server is full");
+                    if let Err(e) = message::try_send_json(&full, &mut socket) {
+                        debug!(error = %e, "Failed to tell client the server is full");
+                    }
+                    return;
+                }
+                add_client(
+                    new_client.try_clone().expect("Failed to clone Client"),
+                    &mut clients,
+                );
             }
+
+            let mut handler = ClientHandler::new(new_client.id.clone(), context);
+            let early: Vec<_> = messages.collect();
+            if !early.is_empty() {
+                dispatch.execute(move || handler.receive_messages(early));
+            }
+
+            let context_clone = context.clone();
+            let dispatch_clone = dispatch.clone();
+            let guard = ListenGuard {
+                client_id: new_client.id.clone(),
+                context: context.clone(),
+                dispatch: dispatch.clone(),
+            };
+            // Listen to the client.
+            dispatch.execute_loop_with(LoopConfig::named("client_listen"), move || {
+                // Owned by the loop, so it is dropped when the loop ends.
+                let _guard = &guard;
+                client_listen(
+                    new_client.try_clone().expect("Failed to clone new Client"),
+                    &mut stream,
+                    &context_clone,
+                    &dispatch_clone,
+                )
+            });
         }
-        Err(e) => (),
+        Err(e) => debug!(error = %e, "Handshake failed"),
     }
 }
 
@@ -201,7 +503,8 @@ fn connect_client(
 ///
 /// * 'mut socket' - The TcpStream of the client.
 /// * 'addr' - The SocketAddr of the Client.
-/// * 'map_mutex' - A reference to the ClientHashmap.
+/// * 'stream' - The messages read from the client, holding any cut off by the end of a read.
+/// * 'context' - A reference to the ServerContext.
 /// * 'dispatch' - A reference to a Dispatcher.
 ///
 /// # Returns
@@ -209,56 +512,48 @@ fn connect_client(
 /// * ConnectionStatus
 fn client_listen(
     client: client::Client,
-    map_mutex: &ClientHashmap,
-    game_mutex: &GameHashmap,
+    stream: &mut JsonStream,
+    context: &ServerContext,
     dispatch: &dispatcher::Dispatcher,
 ) -> errors::ConnectionStatus {
-    let mut client_clone = client.try_clone().expect("Failed to clone client");
+    let span = info_span!("connection", client_id = %client.id);
+    let _enter = span.enter();
+    let dropped = client.is_dropped();
     if let Some(mut socket) = client.socket {
         let mut buff = vec![0; message::MSG_SIZE];
 
         match socket.read(&mut buff) {
-            Ok(0) => {
-                // Dispatch remove_client() to remove this client from the hashmap.
-                let id = client.id.clone();
-                let map_clone = Arc::clone(map_mutex);
-                let game_clone = Arc::clone(game_mutex);
-                dispatch.execute(move || {
-                    remove_client(&id, &map_clone, &game_clone);
-                });
-                Err(errors::ClientDisconnectError {
-                    client_id: client.id,
-                })
-            }
-            Ok(_) => {
-                let msg = buff
-                    .clone()
-                    .into_iter()
-                    .take_while(|&x| x != 0)
-                    .collect::<Vec<_>>();
-                let msg = String::from_utf8(msg).expect("Invalid utf8 message");
-                println!("MSG: {}", msg);
-
-                dispatch.execute(move || {
-                    client_clone.receive_json(&buff);
-                });
+            // The ListenGuard removes the client once the loop ends.
+            Ok(0) => Err(errors::ClientDisconnectError {
+                client_id: client.id,
+            }),
+            // A client dropped for not reading is removed, whatever it still has to say.
+            Ok(_) if dropped => Err(errors::ClientDisconnectError {
+                client_id: client.id,
+            }),
+            Ok(n) => {
+                registry().bytes_received.add(n as u64);
+                // A character can be cut off by the end of a read, so this is only for tracing.
+                // Invalid utf8 is refused by the JsonStream like any other malformed message.
+                trace!(msg = %String::from_utf8_lossy(&buff[..n]), "Received message");
+
+                let messages = stream.push(&buff[..n]);
+                if !messages.is_empty() {
+                    let mut handler = ClientHandler::new(client.id.clone(), context);
+                    let span = span.clone();
+                    dispatch.execute(move || {
+                        let _enter = span.enter();
+                        handler.receive_messages(messages);
+                    });
+                }
 
                 // Say everything is Ok
                 Ok(())
             }
             // Failed to read to buffer.
-            Err(_) => {
-                // Dispatch remove client to remove this client from the hashmap.
-                let id = client.id.clone();
-                let map_clone = Arc::clone(map_mutex);
-                let game_clone = Arc::clone(game_mutex);
-                dispatch.execute(move || {
-                    remove_client(&id, &map_clone, &game_clone);
-                });
-                Err(errors::ClientDisconnectError {
-                    client_id: client.id.clone(),
-                })
-            }
+            Err(_) => Err(errors::ClientDisconnectError {
+                client_id: client.id,
+            }),
         }
     } else {
         Err(errors::ClientDisconnectError {
@@ -267,6 +562,23 @@ fn client_listen(
     }
 }
 
+/// Removes a client once the loop listening to it ends, whether it disconnected, failed, panicked
+/// or was cancelled.
+struct ListenGuard {
+    client_id: client::ClientID,
+    context: ServerContext,
+    dispatch: dispatcher::Dispatcher,
+}
+
+impl Drop for ListenGuard {
+    fn drop(&mut self) {
+        let client_id = self.client_id.clone();
+        let context = self.context.clone();
+        self.dispatch
+            .execute(move || remove_client(&client_id, &context));
+    }
+}
+
 /// Adds a client to the HashMap.
 ///
 /// # Arguments
@@ -274,13 +586,14 @@ fn client_listen(
 /// * 'addr' - The SocketAddr which will serve as a key to the hashmap.
 /// * 'socket' - The TcpStream of the client which will serve as the value to the hashmap.
 /// * 'clients' - A ClientHashMap where the client will be inserted.
-fn add_client(client: client::Client, clients: ClientHashmap) {
-    let mut clients = clients.lock().unwrap();
+fn add_client(client: client::Client, clients: &mut HashMap<client::ClientID, client::Client>) {
     let id = client.id.clone();
-    if let Some(_) = clients.insert(id.clone(), client) {
-        println!("Client {} already in map", id);
+    if clients.insert(id.clone(), client).is_some() {
+        warn!(client_id = %id, "Client already in map");
     } else {
-        println!("Client {} successfully added to map", id);
+        info!(client_id = %id, "Client connected");
+        registry().clients_connected.set(clients.len() as i64);
+        chat::announce(format!("{} joined the server", id), clients);
     }
 }
 
@@ -289,74 +602,227 @@ fn add_client(client: client::Client, clients: ClientHashmap) {
 /// # Arguments
 ///
 /// * 'addr' - The key of the client.
-/// * 'clients' - A ClientHashMap from which the client will be removed.
-fn remove_client(client_id: &client::ClientID, clients: &ClientHashmap, games: &GameHashmap) {
-    let mut clients = clients.lock().unwrap();
-    if let Some(clnt) = clients.remove(client_id) {
-        println!("Client {} successfully removed from ClientMap", client_id);
-        match clnt.game_id {
-            Some(id) => {
-                let mut games = games.lock().unwrap();
-                if let Some(game) = games.get_mut(&id) {
-                    let mut players = game.model.players.lock().unwrap();
-                    if players.remove(client_id) {
-                        println!("Client {} succefully removed from PlayerList", client_id);
-                    }
-                }
+/// * 'context' - The ServerContext from which the client will be removed.
+fn remove_client(client_id: &client::ClientID, context: &ServerContext) {
+    lobby::leave_party(
+        client_id,
+        &context.parties,
+        &context.matchmaker,
+        &context.clients,
+    );
+    relay::leave_hosted_game(client_id, &context.hosted, &context.clients);
+    context.replays.lock().unwrap().remove(client_id);
+
+    let removed = {
+        let mut clients = context.clients.lock().unwrap();
+        let removed = clients.remove(client_id);
+        if removed.is_some() {
+            info!(%client_id, "Client disconnected");
+            registry().clients_connected.set(clients.len() as i64);
+            chat::announce(format!("{} left the server", client_id), &clients);
+        }
+        removed
+    };
+
+    // Games are never reached while holding the ClientHashmap.
+    match removed {
+        Some(clnt) => {
+            if let Some(id) = clnt.game_id {
+                lobby::detach(client_id, id, &context.games);
             }
-            None => (),
         }
-    } else {
-        println!("Faile to remove client  {} from map!", client_id);
+        None => warn!(%client_id, "Failed to remove client from map"),
     }
 }
 
-/// Writes "Game Data" to each client periodically.
+/// Starts a job writing a snapshot of each game to its players and spectators. A game still
+/// publishing its last snapshot is skipped, so a slow game doesn't hold up the others.
 ///
 /// # Arguments
-/// * 'map_mutex' - A reference to a ClientHashMap from which each client connection will be sent a message.
-/// * 'diapatch' - A reference to a dispatcher which will execute the sending of messages.
+/// * 'context' - A reference to the ServerContext holding the games and the connection of each player
+///   and spectator.
+/// * 'dispatch' - The Dispatcher which runs the job of each game.
+/// * 'spectator_delay' - How long snapshots are held back from spectators.
 ///
 /// # Returns
 /// * ExpectedSuccess - This function shouldn't break out of a loop unless something very strange happens.
 fn publish_data(
-    games: &GameHashmap,
-    clients: &ClientHashmap,
+    context: &ServerContext,
     dispatch: &dispatcher::Dispatcher,
+    spectator_delay: Duration,
 ) -> errors::ExpectedSuccess {
-    let mut games = games.lock().unwrap();
-    for (_game_id, game) in games.iter_mut() {
-        let players = game.model.players.lock().unwrap();
-        for player_id in players.iter() {
-            let mut clients = clients.lock().unwrap();
-
-            if let Some(client) = clients.get_mut(player_id) {
-                let clone = client.try_clone().expect("Failed to clone Client");
-                if let Some(mut socket) = clone.socket {
-                    let msg = message::TextMessage::new("Game Data");
-                    message::send_json(msg, &mut socket);
-                }
+    for game in game_actor::all(&context.games) {
+        let beat = match game.begin(Beat::Publish) {
+            Some(beat) => beat,
+            None => {
+                trace!(game_id = game.game_id(), "Still publishing, skipped");
+                continue;
+            }
+        };
+        let clients = Arc::clone(&context.clients);
+        dispatch.execute_named("publish_game", move || {
+            let _beat = beat;
+            publish_game(&game, &clients, spectator_delay);
+        });
+    }
+    Ok(())
+}
+
+/// Writes the snapshots of a game which are due to its players and spectators.
+fn publish_game(game: &GameHandle, clients: &ClientHashmap, spectator_delay: Duration) {
+    // Take the snapshots first so the game isn't held while writing to sockets.
+    let snapshots = game.snapshots(spectator_delay);
+
+    let mut senders = client::senders(clients, snapshots.iter().flat_map(|(to, _)| to));
+    for (recipients, snapshot) in &snapshots {
+        for client_id in recipients {
+            if let Some(sender) = senders.get_mut(client_id) {
+                sender.send(snapshot);
             }
-            std::mem::drop(clients);
         }
-        std::mem::drop(players);
     }
+}
 
-    std::mem::drop(games);
+/// Writes every game to the save directory.
+///
+/// # Returns
+/// * ExpectedSuccess - Failed saves are reported, and tried again on the next autosave.
+fn autosave(games: &GameHashmap, dir: &Path) -> errors::ExpectedSuccess {
+    if let Err(e) = persistence::save_games(games, dir) {
+        warn!(dir = %dir.display(), error = %e, "Failed to save games");
+    }
+    Ok(())
+}
 
-    thread::sleep(Duration::from_secs(1));
+/// Appends the events of every game to its replay file.
+///
+/// # Returns
+/// * ExpectedSuccess - Failed writes are reported, and the events recorded are lost.
+fn record_replays(games: &GameHashmap, dir: &Path) -> errors::ExpectedSuccess {
+    if let Err(e) = replays::record_replays(games, dir) {
+        warn!(dir = %dir.display(), error = %e, "Failed to record replays");
+    }
+    Ok(())
+}
 
+/// Steps the replays clients are watching.
+///
+/// # Returns
+/// * ExpectedSuccess - This function shouldn't break out of a loop unless something very strange happens.
+fn stream_replays(context: &ServerContext) -> errors::ExpectedSuccess {
+    replays::stream_replays(&context.replays, &context.clients, context.tick_interval);
     Ok(())
 }
 
-fn dispatch_sys(games: &GameHashmap) -> errors::ExpectedSuccess {
-    let mut games = games.lock().unwrap();
-    for (_game_id, game) in games.iter_mut() {
-        game.dispatch();
+/// Groups queued clients into new games, and reports the queue status to everyone still waiting.
+///
+/// # Arguments
+/// * 'context' - A reference to the ServerContext holding the Matchmaker, clients and games.
+///
+/// # Returns
+/// * ExpectedSuccess - This function shouldn't break out of a loop unless something very strange happens.
+fn run_matchmaking(context: &ServerContext) -> errors::ExpectedSuccess {
+    let now = Instant::now();
+    let (matches, statuses) = {
+        let mut matchmaker = context.matchmaker.lock().unwrap();
+        let matches = matchmaker.form_matches(now);
+        (matches, matchmaker.statuses(now))
+    };
+
+    for tickets in matches {
+        let members: Vec<_> = tickets.into_iter().flat_map(|t| t.members).collect();
+        let max_games = context.limits.max_games;
+        match lobby::start_game(&members, max_games, &context.clients, &context.games) {
+            Ok(game_id) => info!(?members, game_id, "Matchmaking placed group"),
+            Err(e) => {
+                warn!(?members, error = %e, "Matchmaking failed to place group");
+                lobby::release_group(&members, &e.reason, &context.clients);
+            }
+        }
     }
 
-    std::mem::drop(games);
-    thread::sleep(Duration::from_secs(1));
+    let mut senders = client::senders(&context.clients, statuses.iter().map(|(id, _)| id));
+    for (client_id, status) in &statuses {
+        if let Some(sender) = senders.get_mut(client_id) {
+            sender.send(status);
+        }
+    }
+
+    Ok(())
+}
 
+/// Starts a job running the systems of each game, so every game ticks on its own and a slow game
+/// only delays itself. A game still running its last tick skips this one.
+///
+/// Lockstep games only advance once every player's input for the next tick has arrived, and the
+/// inputs are broadcast so the players can simulate the tick too. Rollback games advance the same
+/// way, which keeps the server's copy of the world for spectators. New games start being
+/// recorded here if the server records replays.
+///
+/// Only Active games are simulated. Pause and resume countdowns are run here, and every change
+/// of GameState is announced to the players and spectators of the game.
+///
+/// # Arguments
+/// * 'context' - A reference to the ServerContext holding the games and clients.
+/// * 'dispatch' - The Dispatcher which runs the job of each game.
+///
+/// # Returns
+/// * ExpectedSuccess - This function shouldn't break out of a loop unless something very strange happens.
+fn dispatch_sys(
+    context: &ServerContext,
+    dispatch: &dispatcher::Dispatcher,
+) -> errors::ExpectedSuccess {
+    let games = game_actor::all(&context.games);
+    registry().games_running.set(games.len() as i64);
+    let record = context.replay_dir.is_some();
+    for game in games {
+        let beat = match game.begin(Beat::Tick) {
+            Some(beat) => beat,
+            None => {
+                trace!(game_id = game.game_id(), "Still ticking, skipped");
+                continue;
+            }
+        };
+        let clients = Arc::clone(&context.clients);
+        dispatch.execute_named("tick_game", move || {
+            let _beat = beat;
+            tick_game(&game, record, &clients);
+        });
+    }
     Ok(())
 }
+
+/// Runs the systems of a game once, then sends the players and spectators what came of it.
+fn tick_game(game: &GameHandle, record: bool, clients: &ClientHashmap) {
+    let now = Instant::now();
+    let (audience, notices, outgoing) = game.call(move |game_id, game| {
+        let _span = debug_span!("game", game_id).entered();
+        if record && game.recorder.is_none() {
+            game.start_recording(game_id);
+        }
+        let notices = pause::poll(game_id, game, now);
+        let mut outgoing = Vec::new();
+        if let Some(lockstep) = &game.lockstep {
+            let (players, rollback) = (lockstep.players.clone(), lockstep.rollback);
+            for bundle in game.advance_lockstep(game_id) {
+                // Rollback players already have every input, they only need to hear who left.
+                if !rollback || !bundle.left.is_empty() {
+                    outgoing.push((players.clone(), bundle));
+                }
+            }
+        } else {
+            game.dispatch();
+        }
+        (game.audience(), notices, outgoing)
+    });
+
+    pause::send(&audience, &notices, clients);
+    let mut senders = client::senders(clients, outgoing.iter().flat_map(|(to, _)| to));
+    for (recipients, bundle) in &outgoing {
+        for client_id in recipients {
+            if let Some(sender) = senders.get_mut(client_id) {
+                sender.send(bundle);
+            }
+        }
+    }
+}
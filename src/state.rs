@@ -1,5 +1,173 @@
+use std::fmt::Debug;
+use std::mem::{self, Discriminant};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::errors::StateError;
+
 /// Trait to give state to structs.
 pub trait State {
     type StateEnum;
-    fn change_state(&mut self, new_state: Self::StateEnum);
+    /// Moves to a new state.
+    ///
+    /// # Returns
+    ///
+    /// * StateError if moving to the new state isn't a legal transition.
+    fn change_state(&mut self, new_state: Self::StateEnum) -> Result<(), StateError>;
+}
+
+/// Trait for the states of a StateMachine, declaring which transitions between them are legal.
+pub trait Transitions: Copy + Debug + PartialEq {
+    /// Returns true if moving from this state to `next` is legal. Staying in the same state is
+    /// always allowed, and doesn't need to be declared.
+    fn can_transition_to(&self, next: &Self) -> bool;
+}
+
+/// A move from one state to another.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transition<S> {
+    pub from: S,
+    pub to: S,
+}
+
+type Callback<S> = Box<dyn FnMut(&Transition<S>) + Send>;
+
+/// Holds a state, and only moves it along the transitions the state declares legal. Callbacks
+/// run as states are exited and entered, and every transition is sent to subscribers.
+///
+/// Callbacks are registered against a variant, so a callback for a variant holding data runs
+/// whatever the data is.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::state::{StateMachine, Transition, Transitions};
+/// use std::sync::{Arc, Mutex};
+///
+/// #[derive(Clone, Copy, Debug, PartialEq)]
+/// enum Door {
+///     Open,
+///     Closed,
+///     Locked,
+/// }
+///
+/// impl Transitions for Door {
+///     fn can_transition_to(&self, next: &Door) -> bool {
+///         match (self, next) {
+///             (Door::Open, Door::Closed) => true,
+///             (Door::Closed, Door::Open) | (Door::Closed, Door::Locked) => true,
+///             (Door::Locked, Door::Closed) => true,
+///             _ => false,
+///         }
+///     }
+/// }
+///
+/// let mut door = StateMachine::new(Door::Open);
+/// let locks = Arc::new(Mutex::new(0));
+/// let counter = Arc::clone(&locks);
+/// door.on_enter(Door::Locked, move |_| *counter.lock().unwrap() += 1);
+/// let events = door.subscribe();
+///
+/// assert!(door.transition(Door::Locked).is_err());
+/// door.transition(Door::Closed).unwrap();
+/// door.transition(Door::Locked).unwrap();
+///
+/// assert_eq!(door.state(), Door::Locked);
+/// assert_eq!(*locks.lock().unwrap(), 1);
+/// let seen: Vec<_> = events.try_iter().collect();
+/// assert_eq!(seen[1], Transition { from: Door::Closed, to: Door::Locked });
+/// ```
+pub struct StateMachine<S: Transitions> {
+    state: S,
+    on_enter: Vec<(Discriminant<S>, Callback<S>)>,
+    on_exit: Vec<(Discriminant<S>, Callback<S>)>,
+    subscribers: Vec<Sender<Transition<S>>>,
+}
+
+impl<S: Transitions> StateMachine<S> {
+    pub fn new(state: S) -> StateMachine<S> {
+        StateMachine {
+            state,
+            on_enter: Vec::new(),
+            on_exit: Vec::new(),
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn state(&self) -> S {
+        self.state
+    }
+
+    /// Returns true if the machine may move to `next`.
+    pub fn can_transition_to(&self, next: &S) -> bool {
+        self.state == *next || self.state.can_transition_to(next)
+    }
+
+    /// Registers a callback to run whenever the machine enters a variant of `state`.
+    pub fn on_enter<F>(&mut self, state: S, callback: F)
+    where
+        F: FnMut(&Transition<S>) + Send + 'static,
+    {
+        self.on_enter
+            .push((mem::discriminant(&state), Box::new(callback)));
+    }
+
+    /// Registers a callback to run whenever the machine leaves a variant of `state`.
+    pub fn on_exit<F>(&mut self, state: S, callback: F)
+    where
+        F: FnMut(&Transition<S>) + Send + 'static,
+    {
+        self.on_exit
+            .push((mem::discriminant(&state), Box::new(callback)));
+    }
+
+    /// Returns a receiver which is sent every transition from now on. Dropping the receiver
+    /// unsubscribes it.
+    pub fn subscribe(&mut self) -> Receiver<Transition<S>> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Moves the machine to `next`. The exit callbacks of the current state run first, then the
+    /// enter callbacks of the next, then subscribers are sent the transition. Moving to the
+    /// current state does nothing.
+    ///
+    /// # Returns
+    ///
+    /// * The transition made, or None if the machine was already in `next`.
+    /// * StateError if the transition isn't legal.
+    pub fn transition(&mut self, next: S) -> Result<Option<Transition<S>>, StateError> {
+        if self.state == next {
+            return Ok(None);
+        }
+        if !self.state.can_transition_to(&next) {
+            return Err(StateError {
+                reason: format!("Can't move from {:?} to {:?}", self.state, next),
+            });
+        }
+
+        let transition = Transition {
+            from: self.state,
+            to: next,
+        };
+        let from = mem::discriminant(&transition.from);
+        for (_, callback) in self.on_exit.iter_mut().filter(|(d, _)| *d == from) {
+            callback(&transition);
+        }
+        self.state = next;
+        let to = mem::discriminant(&transition.to);
+        for (_, callback) in self.on_enter.iter_mut().filter(|(d, _)| *d == to) {
+            callback(&transition);
+        }
+        self.subscribers
+            .retain(|subscriber| subscriber.send(transition).is_ok());
+        Ok(Some(transition))
+    }
+
+    /// Puts the machine in a state without a transition, for things which are being built or
+    /// restored. No callbacks run and subscribers aren't told.
+    pub fn reset(&mut self, state: S) {
+        self.state = state;
+    }
 }
@@ -1,15 +1,74 @@
 use crate::threading::job;
-use std::sync::{mpsc, Arc, Mutex};
+use crate::threading::job_handle::{self, JobHandle};
+use crate::threading::loop_handle::{self, LoopConfig, LoopExit, LoopHandle, LoopState};
+use crate::threading::queue::{JobQueue, Priority, QueueStats, Task};
+use crate::threading::scheduler::{self, Every, Timer, TimerHandle};
+use crate::threading::worker::{self, Event, Health, PoolHealth};
+use std::any::type_name;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tracing::debug;
 
+/// Sends jobs to the workers of a ThreadPool. Every job it sends, including scheduled and
+/// looping jobs, goes at its priority, which is Normal unless set with `with_priority`.
 #[derive(Clone)]
 pub struct Dispatcher {
-    pub sender: mpsc::Sender<job::Message>,
-    pub send_term: mpsc::Sender<job::Message>,
-    pub recv_term: Arc<Mutex<mpsc::Receiver<job::Message>>>,
+    pub(crate) queue: Arc<JobQueue>,
+    events: mpsc::Sender<Event>,
+    pub health: Arc<Health>,
+    pub(crate) timers: mpsc::Sender<scheduler::Request>,
+    priority: Priority,
+    loops: Arc<Mutex<Vec<Weak<LoopState>>>>,
 }
 
 impl Dispatcher {
-    /// Sends a one-time job to a worker.
+    pub(crate) fn new(
+        queue: Arc<JobQueue>,
+        events: mpsc::Sender<Event>,
+        timers: mpsc::Sender<scheduler::Request>,
+        health: Arc<Health>,
+    ) -> Dispatcher {
+        Dispatcher {
+            queue,
+            events,
+            health,
+            timers,
+            priority: Priority::default(),
+            loops: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// A dispatcher for the same pool which sends its jobs at another priority.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate multiplayer;
+    /// use multiplayer::threading::queue::Priority;
+    /// use multiplayer::threading::threadpool;
+    ///
+    /// let pool = threadpool::ThreadPool::new(2);
+    /// let background = pool.dispatcher.with_priority(Priority::Background);
+    /// background.execute(|| println!("Saving"));
+    ///
+    /// assert_eq!(background.priority(), Priority::Background);
+    /// assert_eq!(pool.dispatcher.priority(), Priority::Normal);
+    /// ```
+    pub fn with_priority(&self, priority: Priority) -> Dispatcher {
+        Dispatcher {
+            priority,
+            ..self.clone()
+        }
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Sends a one-time job to a worker. If the job panics, the panic is logged along with the
+    /// job's name, which is the type name of the closure, and the worker carries on.
     ///
     /// # Example
     ///
@@ -28,11 +87,81 @@ impl Dispatcher {
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
-        self.sender.send(job::Message::NewJob(job)).unwrap();
+        self.execute_named(type_name::<F>(), f);
+    }
+
+    /// Sends a generic job to a worker. Workers stop when their ThreadPool is dropped, so
+    /// Terminate does nothing.
+    #[deprecated(note = "send jobs with execute")]
+    #[allow(deprecated)]
+    pub fn send(&self, msg: job::Message) {
+        match msg {
+            job::Message::NewJob(job) => self.execute_named("job", move || job.call_box()),
+            job::Message::Terminate => {}
+        }
+    }
+
+    /// Sends a one-time job to a worker under the given name.
+    pub(crate) fn execute_named<F>(&self, name: &'static str, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.submit(self.priority, name, Box::new(f));
+    }
+
+    /// Queues a job, asking the supervisor for another worker if every worker is busy.
+    pub(crate) fn submit(&self, priority: Priority, name: &'static str, job: job::Job) {
+        self.queue.push(Task {
+            name,
+            priority,
+            queued_at: Instant::now(),
+            job,
+        });
+        let can_grow = self.health.size.load(Ordering::Acquire) < self.health.max;
+        if can_grow && self.queue.report_backlog() {
+            // The supervisor is gone once the pool has shut down.
+            let _ = self.events.send(Event::Backlog);
+        }
+    }
+
+    /// Sends a one-time job to a worker, returning a handle to what it returns. A panic in the job
+    /// is logged as usual, and handed back through the handle.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate multiplayer;
+    /// use multiplayer::threading::threadpool;
+    /// use std::time::Duration;
+    ///
+    /// let pool = threadpool::ThreadPool::new(5);
+    /// let handles: Vec<_> = (0..10)
+    ///     .map(|i| pool.dispatcher.execute_with_result(move || i * i))
+    ///     .collect();
+    ///
+    /// assert!(handles[9].wait_timeout(Duration::from_secs(1)));
+    /// let squares: Vec<i32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    /// assert_eq!(squares[9], 81);
+    /// ```
+    pub fn execute_with_result<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (handle, completer) = job_handle::pair();
+        let health = Arc::clone(&self.health);
+        self.execute(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            if let Err(payload) = &result {
+                worker::record_panic(type_name::<F>(), payload, &health);
+            }
+            completer.complete(result);
+        });
+        handle
     }
 
-    /// Sends a job to a worker which will be repeated until an error is thrown.
+    /// Sends a job to a worker which will be repeated until an error is thrown, it panics, or it
+    /// is cancelled through the returned handle. Every loop is cancelled when the pool shuts down.
     ///
     /// # Example
     ///
@@ -56,34 +185,181 @@ impl Dispatcher {
     ///     });
     /// }
     /// ```
-    pub fn execute_loop<F, T, E>(&self, mut f: F)
+    pub fn execute_loop<F, T, E>(&self, f: F) -> LoopHandle
     where
         F: FnMut() -> Result<T, E> + Send + 'static,
         E: std::error::Error,
     {
-        let rcv = Arc::clone(&self.recv_term);
-
-        let job2 = Box::new(move || loop {
-            let result = rcv.lock().unwrap().try_recv();
-
-            match result {
-                Ok(_) => break,
-                Err(_) => match f() {
-                    Err(e) => {
-                        println!("{}", e);
-                        break;
-                    }
-                    _ => (),
-                },
+        self.execute_loop_with(LoopConfig::default(), f)
+    }
+
+    /// Sends a looping job to a worker with a name and an interval between iterations.
+    pub fn execute_loop_with<F, T, E>(&self, config: LoopConfig, f: F) -> LoopHandle
+    where
+        F: FnMut() -> Result<T, E> + Send + 'static,
+        E: std::error::Error,
+    {
+        let name = config.name.unwrap_or(type_name::<F>());
+        let state = self.register_loop(name);
+        let handle = LoopHandle::new(Arc::clone(&state));
+        let health = Arc::clone(&self.health);
+        self.execute_named(name, move || {
+            loop_handle::run(state, config.interval, &health, f)
+        });
+        handle
+    }
+
+    /// Runs a one-time job on a worker once the time comes. No worker is held while it waits.
+    /// Once the pool has shut down the job never runs, and the handle returned is already
+    /// cancelled.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate multiplayer;
+    /// use multiplayer::threading::threadpool;
+    /// use std::sync::mpsc;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let pool = threadpool::ThreadPool::new(2);
+    /// let (sender, receiver) = mpsc::channel();
+    /// let start = Instant::now();
+    /// pool.dispatcher
+    ///     .schedule_at(start + Duration::from_millis(20), move || sender.send(()).unwrap());
+    ///
+    /// receiver.recv().unwrap();
+    /// assert!(start.elapsed() >= Duration::from_millis(20));
+    /// ```
+    pub fn schedule_at<F>(&self, at: Instant, f: F) -> TimerHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let (timer, handle) = Timer::once(type_name::<F>(), self.priority, Box::new(f));
+        // The scheduler is gone once the pool has shut down, so the job never runs.
+        if self
+            .timers
+            .send(scheduler::Request::Add(at, timer))
+            .is_err()
+        {
+            handle.cancel();
+        }
+        handle
+    }
+
+    /// Runs a one-time job on a worker after a delay, e.g. to time something out.
+    pub fn schedule_after<F>(&self, delay: Duration, f: F) -> TimerHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.schedule_at(Instant::now() + delay, f)
+    }
+
+    /// Runs a job on a worker every period, at a fixed rate or with a fixed delay between runs,
+    /// until an error is thrown, it panics, or it is cancelled through the returned handle. No
+    /// worker is held between runs, and runs of the same job never overlap.
+    /// Once the pool has shut down the job never runs, and the loop returned has already ended.
+    ///
+    /// # Panics
+    ///
+    /// If the period is zero.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate multiplayer;
+    /// use multiplayer::errors::UnexpectedError;
+    /// use multiplayer::threading::loop_handle::LoopExit;
+    /// use multiplayer::threading::scheduler::Every;
+    /// use multiplayer::threading::threadpool;
+    /// use std::time::Duration;
+    ///
+    /// let pool = threadpool::ThreadPool::new(1);
+    /// let mut runs = 0;
+    /// let every = Every::fixed_rate(Duration::from_millis(5)).named("countdown");
+    /// let countdown = pool.dispatcher.schedule_every(every, move || {
+    ///     runs += 1;
+    ///     match runs {
+    ///         3 => Err(UnexpectedError),
+    ///         _ => Ok(()),
+    ///     }
+    /// });
+    ///
+    /// assert_eq!(countdown.name(), "countdown");
+    /// assert_eq!(countdown.join(), LoopExit::Stopped(String::from("Unexpected Error!")));
+    /// ```
+    pub fn schedule_every<F, T, E>(&self, every: Every, f: F) -> LoopHandle
+    where
+        F: FnMut() -> Result<T, E> + Send + 'static,
+        E: std::error::Error,
+    {
+        assert!(!every.period.is_zero());
+        let name = every.name.unwrap_or(type_name::<F>());
+        let state = self.register_loop(name);
+        let handle = LoopHandle::new(Arc::clone(&state));
+
+        let health = Arc::clone(&self.health);
+        let run_state = Arc::clone(&state);
+        let mut f = f;
+        let run = Box::new(move || {
+            if run_state.is_cancelled() {
+                return;
             }
+            let exit = match panic::catch_unwind(AssertUnwindSafe(&mut f)) {
+                Ok(Ok(_)) => return,
+                Ok(Err(e)) => {
+                    debug!(name, error = %e, "Scheduled job ended");
+                    LoopExit::Stopped(e.to_string())
+                }
+                Err(payload) => {
+                    worker::record_panic(name, &payload, &health);
+                    let exit = LoopExit::Panicked(job_handle::panic_message(&payload));
+                    worker::drop_payload(payload);
+                    exit
+                }
+            };
+            run_state.finish(exit);
+            run_state.cancel();
         });
 
-        self.sender.send(job::Message::NewJob(job2)).unwrap();
+        let repeating = Arc::new(scheduler::Repeating::new(
+            name,
+            self.priority,
+            every,
+            state,
+            run,
+        ));
+        let first = Instant::now() + every.period;
+        // Once the pool has shut down the job is dropped unsent, which ends its loop.
+        let request = scheduler::Request::Add(first, Timer::Every(repeating));
+        if self.timers.send(request).is_err() {
+            handle.cancel();
+        }
+        handle
     }
 
-    /// Sends a generic job to a worker.
-    /// Used to terminate the workers.
-    pub fn send(&self, msg: job::Message) {
-        self.sender.send(msg).unwrap();
+    /// Keeps track of a looping job, so it can be cancelled when the pool shuts down.
+    fn register_loop(&self, name: &'static str) -> Arc<LoopState> {
+        let state = Arc::new(LoopState::new(name));
+        let mut loops = self.loops.lock().unwrap();
+        loops.retain(|l| l.strong_count() > 0);
+        loops.push(Arc::downgrade(&state));
+        state
+    }
+
+    /// Cancels every looping job, so their workers are free to terminate.
+    pub(crate) fn cancel_loops(&self) {
+        for state in self.loops.lock().unwrap().iter().filter_map(Weak::upgrade) {
+            state.cancel();
+        }
+    }
+
+    /// How many workers of the pool are alive and busy, and how many jobs have panicked.
+    pub fn health(&self) -> PoolHealth {
+        self.health.report()
+    }
+
+    /// How many jobs of the pool are waiting at each priority, and how long they waited.
+    pub fn stats(&self) -> QueueStats {
+        self.queue.stats()
     }
 }
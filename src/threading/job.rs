@@ -8,7 +8,12 @@ impl<F: FnOnce()> FnBox for F {
     }
 }
 
-pub type Job = Box<FnBox + Send + 'static>;
+pub type Job = Box<dyn FnBox + Send + 'static>;
+
+/// A message for the workers, from when jobs were sent to them over a channel.
+/// * NewJob - A job to run.
+/// * Terminate - Asked a worker to stop.
+#[deprecated(note = "jobs are queued by the Dispatcher, send them with Dispatcher::execute")]
 pub enum Message {
     NewJob(Job),
     Terminate,
@@ -0,0 +1,171 @@
+use std::any::Any;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// What a job left behind, and who to tell once it has.
+struct State<T> {
+    result: Option<thread::Result<T>>,
+    finished: bool,
+    waker: Option<Waker>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    done: Condvar,
+}
+
+/// The result of a job sent with `Dispatcher::execute_with_result`, like a `JoinHandle` for a
+/// thread. The result can be joined, waited on with a timeout, or awaited as a `Future`.
+///
+/// A job which panics hands the panic back as an `Err`, and the worker carries on. A job which
+/// is dropped without running, because the pool shut down first, also gives an `Err`.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::threading::job_handle::panic_message;
+/// use multiplayer::threading::threadpool;
+/// use std::future::Future;
+/// use std::pin::pin;
+/// use std::task::{Context, Poll, Waker};
+/// use std::time::Duration;
+///
+/// let pool = threadpool::ThreadPool::new(2);
+/// let handle = pool.dispatcher.execute_with_result(|| 6 * 7);
+/// assert_eq!(handle.join().unwrap(), 42);
+///
+/// let handle = pool.dispatcher.execute_with_result(|| -> u32 { panic!("Out of cheese") });
+/// let panic = handle.join().unwrap_err();
+/// assert_eq!(panic_message(&panic), "Out of cheese");
+///
+/// // Polled like any other future, here without an executor.
+/// let mut handle = pin!(pool.dispatcher.execute_with_result(|| "done"));
+/// assert!(handle.wait_timeout(Duration::from_secs(1)));
+/// let mut cx = Context::from_waker(Waker::noop());
+/// assert!(matches!(handle.as_mut().poll(&mut cx), Poll::Ready(Ok("done"))));
+/// ```
+pub struct JobHandle<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The half of a JobHandle which goes with the job, to hand over its result.
+pub(crate) struct Completer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Makes a handle and the completer which finishes it.
+pub(crate) fn pair<T>() -> (JobHandle<T>, Completer<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            result: None,
+            finished: false,
+            waker: None,
+        }),
+        done: Condvar::new(),
+    });
+    (
+        JobHandle {
+            shared: Arc::clone(&shared),
+        },
+        Completer { shared },
+    )
+}
+
+impl<T> Completer<T> {
+    pub(crate) fn complete(self, result: thread::Result<T>) {
+        self.finish(result);
+    }
+
+    fn finish(&self, result: thread::Result<T>) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.finished {
+            return;
+        }
+        state.result = Some(result);
+        state.finished = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.shared.done.notify_all();
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        // Only does anything if the job was dropped before it ran.
+        self.finish(Err(Box::new("Job was dropped before it ran")));
+    }
+}
+
+impl<T> JobHandle<T> {
+    /// Whether the job has finished, successfully or not.
+    pub fn is_finished(&self) -> bool {
+        self.shared.state.lock().unwrap().finished
+    }
+
+    /// Waits for the job to finish, for no longer than the timeout.
+    ///
+    /// # Returns
+    ///
+    /// * Whether the job has finished.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+        while !state.finished {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return false;
+            }
+            state = self.shared.done.wait_timeout(state, left).unwrap().0;
+        }
+        true
+    }
+
+    /// Waits for the job to finish.
+    ///
+    /// # Returns
+    ///
+    /// * What the job returned.
+    /// * The payload of the panic if the job panicked, which can be passed on with
+    ///   `std::panic::resume_unwind`.
+    ///
+    /// # Panics
+    ///
+    /// If the result was already taken by polling the handle as a future.
+    pub fn join(self) -> thread::Result<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        while !state.finished {
+            state = self.shared.done.wait(state).unwrap();
+        }
+        state.result.take().expect("Job result already taken")
+    }
+}
+
+impl<T> Future for JobHandle<T> {
+    type Output = thread::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.finished {
+            return Poll::Ready(state.result.take().expect("Job result already taken"));
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// The message a panic was raised with, for reporting it.
+pub fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        String::from(*message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("Panicked with a non-string payload")
+    }
+}
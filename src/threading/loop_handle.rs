@@ -0,0 +1,229 @@
+use std::error::Error;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::threading::job_handle::panic_message;
+use crate::threading::worker::{self, Health};
+
+/// Describes a looping job
+/// * name - The name the loop is logged and reported under. The type name of the closure if
+///   not given.
+/// * interval - How long to wait after each iteration. Cancelling the loop cuts the wait short.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::threading::loop_handle::LoopConfig;
+/// use std::time::Duration;
+///
+/// let config = LoopConfig::named("autosave").every(Duration::from_secs(30));
+/// assert_eq!(config.name, Some("autosave"));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LoopConfig {
+    pub name: Option<&'static str>,
+    pub interval: Option<Duration>,
+}
+
+impl LoopConfig {
+    pub fn named(name: &'static str) -> LoopConfig {
+        LoopConfig {
+            name: Some(name),
+            interval: None,
+        }
+    }
+
+    pub fn every(self, interval: Duration) -> LoopConfig {
+        LoopConfig {
+            interval: Some(interval),
+            ..self
+        }
+    }
+}
+
+/// Why a looping job ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoopExit {
+    /// The loop was cancelled, or its pool shut down.
+    Cancelled,
+    /// An iteration returned this error.
+    Stopped(String),
+    /// An iteration panicked with this message.
+    Panicked(String),
+}
+
+struct Status {
+    cancelled: bool,
+    exit: Option<LoopExit>,
+}
+
+/// The state of a looping job, shared by its handles and the job itself.
+pub(crate) struct LoopState {
+    name: &'static str,
+    status: Mutex<Status>,
+    changed: Condvar,
+}
+
+impl LoopState {
+    pub(crate) fn new(name: &'static str) -> LoopState {
+        LoopState {
+            name,
+            status: Mutex::new(Status {
+                cancelled: false,
+                exit: None,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.status.lock().unwrap().cancelled = true;
+        self.changed.notify_all();
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.status.lock().unwrap().cancelled
+    }
+
+    /// Waits for the interval, or until the loop is cancelled.
+    fn pause(&self, interval: Duration) {
+        let deadline = Instant::now() + interval;
+        let mut status = self.status.lock().unwrap();
+        while !status.cancelled {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            status = self.changed.wait_timeout(status, left).unwrap().0;
+        }
+    }
+
+    /// Records why the loop ended, unless it already has.
+    pub(crate) fn finish(&self, exit: LoopExit) {
+        let mut status = self.status.lock().unwrap();
+        if status.exit.is_none() {
+            status.exit = Some(exit);
+        }
+        self.changed.notify_all();
+    }
+}
+
+/// Marks the loop as ended however its job ends, including being dropped without running.
+pub(crate) struct Finisher(Arc<LoopState>);
+
+impl Finisher {
+    pub(crate) fn new(state: Arc<LoopState>) -> Finisher {
+        Finisher(state)
+    }
+
+    pub(crate) fn state(&self) -> &LoopState {
+        &self.0
+    }
+}
+
+impl Drop for Finisher {
+    fn drop(&mut self) {
+        self.0.finish(LoopExit::Cancelled);
+    }
+}
+
+/// A handle to a looping job sent with `Dispatcher::execute_loop` or `schedule_every`. Dropping
+/// every handle leaves the loop running, like dropping a thread's JoinHandle.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::errors;
+/// use multiplayer::threading::loop_handle::{LoopConfig, LoopExit};
+/// use multiplayer::threading::threadpool;
+/// use std::time::Duration;
+///
+/// let pool = threadpool::ThreadPool::new(2);
+/// let config = LoopConfig::named("heartbeat").every(Duration::from_millis(10));
+/// let heartbeat = pool.dispatcher.execute_loop_with(config, || -> errors::ExpectedSuccess {
+///     Ok(())
+/// });
+///
+/// assert!(heartbeat.is_running());
+/// heartbeat.cancel();
+/// assert_eq!(heartbeat.join(), LoopExit::Cancelled);
+/// assert!(!heartbeat.is_running());
+/// ```
+#[derive(Clone)]
+pub struct LoopHandle {
+    state: Arc<LoopState>,
+}
+
+impl LoopHandle {
+    pub(crate) fn new(state: Arc<LoopState>) -> LoopHandle {
+        LoopHandle { state }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.state.name
+    }
+
+    /// Stops the loop once the current iteration returns. An iteration blocked on something
+    /// else, like a read from a socket, isn't interrupted. A scheduled job ends when its next run
+    /// comes due.
+    pub fn cancel(&self) {
+        self.state.cancel();
+    }
+
+    /// Whether the loop is still going.
+    pub fn is_running(&self) -> bool {
+        self.state.status.lock().unwrap().exit.is_none()
+    }
+
+    /// Waits for the loop to end.
+    ///
+    /// # Returns
+    ///
+    /// * Why the loop ended.
+    pub fn join(&self) -> LoopExit {
+        let mut status = self.state.status.lock().unwrap();
+        loop {
+            if let Some(exit) = &status.exit {
+                return exit.clone();
+            }
+            status = self.state.changed.wait(status).unwrap();
+        }
+    }
+}
+
+/// Runs iterations of a looping job until it is cancelled, errors or panics.
+pub(crate) fn run<F, T, E>(
+    state: Arc<LoopState>,
+    interval: Option<Duration>,
+    health: &Health,
+    mut f: F,
+) where
+    F: FnMut() -> Result<T, E>,
+    E: Error,
+{
+    let finisher = Finisher(state);
+    let state = &finisher.0;
+    while !state.is_cancelled() {
+        match panic::catch_unwind(AssertUnwindSafe(&mut f)) {
+            Ok(Ok(_)) => (),
+            Ok(Err(e)) => {
+                debug!(name = state.name, error = %e, "Loop ended");
+                state.finish(LoopExit::Stopped(e.to_string()));
+                return;
+            }
+            Err(payload) => {
+                worker::record_panic(state.name, &payload, health);
+                state.finish(LoopExit::Panicked(panic_message(&payload)));
+                worker::drop_payload(payload);
+                return;
+            }
+        }
+        if let Some(interval) = interval {
+            state.pause(interval);
+        }
+    }
+}
@@ -1,5 +1,9 @@
 pub mod dispatcher;
 pub mod job;
+pub mod job_handle;
+pub mod loop_handle;
+pub mod queue;
+pub mod scheduler;
 pub mod threadpool;
 pub mod worker;
 
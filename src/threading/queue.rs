@@ -0,0 +1,295 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::metrics::registry::registry;
+use crate::threading::job;
+
+/// How urgent a job is. Workers always pick up the most urgent job waiting, so a burst of
+/// normal work, like chat, can't hold up a game tick.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Priority {
+    /// Work which has to happen on time, like game ticks and publishing snapshots.
+    Realtime,
+    /// Handling clients and their messages.
+    #[default]
+    Normal,
+    /// Work which can wait, like saving games and writing replays.
+    Background,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::Realtime, Priority::Normal, Priority::Background];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A job waiting in a queue.
+pub(crate) struct Task {
+    pub name: &'static str,
+    pub priority: Priority,
+    pub queued_at: Instant,
+    pub job: job::Job,
+}
+
+/// What a worker waiting on the queue is given.
+pub(crate) enum Next {
+    Job(Task),
+    /// Nothing came for the idle timeout.
+    Idle,
+    /// The pool is shutting down and every job has been picked up.
+    Shutdown,
+}
+
+/// Jobs waiting at each priority.
+type Deques = [VecDeque<Task>; 3];
+
+fn deques() -> Deques {
+    [VecDeque::new(), VecDeque::new(), VecDeque::new()]
+}
+
+/// Counts kept for one priority.
+#[derive(Default)]
+struct Counts {
+    queued: AtomicUsize,
+    executed: AtomicU64,
+    wait_micros: AtomicU64,
+    max_wait_micros: AtomicU64,
+}
+
+/// How the jobs of one priority are getting on
+/// * queued - Jobs waiting for a worker.
+/// * executed - Jobs picked up by a worker.
+/// * mean_wait - How long picked up jobs waited for a worker on average.
+/// * max_wait - The longest a picked up job waited for a worker.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PriorityStats {
+    pub queued: usize,
+    pub executed: u64,
+    pub mean_wait: Duration,
+    pub max_wait: Duration,
+}
+
+/// The queues of a thread pool at one moment, read through `ThreadPool::stats`
+/// * realtime - Jobs sent at Priority::Realtime.
+/// * normal - Jobs sent at Priority::Normal.
+/// * background - Jobs sent at Priority::Background.
+/// * stolen - Jobs a worker took from another worker's queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueueStats {
+    pub realtime: PriorityStats,
+    pub normal: PriorityStats,
+    pub background: PriorityStats,
+    pub stolen: u64,
+}
+
+impl QueueStats {
+    pub fn get(&self, priority: Priority) -> &PriorityStats {
+        match priority {
+            Priority::Realtime => &self.realtime,
+            Priority::Normal => &self.normal,
+            Priority::Background => &self.background,
+        }
+    }
+
+    /// Jobs waiting for a worker at every priority.
+    pub fn queued(&self) -> usize {
+        Priority::ALL.iter().map(|p| self.get(*p).queued).sum()
+    }
+}
+
+thread_local! {
+    /// The queue and slot of the worker running on this thread, if it is one.
+    static CURRENT: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/// The jobs of a thread pool. Jobs sent from outside the pool go on a shared queue, while jobs
+/// sent by a worker go on that worker's own queue. A worker takes the most urgent job it can
+/// find, from its own queue first, then the shared one, then by stealing from other workers.
+pub(crate) struct JobQueue {
+    shared: Mutex<Deques>,
+    /// A queue for each worker slot, so a slot's queue outlives the worker using it.
+    locals: Vec<Mutex<Deques>>,
+    counts: [Counts; 3],
+    stolen: AtomicU64,
+    /// Held to sleep and to wake sleepers, so a wake up can't be missed.
+    sleep: Mutex<()>,
+    available: Condvar,
+    idle: AtomicUsize,
+    /// Whether the supervisor has been told jobs are backing up and hasn't acted on it yet.
+    backlog: AtomicBool,
+    shutdown: AtomicBool,
+}
+
+impl JobQueue {
+    pub(crate) fn new(slots: usize) -> JobQueue {
+        JobQueue {
+            shared: Mutex::new(deques()),
+            locals: (0..slots).map(|_| Mutex::new(deques())).collect(),
+            counts: Default::default(),
+            stolen: AtomicU64::new(0),
+            sleep: Mutex::new(()),
+            available: Condvar::new(),
+            idle: AtomicUsize::new(0),
+            backlog: AtomicBool::new(false),
+            shutdown: AtomicBool::new(false),
+        }
+    }
+
+    fn id(&self) -> usize {
+        self as *const JobQueue as usize
+    }
+
+    /// Marks this thread as the worker in a slot, so the jobs it sends go on its own queue.
+    pub(crate) fn enter(&self, slot: usize) {
+        CURRENT.with(|current| current.set(Some((self.id(), slot))));
+    }
+
+    pub(crate) fn push(&self, task: Task) {
+        let priority = task.priority.index();
+        let slot = CURRENT
+            .with(Cell::get)
+            .and_then(|(queue, slot)| match queue == self.id() {
+                true => Some(slot),
+                false => None,
+            });
+        // Counted before it can be found, so a worker picking it up never takes the count below
+        // zero.
+        self.counts[priority].queued.fetch_add(1, Ordering::AcqRel);
+        registry().jobs_queued.inc();
+        match slot {
+            Some(slot) => self.locals[slot].lock().unwrap()[priority].push_back(task),
+            None => self.shared.lock().unwrap()[priority].push_back(task),
+        }
+
+        let _sleep = self.sleep.lock().unwrap();
+        self.available.notify_one();
+    }
+
+    /// Waits for the most urgent job.
+    pub(crate) fn next(&self, slot: usize, idle_timeout: Option<Duration>) -> Next {
+        loop {
+            if let Some(task) = self.find(slot) {
+                return Next::Job(task);
+            }
+            let sleep = self.sleep.lock().unwrap();
+            if self.queued() > 0 {
+                continue;
+            }
+            if self.shutdown.load(Ordering::Acquire) {
+                return Next::Shutdown;
+            }
+            self.idle.fetch_add(1, Ordering::AcqRel);
+            let timed_out = match idle_timeout {
+                Some(timeout) => self
+                    .available
+                    .wait_timeout(sleep, timeout)
+                    .unwrap()
+                    .1
+                    .timed_out(),
+                None => {
+                    drop(self.available.wait(sleep).unwrap());
+                    false
+                }
+            };
+            self.idle.fetch_sub(1, Ordering::AcqRel);
+            if timed_out && self.queued() == 0 {
+                return Next::Idle;
+            }
+        }
+    }
+
+    fn find(&self, slot: usize) -> Option<Task> {
+        for priority in Priority::ALL.iter().map(|p| p.index()) {
+            if self.counts[priority].queued.load(Ordering::Acquire) == 0 {
+                continue;
+            }
+            let own = self.locals[slot].lock().unwrap()[priority].pop_front();
+            let task = own.or_else(|| self.shared.lock().unwrap()[priority].pop_front());
+            let task = task.or_else(|| self.steal(slot, priority));
+            if let Some(task) = task {
+                self.picked_up(&task);
+                return Some(task);
+            }
+        }
+        None
+    }
+
+    /// Takes the oldest job of a priority from another worker's queue.
+    fn steal(&self, slot: usize, priority: usize) -> Option<Task> {
+        let slots = self.locals.len();
+        let task = (1..slots)
+            .map(|offset| (slot + offset) % slots)
+            .find_map(|victim| self.locals[victim].lock().unwrap()[priority].pop_front())?;
+        self.stolen.fetch_add(1, Ordering::Relaxed);
+        registry().jobs_stolen.inc();
+        Some(task)
+    }
+
+    fn picked_up(&self, task: &Task) {
+        let counts = &self.counts[task.priority.index()];
+        let wait = task.queued_at.elapsed();
+        let micros = wait.as_micros() as u64;
+        counts.queued.fetch_sub(1, Ordering::AcqRel);
+        counts.executed.fetch_add(1, Ordering::Relaxed);
+        counts.wait_micros.fetch_add(micros, Ordering::Relaxed);
+        counts.max_wait_micros.fetch_max(micros, Ordering::Relaxed);
+        let metrics = registry();
+        metrics.jobs_queued.dec();
+        metrics.job_wait.observe(wait);
+    }
+
+    /// Jobs waiting at every priority.
+    pub(crate) fn queued(&self) -> usize {
+        self.counts
+            .iter()
+            .map(|c| c.queued.load(Ordering::Acquire))
+            .sum()
+    }
+
+    /// Workers waiting for a job.
+    pub(crate) fn idle(&self) -> usize {
+        self.idle.load(Ordering::Acquire)
+    }
+
+    /// Whether more jobs are waiting than there are idle workers, when nobody has said so yet.
+    pub(crate) fn report_backlog(&self) -> bool {
+        self.queued() > self.idle() && !self.backlog.swap(true, Ordering::AcqRel)
+    }
+
+    /// Lets the backlog be reported again.
+    pub(crate) fn backlog_seen(&self) {
+        self.backlog.store(false, Ordering::Release);
+    }
+
+    /// Wakes every worker to finish the jobs left and stop.
+    pub(crate) fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::Release);
+        let _sleep = self.sleep.lock().unwrap();
+        self.available.notify_all();
+    }
+
+    pub(crate) fn stats(&self) -> QueueStats {
+        let stats = |priority: Priority| {
+            let counts = &self.counts[priority.index()];
+            let executed = counts.executed.load(Ordering::Relaxed);
+            let wait = counts.wait_micros.load(Ordering::Relaxed);
+            PriorityStats {
+                queued: counts.queued.load(Ordering::Relaxed),
+                executed,
+                mean_wait: Duration::from_micros(wait.checked_div(executed).unwrap_or(0)),
+                max_wait: Duration::from_micros(counts.max_wait_micros.load(Ordering::Relaxed)),
+            }
+        };
+        QueueStats {
+            realtime: stats(Priority::Realtime),
+            normal: stats(Priority::Normal),
+            background: stats(Priority::Background),
+            stolen: self.stolen.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, trace};
+
+use crate::threading::dispatcher::Dispatcher;
+use crate::threading::job;
+use crate::threading::loop_handle::{Finisher, LoopState};
+use crate::threading::queue::Priority;
+
+/// How far apart the ticks of the timer wheel are. Jobs run on the first tick at or after the
+/// time they are due.
+pub const TICK: Duration = Duration::from_millis(1);
+/// How many ticks the wheel holds. Jobs due further off stay in their slot for more turns.
+const SLOTS: usize = 512;
+
+/// How a repeating job is spaced out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pace {
+    /// Runs start a period apart, however long each takes. A run that would start while the
+    /// last is still going is skipped, as are runs missed while the pool was busy.
+    FixedRate,
+    /// Each run starts a period after the last one finished.
+    FixedDelay,
+}
+
+/// Describes a repeating job sent with `Dispatcher::schedule_every`
+/// * name - The name the job is logged and reported under. The type name of the closure if not
+///   given.
+/// * period - How far apart runs are. The first run is a period after the job is scheduled.
+/// * pace - Whether the period is measured from the start of the last run or its end.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::threading::scheduler::{Every, Pace};
+/// use std::time::Duration;
+///
+/// let every = Every::fixed_rate(Duration::from_millis(50)).named("tick");
+/// assert_eq!(every.pace, Pace::FixedRate);
+/// assert_eq!(every.name, Some("tick"));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Every {
+    pub name: Option<&'static str>,
+    pub period: Duration,
+    pub pace: Pace,
+}
+
+impl Every {
+    pub fn fixed_rate(period: Duration) -> Every {
+        Every {
+            name: None,
+            period,
+            pace: Pace::FixedRate,
+        }
+    }
+
+    pub fn fixed_delay(period: Duration) -> Every {
+        Every {
+            name: None,
+            period,
+            pace: Pace::FixedDelay,
+        }
+    }
+
+    pub fn named(self, name: &'static str) -> Every {
+        Every {
+            name: Some(name),
+            ..self
+        }
+    }
+}
+
+const PENDING: u8 = 0;
+const CANCELLED: u8 = 1;
+const FIRED: u8 = 2;
+
+/// A handle to a one-time job sent with `Dispatcher::schedule_at` or `schedule_after`.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::threading::threadpool;
+/// use std::time::Duration;
+///
+/// let pool = threadpool::ThreadPool::new(2);
+/// let timeout = pool
+///     .dispatcher
+///     .schedule_after(Duration::from_secs(60), || println!("Timed out"));
+///
+/// assert!(timeout.is_pending());
+/// assert!(timeout.cancel());
+/// assert!(!timeout.is_pending());
+/// ```
+#[derive(Clone)]
+pub struct TimerHandle {
+    state: Arc<AtomicU8>,
+}
+
+impl TimerHandle {
+    /// Stops the job from running, if it hasn't yet been handed to a worker.
+    ///
+    /// # Returns
+    ///
+    /// * Whether the job was stopped. False if it already ran, or was cancelled before.
+    pub fn cancel(&self) -> bool {
+        self.state
+            .compare_exchange(PENDING, CANCELLED, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    /// Whether the job is still waiting to be run.
+    pub fn is_pending(&self) -> bool {
+        self.state.load(Ordering::Acquire) == PENDING
+    }
+}
+
+/// A repeating job, shared by the wheel and the run in progress. Its loop is marked as ended
+/// once neither holds it any more.
+pub(crate) struct Repeating {
+    name: &'static str,
+    priority: Priority,
+    every: Every,
+    running: AtomicBool,
+    finisher: Finisher,
+    run: Mutex<Box<dyn FnMut() + Send>>,
+}
+
+impl Repeating {
+    pub(crate) fn new(
+        name: &'static str,
+        priority: Priority,
+        every: Every,
+        state: Arc<LoopState>,
+        run: Box<dyn FnMut() + Send>,
+    ) -> Repeating {
+        Repeating {
+            name,
+            priority,
+            every,
+            running: AtomicBool::new(false),
+            finisher: Finisher::new(state),
+            run: Mutex::new(run),
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.finisher.state().is_cancelled()
+    }
+}
+
+/// A job waiting on the wheel.
+pub(crate) enum Timer {
+    Once {
+        name: &'static str,
+        priority: Priority,
+        job: job::Job,
+        state: Arc<AtomicU8>,
+    },
+    Every(Arc<Repeating>),
+}
+
+impl Timer {
+    pub(crate) fn once(
+        name: &'static str,
+        priority: Priority,
+        job: job::Job,
+    ) -> (Timer, TimerHandle) {
+        let state = Arc::new(AtomicU8::new(PENDING));
+        let handle = TimerHandle {
+            state: Arc::clone(&state),
+        };
+        let timer = Timer::Once {
+            name,
+            priority,
+            job,
+            state,
+        };
+        (timer, handle)
+    }
+}
+
+pub(crate) enum Request {
+    /// Runs the job once the time comes.
+    Add(Instant, Timer),
+    /// The pool is shutting down, so the scheduler should too. Waiting jobs are dropped.
+    Shutdown,
+}
+
+struct Entry {
+    tick: u64,
+    deadline: Instant,
+    timer: Timer,
+}
+
+/// A hashed timer wheel. Each job goes in the slot of the tick it is due on, wrapping around
+/// the wheel, so adding a job and finding the due ones costs the same however many are waiting.
+struct Wheel {
+    start: Instant,
+    slots: Vec<Vec<Entry>>,
+    /// The next tick to be checked for due jobs.
+    current: u64,
+    len: usize,
+}
+
+impl Wheel {
+    fn new(start: Instant) -> Wheel {
+        Wheel {
+            start,
+            slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            current: 0,
+            len: 0,
+        }
+    }
+
+    /// The first tick at or after an instant.
+    fn tick_at(&self, at: Instant) -> u64 {
+        let since = at.saturating_duration_since(self.start).as_nanos();
+        since.div_ceil(TICK.as_nanos()) as u64
+    }
+
+    fn insert(&mut self, deadline: Instant, timer: Timer) {
+        // Jobs already due go on the next tick checked.
+        let tick = self.tick_at(deadline).max(self.current);
+        self.slots[(tick % SLOTS as u64) as usize].push(Entry {
+            tick,
+            deadline,
+            timer,
+        });
+        self.len += 1;
+    }
+
+    /// Takes every job due by now.
+    fn advance(&mut self, now: Instant) -> Vec<Entry> {
+        let mut due = Vec::new();
+        let now_tick =
+            (now.saturating_duration_since(self.start).as_nanos() / TICK.as_nanos()) as u64;
+        if now_tick < self.current {
+            return due;
+        }
+        // A whole turn of the wheel visits every slot, however long it has been.
+        let visits = (now_tick - self.current + 1).min(SLOTS as u64);
+        for offset in 0..visits {
+            let slot = &mut self.slots[((self.current + offset) % SLOTS as u64) as usize];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].tick <= now_tick {
+                    due.push(slot.swap_remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.current = now_tick + 1;
+        self.len -= due.len();
+        due
+    }
+
+    /// When the next job is due, looking no more than a turn of the wheel ahead.
+    fn next_wake(&self) -> Option<Instant> {
+        if self.len == 0 {
+            return None;
+        }
+        let tick = (0..SLOTS as u64)
+            .map(|offset| self.current + offset)
+            .find(|tick| {
+                self.slots[(tick % SLOTS as u64) as usize]
+                    .iter()
+                    .any(|entry| entry.tick <= *tick)
+            })
+            .unwrap_or(self.current + SLOTS as u64);
+        Some(self.start + Duration::from_nanos(tick * TICK.as_nanos() as u64))
+    }
+}
+
+/// Keeps the timer wheel, handing jobs to the pool's workers as they come due, until the pool
+/// shuts down. No worker is held while a job waits.
+pub(crate) fn run(requests: mpsc::Receiver<Request>, dispatcher: Dispatcher) {
+    let mut wheel = Wheel::new(Instant::now());
+    loop {
+        let first = match wheel.next_wake() {
+            Some(wake) => requests.recv_timeout(wake.saturating_duration_since(Instant::now())),
+            None => requests.recv().map_err(mpsc::RecvTimeoutError::from),
+        };
+        let first = match first {
+            Ok(request) => Some(request),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        for request in first.into_iter().chain(requests.try_iter()) {
+            match request {
+                Request::Add(deadline, timer) => wheel.insert(deadline, timer),
+                Request::Shutdown => {
+                    debug!(waiting = wheel.len, "Scheduler stopped");
+                    return;
+                }
+            }
+        }
+
+        let now = Instant::now();
+        for entry in wheel.advance(now) {
+            if let Some((deadline, timer)) = fire(entry, now, &dispatcher) {
+                wheel.insert(deadline, timer);
+            }
+        }
+    }
+}
+
+/// Hands a due job to a worker.
+///
+/// # Returns
+///
+/// * When a fixed rate job runs next.
+fn fire(entry: Entry, now: Instant, dispatcher: &Dispatcher) -> Option<(Instant, Timer)> {
+    let repeating = match entry.timer {
+        Timer::Once {
+            name,
+            priority,
+            job,
+            state,
+        } => {
+            let fired = state.compare_exchange(PENDING, FIRED, Ordering::AcqRel, Ordering::Acquire);
+            if fired.is_ok() {
+                dispatcher.submit(priority, name, job);
+            }
+            return None;
+        }
+        Timer::Every(repeating) => repeating,
+    };
+    if repeating.is_cancelled() {
+        return None;
+    }
+
+    let period = repeating.every.period;
+    match repeating.every.pace {
+        Pace::FixedRate => {
+            if repeating.running.swap(true, Ordering::AcqRel) {
+                trace!(job = repeating.name, "Still running, skipped");
+            } else {
+                let job = Arc::clone(&repeating);
+                let run = Box::new(move || {
+                    (job.run.lock().unwrap())();
+                    job.running.store(false, Ordering::Release);
+                });
+                dispatcher.submit(repeating.priority, repeating.name, run);
+            }
+            // Runs missed while behind are skipped rather than run back to back.
+            let behind = now.saturating_duration_since(entry.deadline).as_nanos();
+            let runs = behind / period.as_nanos() + 1;
+            let next = entry.deadline + Duration::from_nanos((runs * period.as_nanos()) as u64);
+            Some((next, Timer::Every(repeating)))
+        }
+        Pace::FixedDelay => {
+            let requests = dispatcher.timers.clone();
+            let (priority, name) = (repeating.priority, repeating.name);
+            let run = Box::new(move || {
+                (repeating.run.lock().unwrap())();
+                if !repeating.is_cancelled() {
+                    let next = Instant::now() + period;
+                    // The scheduler is gone once the pool has shut down.
+                    let _ = requests.send(Request::Add(next, Timer::Every(repeating)));
+                }
+            });
+            dispatcher.submit(priority, name, run);
+            None
+        }
+    }
+}
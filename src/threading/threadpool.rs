@@ -1,69 +1,274 @@
+use std::sync::atomic::Ordering;
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
 
+use crate::metrics::registry::registry;
 use crate::threading::dispatcher::Dispatcher;
-use crate::threading::job;
-use crate::threading::worker::Worker;
+use crate::threading::queue::{JobQueue, QueueStats};
+use crate::threading::scheduler::{self, Request};
+use crate::threading::worker::{Event, Health, PoolHealth, Worker};
+
+/// Describes the size of a ThreadPool
+/// * min_threads - Workers kept even when idle.
+/// * max_threads - The most workers the pool grows to while jobs are waiting.
+/// * idle_timeout - How long a worker above the minimum waits for a job before stopping.
+///
+/// # Example
+///
+/// ```
+/// extern crate multiplayer;
+/// use multiplayer::threading::threadpool::{PoolConfig, ThreadPool};
+///
+/// let pool = ThreadPool::with_config(PoolConfig::new(2, 8));
+/// let health = pool.health();
+/// assert_eq!((health.min_size, health.max_size), (2, 8));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoolConfig {
+    pub min_threads: usize,
+    pub max_threads: usize,
+    pub idle_timeout: Duration,
+}
+
+impl PoolConfig {
+    pub fn new(min_threads: usize, max_threads: usize) -> PoolConfig {
+        PoolConfig {
+            min_threads,
+            max_threads,
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// What the pool and its supervisor need to start workers.
+struct Spawner {
+    queue: Arc<JobQueue>,
+    events: mpsc::Sender<Event>,
+    health: Arc<Health>,
+    idle_timeout: Option<Duration>,
+}
+
+impl Spawner {
+    fn spawn(&self, id: usize) -> Worker {
+        Worker::new(
+            id,
+            Arc::clone(&self.queue),
+            self.events.clone(),
+            Arc::clone(&self.health),
+            self.idle_timeout,
+        )
+    }
+}
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
+    workers: Arc<Mutex<Vec<Worker>>>,
+    supervisor: Option<thread::JoinHandle<()>>,
+    events: mpsc::Sender<Event>,
+    scheduler: Option<thread::JoinHandle<()>>,
     pub dispatcher: Dispatcher,
 }
 
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
-    /// The size is the number of threads in the pool.
+    /// The size is the number of threads in the pool. Jobs which panic don't take their worker
+    /// with them, and a worker whose thread dies anyway is replaced by a supervisor thread.
+    /// Scheduled jobs wait on a timer wheel kept by a scheduler thread, not on a worker.
     ///
     /// # Panics
     ///
     /// The `new` function will panic if the size is zero.
     pub fn new(size: usize) -> ThreadPool {
-        assert!(size > 0);
+        ThreadPool::with_config(PoolConfig::new(size, size))
+    }
 
-        let (send_term, recv_term) = mpsc::channel();
-        let recv_term = Arc::new(Mutex::new(recv_term));
+    /// Create a new ThreadPool which grows from the minimum number of threads to the maximum
+    /// while jobs are waiting, and shrinks back as workers sit idle.
+    ///
+    /// Each worker has its own queue for the jobs it sends, which idle workers steal from, and
+    /// workers pick up the most urgent job waiting first. See `Dispatcher::with_priority`.
+    ///
+    /// # Panics
+    ///
+    /// If the maximum is zero or below the minimum.
+    pub fn with_config(config: PoolConfig) -> ThreadPool {
+        assert!(config.max_threads > 0);
+        assert!(config.min_threads <= config.max_threads);
 
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
-        }
+        let queue = Arc::new(JobQueue::new(config.max_threads));
+        let (events, event_receiver) = mpsc::channel();
+        let health = Arc::new(Health::new(config.min_threads, config.max_threads));
+        let spawner = Spawner {
+            queue: Arc::clone(&queue),
+            events: events.clone(),
+            health: Arc::clone(&health),
+            idle_timeout: match config.min_threads < config.max_threads {
+                true => Some(config.idle_timeout),
+                false => None,
+            },
+        };
+        let workers: Vec<_> = (0..config.min_threads)
+            .map(|id| spawner.spawn(id))
+            .collect();
+        let workers = Arc::new(Mutex::new(workers));
 
-        let dispatcher = Dispatcher {
-            sender,
-            send_term,
-            recv_term,
+        let supervisor = {
+            let workers = Arc::clone(&workers);
+            thread::Builder::new()
+                .name(String::from("supervisor"))
+                .spawn(move || supervise(event_receiver, &workers, &spawner))
+                .expect("Failed to spawn supervisor thread")
+        };
+
+        let (timers, requests) = mpsc::channel();
+        let dispatcher = Dispatcher::new(queue, events.clone(), timers, health);
+        let scheduler = {
+            let dispatcher = dispatcher.clone();
+            thread::Builder::new()
+                .name(String::from("scheduler"))
+                .spawn(move || scheduler::run(requests, dispatcher))
+                .expect("Failed to spawn scheduler thread")
         };
 
         ThreadPool {
             workers,
+            supervisor: Some(supervisor),
+            events,
+            scheduler: Some(scheduler),
             dispatcher,
         }
     }
+
+    /// How many workers are alive and busy, and how many jobs have panicked.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate multiplayer;
+    /// use multiplayer::threading::threadpool;
+    ///
+    /// let pool = threadpool::ThreadPool::new(2);
+    /// let _ = pool.dispatcher.execute_with_result(|| panic!("Oops")).join();
+    ///
+    /// let health = pool.health();
+    /// assert_eq!(health.live_workers, 2);
+    /// assert_eq!(health.panics, 1);
+    /// ```
+    pub fn health(&self) -> PoolHealth {
+        self.dispatcher.health()
+    }
+
+    /// How many jobs are waiting at each priority, and how long they waited for a worker.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate multiplayer;
+    /// use multiplayer::threading::queue::Priority;
+    /// use multiplayer::threading::threadpool;
+    ///
+    /// let pool = threadpool::ThreadPool::new(2);
+    /// let realtime = pool.dispatcher.with_priority(Priority::Realtime);
+    /// realtime.execute_with_result(|| ()).join().unwrap();
+    ///
+    /// let stats = pool.stats();
+    /// assert_eq!(stats.realtime.executed, 1);
+    /// assert_eq!(stats.queued(), 0);
+    /// ```
+    pub fn stats(&self) -> QueueStats {
+        self.dispatcher.stats()
+    }
+}
+
+/// Replaces workers whose thread died, starts workers while jobs back up, and collects workers
+/// which stopped for being idle, until the pool shuts down.
+fn supervise(events: mpsc::Receiver<Event>, workers: &Mutex<Vec<Worker>>, spawner: &Spawner) {
+    for event in events.iter() {
+        match event {
+            Event::Died(id) => {
+                warn!(worker = id, "Worker died, respawning");
+                let worker = spawner.spawn(id);
+                spawner.health.respawns.fetch_add(1, Ordering::Relaxed);
+                registry().workers_respawned.inc();
+                replace(workers, id, Some(worker));
+            }
+            Event::Stopped(id) => replace(workers, id, None),
+            Event::Backlog => {
+                spawner.queue.backlog_seen();
+                let wanted = spawner.queue.queued().saturating_sub(spawner.queue.idle());
+                let mut workers = workers.lock().unwrap();
+                for _ in 0..wanted {
+                    if !spawner.health.grow() {
+                        break;
+                    }
+                    // Slots are reused, so every worker has the queue of a slot to itself.
+                    let id = (0..spawner.health.max)
+                        .find(|id| workers.iter().all(|w| w.id != *id))
+                        .expect("Pool grew past its slots");
+                    debug!(worker = id, "Jobs waiting, starting a worker");
+                    workers.push(spawner.spawn(id));
+                }
+            }
+            Event::Shutdown => break,
+        }
+    }
+    debug!("Supervisor stopped");
+}
+
+/// Collects the thread of a worker which has ended, putting a new worker in its place if given.
+fn replace(workers: &Mutex<Vec<Worker>>, id: usize, worker: Option<Worker>) {
+    let mut workers = workers.lock().unwrap();
+    let index = match workers.iter().position(|w| w.id == id) {
+        Some(index) => index,
+        None => return,
+    };
+    // Joined only to collect the thread, which has already ended.
+    if let Some(thread) = workers[index].thread.take() {
+        let _ = thread.join();
+    }
+    match worker {
+        Some(worker) => workers[index] = worker,
+        None => {
+            workers.swap_remove(index);
+        }
+    }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        println!("Sending terminate message to all workers.");
-
-        for _ in &mut self.workers {
-            self.dispatcher.send(job::Message::Terminate);
-            self.dispatcher
-                .send_term
-                .send(job::Message::Terminate)
-                .unwrap();
+        // Stop the supervisor first, so no workers are started while shutting down.
+        let _ = self.events.send(Event::Shutdown);
+        if let Some(supervisor) = self.supervisor.take() {
+            if supervisor.join().is_err() {
+                warn!("Supervisor panicked");
+            }
+        }
+
+        // Then the scheduler, dropping the jobs still waiting so no more are sent.
+        let _ = self.dispatcher.timers.send(Request::Shutdown);
+        if let Some(scheduler) = self.scheduler.take() {
+            if scheduler.join().is_err() {
+                warn!("Scheduler panicked");
+            }
         }
 
-        println!("Shutting down all workers.");
+        debug!("Telling all workers to terminate");
+        self.dispatcher.cancel_loops();
+        self.dispatcher.queue.shutdown();
 
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+        debug!("Shutting down all workers");
+        let mut workers = self.workers.lock().unwrap();
+        for worker in workers.iter_mut() {
+            debug!(worker = worker.id, "Shutting down worker");
 
             if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+                if thread.join().is_err() {
+                    warn!(worker = worker.id, "Worker died while shutting down");
+                }
             }
         }
     }
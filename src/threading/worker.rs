@@ -1,7 +1,133 @@
-use std::sync::{mpsc, Arc, Mutex};
+use std::any::Any;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
+use std::time::Duration;
+use tracing::{debug, debug_span, error, trace};
 
-use crate::threading::job;
+use crate::metrics::registry::registry;
+use crate::threading::job_handle::panic_message;
+use crate::threading::queue::{JobQueue, Next, Task};
+
+/// Counts kept by the workers of a pool, read through `ThreadPool::health`.
+/// * size - Workers the pool is meant to have, between min and max.
+/// * min - Workers kept even when idle.
+/// * max - The most workers the pool grows to.
+/// * live - Worker threads running.
+/// * busy - Workers running a job.
+/// * panics - Jobs which panicked.
+/// * respawns - Workers started again after their thread died.
+#[derive(Debug)]
+pub struct Health {
+    pub size: AtomicUsize,
+    pub min: usize,
+    pub max: usize,
+    pub live: AtomicUsize,
+    pub busy: AtomicUsize,
+    pub panics: AtomicU64,
+    pub respawns: AtomicU64,
+}
+
+impl Health {
+    pub fn new(min: usize, max: usize) -> Health {
+        Health {
+            size: AtomicUsize::new(min),
+            min,
+            max,
+            live: AtomicUsize::new(0),
+            busy: AtomicUsize::new(0),
+            panics: AtomicU64::new(0),
+            respawns: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads every count at once.
+    pub fn report(&self) -> PoolHealth {
+        PoolHealth {
+            size: self.size.load(Ordering::Relaxed),
+            min_size: self.min,
+            max_size: self.max,
+            live_workers: self.live.load(Ordering::Relaxed),
+            busy_workers: self.busy.load(Ordering::Relaxed),
+            panics: self.panics.load(Ordering::Relaxed),
+            respawns: self.respawns.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Makes room for one more worker, unless the pool is at its largest.
+    pub(crate) fn grow(&self) -> bool {
+        self.size
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |size| {
+                (size < self.max).then_some(size + 1)
+            })
+            .is_ok()
+    }
+
+    /// Lets one worker go, unless the pool is at its smallest.
+    pub(crate) fn shrink(&self) -> bool {
+        self.size
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |size| {
+                (size > self.min).then_some(size - 1)
+            })
+            .is_ok()
+    }
+}
+
+/// The health of a thread pool at one moment
+/// * size - Workers the pool is meant to have. It grows while jobs are waiting and every worker
+///   is busy, and shrinks as workers sit idle.
+/// * min_size - Workers kept even when idle.
+/// * max_size - The most workers the pool grows to.
+/// * live_workers - Worker threads running. Below size only while a worker is started or
+///   replaced.
+/// * busy_workers - Workers running a job, including every looping job.
+/// * panics - Jobs which have panicked.
+/// * respawns - Workers started again after their thread died.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoolHealth {
+    pub size: usize,
+    pub min_size: usize,
+    pub max_size: usize,
+    pub live_workers: usize,
+    pub busy_workers: usize,
+    pub panics: u64,
+    pub respawns: u64,
+}
+
+/// What the pool's supervisor is told.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// A worker stopped, because it was idle or the pool is shutting down.
+    Stopped(usize),
+    /// A worker died, and should be replaced.
+    Died(usize),
+    /// Jobs are waiting and every worker is busy, so the pool should grow.
+    Backlog,
+    /// The pool is shutting down, so the supervisor should too.
+    Shutdown,
+}
+
+/// Tells the supervisor a worker ended, however it ended.
+struct ExitNotice {
+    id: usize,
+    events: mpsc::Sender<Event>,
+    health: Arc<Health>,
+}
+
+impl Drop for ExitNotice {
+    fn drop(&mut self) {
+        self.health.live.fetch_sub(1, Ordering::Relaxed);
+        registry().workers_live.dec();
+        let event = match thread::panicking() {
+            true => Event::Died(self.id),
+            false => Event::Stopped(self.id),
+        };
+        // The supervisor is gone once the pool has shut down.
+        let _ = self.events.send(event);
+    }
+}
 
 pub struct Worker {
     pub id: usize,
@@ -9,22 +135,44 @@ pub struct Worker {
 }
 
 impl Worker {
-    pub fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<job::Message>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            println!("Worker {} waiting for job", id);
-            let message = receiver.lock().unwrap().recv().unwrap();
-
-            match message {
-                job::Message::NewJob(job) => {
-                    println!("Worker {} got a job; executing.", id);
-                    job.call_box();
-                }
-                job::Message::Terminate => {
-                    println!("Worker {} was told to terminate.", id);
-                    break;
+    /// Starts a worker in a slot of the queue. Workers of a pool which can shrink stop once they
+    /// have been idle for the timeout.
+    pub(crate) fn new(
+        id: usize,
+        queue: Arc<JobQueue>,
+        events: mpsc::Sender<Event>,
+        health: Arc<Health>,
+        idle_timeout: Option<Duration>,
+    ) -> Worker {
+        health.live.fetch_add(1, Ordering::Relaxed);
+        registry().workers_live.inc();
+        let notice = ExitNotice {
+            id,
+            events,
+            health: Arc::clone(&health),
+        };
+        let thread = thread::Builder::new()
+            .name(format!("worker-{}", id))
+            .spawn(move || {
+                let _notice = notice;
+                queue.enter(id);
+                loop {
+                    trace!(worker = id, "Waiting for job");
+                    match queue.next(id, idle_timeout) {
+                        Next::Job(task) => run(id, task, &health),
+                        Next::Idle if health.shrink() => {
+                            debug!(worker = id, "Idle, stopping");
+                            break;
+                        }
+                        Next::Idle => (),
+                        Next::Shutdown => {
+                            debug!(worker = id, "Told to terminate");
+                            break;
+                        }
+                    }
                 }
-            }
-        });
+            })
+            .expect("Failed to spawn worker thread");
 
         Worker {
             id,
@@ -32,3 +180,36 @@ impl Worker {
         }
     }
 }
+
+/// Runs a job, catching a panic so the worker can carry on with the next one.
+fn run(id: usize, task: Task, health: &Health) {
+    let Task { name, job, .. } = task;
+    let metrics = registry();
+    metrics.jobs_executed.inc();
+    metrics.workers_busy.inc();
+    health.busy.fetch_add(1, Ordering::Relaxed);
+
+    let _span = debug_span!("job", worker = id, job = name).entered();
+    trace!("Executing job");
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| job.call_box())) {
+        record_panic(name, &payload, health);
+        drop_payload(payload);
+    }
+
+    health.busy.fetch_sub(1, Ordering::Relaxed);
+    metrics.workers_busy.dec();
+}
+
+/// Drops the payload of a caught panic. Dropping it can panic too, which would end the worker.
+pub(crate) fn drop_payload(payload: Box<dyn Any + Send>) {
+    if let Err(again) = panic::catch_unwind(AssertUnwindSafe(|| drop(payload))) {
+        mem::forget(again);
+    }
+}
+
+/// Counts and logs a panic caught in a job.
+pub(crate) fn record_panic(name: &str, payload: &Box<dyn Any + Send>, health: &Health) {
+    health.panics.fetch_add(1, Ordering::Relaxed);
+    registry().jobs_panicked.inc();
+    error!(job = name, panic = %panic_message(payload), "Job panicked");
+}